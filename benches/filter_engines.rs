@@ -0,0 +1,59 @@
+//! Per-record cost of a simple filter in the native evaluator and in V8:
+//!
+//! ```sh
+//! cargo bench --bench filter_engines
+//! ```
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use rust_htslib::bam;
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::native::NativeFilter;
+
+const EXPRS: [&str; 3] = [
+    "aln.mapq >= 20 && !aln.duplicate",
+    "hasFlag(aln.flag, 0x2) && aln.chrom === 'chr1' && aln.end > 1010",
+    "aln.qname !== 'synthetic_unmapped' || aln.mapq > 0",
+];
+const RECORDS: usize = 300_000;
+
+fn main() {
+    let header = synthetic_header();
+    let records: Vec<bam::Record> = synthetic_records()
+        .into_iter()
+        .map(|(_, rec)| rec)
+        .cycle()
+        .take(RECORDS)
+        .collect();
+
+    for expr in EXPRS {
+        let native = NativeFilter::parse(expr).expect("expression should be native");
+        let mut engine = JsBamFilterEngine::new(expr).unwrap();
+
+        let start = Instant::now();
+        let native_passed = records
+            .iter()
+            .filter(|rec| native.record_passes(black_box(rec), &header))
+            .count();
+        let native_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut js_passed = 0;
+        for rec in &records {
+            js_passed += engine.record_passes(black_box(rec), &header).unwrap() as usize;
+        }
+        let js_time = start.elapsed();
+
+        assert_eq!(native_passed, js_passed, "{expr}");
+        let per_record = |t: std::time::Duration| t.as_nanos() as f64 / RECORDS as f64;
+        println!("{expr}");
+        println!(
+            "  native {:8.1} ns/record\n  js     {:8.1} ns/record ({:.1}x)",
+            per_record(native_time),
+            per_record(js_time),
+            js_time.as_secs_f64() / native_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! UMI and cell-barcode helpers: `aln.umi`, `aln.cellBarcode`,
+//! `hamming(a, b)` and the `barcodes.has(bc)` whitelist.
+//!
+//! Each barcode comes from the first present tag of a list (e.g. `UB,RX`) or
+//! from a regex capture on the read name, for pipelines that keep the UMI in
+//! the qname.
+
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use regex::bytes::Regex;
+use rust_htslib::bam::record::Aux;
+
+use crate::record::AlignmentRecord;
+use crate::{alignment_from_obj, open_text_input, throw_type_error};
+
+/// Where a barcode is read from.
+#[derive(Debug, Clone)]
+pub enum BarcodeSource {
+    /// The first of these tags present on the record.
+    Tags(Vec<[u8; 2]>),
+    /// Capture group 1 (or the whole match) of a regex on the read name.
+    Qname(Regex),
+}
+
+impl BarcodeSource {
+    /// Parse `tag:UB,RX` or `qname:REGEX`.
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("tag", tags)) => {
+                let tags = tags
+                    .split(',')
+                    .map(|t| {
+                        <[u8; 2]>::try_from(t.as_bytes())
+                            .map_err(|_| anyhow!("{t:?} is not a two-character tag"))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Self::Tags(tags))
+            }
+            Some(("qname", re)) => Ok(Self::Qname(
+                Regex::new(re).with_context(|| format!("invalid regex {re:?}"))?,
+            )),
+            _ => bail!("{s:?} is not of the form tag:TAG[,TAG...] or qname:REGEX"),
+        }
+    }
+
+    fn extract<'r>(&self, rec: &'r dyn AlignmentRecord) -> Option<&'r [u8]> {
+        match self {
+            Self::Tags(tags) => tags.iter().find_map(|tag| match rec.aux(tag) {
+                Some(Aux::String(s)) => Some(s.as_bytes()),
+                _ => None,
+            }),
+            Self::Qname(re) => {
+                let caps = re.captures(rec.qname())?;
+                caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_bytes())
+            }
+        }
+    }
+}
+
+/// Barcode sources and whitelist of an engine.
+#[derive(Debug, Clone)]
+pub struct BarcodeOptions {
+    /// Source of `aln.umi`; by default the `UB` tag, else `RX`.
+    pub umi: BarcodeSource,
+    /// Source of `aln.cellBarcode`; by default the `CB` tag, else `CR`.
+    pub cell_barcode: BarcodeSource,
+    /// Valid barcodes for `barcodes.has()`.
+    pub whitelist: Option<Arc<HashSet<String>>>,
+}
+
+impl Default for BarcodeOptions {
+    fn default() -> Self {
+        Self {
+            umi: BarcodeSource::Tags(vec![*b"UB", *b"RX"]),
+            cell_barcode: BarcodeSource::Tags(vec![*b"CB", *b"CR"]),
+            whitelist: None,
+        }
+    }
+}
+
+/// Load a barcode whitelist, one barcode per line (optionally gzipped, as
+/// shipped with 10x Genomics pipelines). Anything after the first
+/// whitespace is ignored.
+pub fn load_whitelist(path: &Path) -> Result<HashSet<String>> {
+    let mut set = HashSet::new();
+    for line in open_text_input(path)?.lines() {
+        let line = line.with_context(|| format!("failed to read {}", path.display()))?;
+        if let Some(bc) = line.split_whitespace().next() {
+            set.insert(bc.to_string());
+        }
+    }
+    Ok(set)
+}
+
+/// Install the `barcodes` global (`barcodes.has(bc)`, `barcodes.size`).
+/// Without a whitelist, `has()` throws so a forgotten `--barcodes` doesn't
+/// let every barcode through.
+pub(crate) fn install(
+    scope: &mut v8::ContextScope<'_, '_, v8::HandleScope<'_>>,
+    global: v8::Local<v8::Object>,
+    whitelist: Option<&HashSet<String>>,
+) {
+    let barcodes = v8::Object::new(scope);
+    let has = v8::Function::new(scope, barcodes_has_callback).unwrap();
+    let key = v8::String::new(scope, "has").unwrap();
+    barcodes.set(scope, key.into(), has.into());
+    let size: v8::Local<v8::Value> = match whitelist {
+        Some(set) => v8::Number::new(scope, set.len() as f64).into(),
+        None => v8::null(scope).into(),
+    };
+    let key = v8::String::new(scope, "size").unwrap();
+    barcodes.set(scope, key.into(), size);
+    let key = v8::String::new(scope, "barcodes").unwrap();
+    global.set(scope, key.into(), barcodes.into());
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn barcodes_has_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let bc = args.get(0).to_rust_string_lossy(scope);
+    let has = match scope.get_slot::<BarcodeOptions>() {
+        Some(BarcodeOptions {
+            whitelist: Some(set),
+            ..
+        }) => set.contains(&bc),
+        _ => {
+            throw_type_error(scope, "barcodes.has(): no --barcodes whitelist loaded");
+            return;
+        }
+    };
+    rv.set(v8::Boolean::new(scope, has).into());
+}
+
+fn barcode_getter(
+    scope: &mut v8::PinScope,
+    args: &v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+    source: fn(&BarcodeOptions) -> &BarcodeSource,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let bc = scope
+        .get_slot::<BarcodeOptions>()
+        .and_then(|opts| source(opts).extract(rec))
+        .map(|bc| String::from_utf8_lossy(bc).into_owned());
+    match bc {
+        Some(bc) => rv.set(v8::String::new(scope, &bc).unwrap().into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_umi_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    barcode_getter(scope, &args, rv, |opts| &opts.umi);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_cell_barcode_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    barcode_getter(scope, &args, rv, |opts| &opts.cell_barcode);
+}
+
+/// `hamming(a, b)`: number of differing characters, or `Infinity` when the
+/// lengths differ.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn hamming_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let a = args.get(0).to_rust_string_lossy(scope);
+    let b = args.get(1).to_rust_string_lossy(scope);
+    let d = if a.len() == b.len() {
+        a.bytes().zip(b.bytes()).filter(|(x, y)| x != y).count() as f64
+    } else {
+        f64::INFINITY
+    };
+    rv.set(v8::Number::new(scope, d).into());
+}
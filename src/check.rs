@@ -0,0 +1,239 @@
+//! `--check` mode: compile a filter and run it against a few synthetic
+//! records of the input's type (alignments, FASTQ reads or VCF variants) so
+//! typos and runtime errors surface before a long run.
+
+use anyhow::Result;
+use rust_htslib::bam;
+use rust_htslib::bam::header::HeaderRecord;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use rust_htslib::bcf;
+use rust_htslib::bcf::record::{GenotypeAllele, Numeric};
+
+use crate::fastq::{FastqRecord, JsFastqFilterEngine};
+use crate::vcf::JsVcfFilterEngine;
+use crate::{EngineOptions, JsBamFilterEngine, UnknownPropertyMode};
+
+/// Outcome of running a filter over the synthetic records.
+pub struct CheckReport {
+    /// Name of the object the script sees, e.g. `aln`.
+    pub object: &'static str,
+    /// Description of each synthetic record and the filter result for it.
+    pub outcomes: Vec<(&'static str, Result<bool>)>,
+    /// Unknown properties of the object the script read.
+    pub unknown_properties: Vec<String>,
+}
+
+impl CheckReport {
+    /// True if the script ran on every record without reading unknown
+    /// properties.
+    pub fn is_ok(&self) -> bool {
+        self.unknown_properties.is_empty() && self.outcomes.iter().all(|(_, r)| r.is_ok())
+    }
+}
+
+/// Compile `expr` and run it over [`synthetic_records`]. Unknown properties
+/// are always tracked; `opts.unknown_properties` only chooses whether they
+/// throw ([`UnknownPropertyMode::Error`]) or just warn.
+pub fn check_filter(expr: &str, opts: EngineOptions) -> Result<CheckReport> {
+    let mut engine = JsBamFilterEngine::with_options(expr, tracking_unknown(opts))?;
+    let header = synthetic_header();
+
+    let outcomes = synthetic_records()
+        .into_iter()
+        .map(|(desc, rec)| (desc, engine.record_passes(&rec, &header)))
+        .collect();
+
+    Ok(CheckReport {
+        object: "aln",
+        outcomes,
+        unknown_properties: engine.unknown_properties(),
+    })
+}
+
+/// [`check_filter`] for FASTQ scripts, over [`synthetic_fastq_records`].
+pub fn check_fastq_filter(expr: &str, opts: EngineOptions) -> Result<CheckReport> {
+    let mut engine = JsFastqFilterEngine::with_options(expr, tracking_unknown(opts))?;
+
+    let outcomes = synthetic_fastq_records()
+        .into_iter()
+        .map(|(desc, rec)| (desc, engine.record_passes(&rec)))
+        .collect();
+
+    Ok(CheckReport {
+        object: "read",
+        outcomes,
+        unknown_properties: engine.unknown_properties(),
+    })
+}
+
+/// [`check_filter`] for VCF scripts, over [`synthetic_variants`].
+pub fn check_vcf_filter(expr: &str, opts: EngineOptions) -> Result<CheckReport> {
+    let mut engine = JsVcfFilterEngine::with_options(expr, tracking_unknown(opts))?;
+
+    let outcomes = synthetic_variants()?
+        .into_iter()
+        .map(|(desc, rec)| (desc, engine.record_passes(&rec)))
+        .collect();
+
+    Ok(CheckReport {
+        object: "variant",
+        outcomes,
+        unknown_properties: engine.unknown_properties(),
+    })
+}
+
+/// Unknown properties are always tracked; `opts.unknown_properties` only
+/// chooses whether they throw or just warn.
+fn tracking_unknown(mut opts: EngineOptions) -> EngineOptions {
+    if opts.unknown_properties == UnknownPropertyMode::Ignore {
+        opts.unknown_properties = UnknownPropertyMode::Warn;
+    }
+    opts
+}
+
+/// Header with a single reference, `chr1`, and read group `rg1`.
+pub fn synthetic_header() -> bam::HeaderView {
+    let mut header = bam::Header::new();
+    let mut sq = HeaderRecord::new(b"SQ");
+    sq.push_tag(b"SN", "chr1");
+    sq.push_tag(b"LN", 248_956_422);
+    header.push_record(&sq);
+    let mut rg = HeaderRecord::new(b"RG");
+    rg.push_tag(b"ID", "rg1");
+    rg.push_tag(b"SM", "sample1");
+    rg.push_tag(b"LB", "lib1");
+    rg.push_tag(b"PL", "ILLUMINA");
+    header.push_record(&rg);
+    bam::HeaderView::from_header(&header)
+}
+
+/// A small set of records covering the common shapes a filter sees.
+pub fn synthetic_records() -> Vec<(&'static str, bam::Record)> {
+    let seq = b"ACGTACGTACGTACGTACGT";
+    let qual = [30u8; 20];
+
+    let mut fwd = bam::Record::new();
+    fwd.set(
+        b"synthetic_fwd",
+        Some(&CigarString(vec![Cigar::Match(20)])),
+        seq,
+        &qual,
+    );
+    fwd.set_tid(0);
+    fwd.set_pos(1000);
+    fwd.set_mapq(60);
+    fwd.set_flags(0x1 | 0x2 | 0x40);
+    fwd.set_mtid(0);
+    fwd.set_mpos(1200);
+    fwd.set_insert_size(220);
+    let _ = fwd.push_aux(b"NM", Aux::U8(1));
+    let _ = fwd.push_aux(b"RG", Aux::String("rg1"));
+
+    let mut rev = bam::Record::new();
+    rev.set(
+        b"synthetic_rev_clipped",
+        Some(&CigarString(vec![
+            Cigar::SoftClip(5),
+            Cigar::Match(12),
+            Cigar::HardClip(3),
+        ])),
+        &seq[..17],
+        &qual[..17],
+    );
+    rev.set_tid(0);
+    rev.set_pos(1200);
+    rev.set_mapq(3);
+    rev.set_flags(0x1 | 0x2 | 0x10 | 0x80);
+    rev.set_mtid(0);
+    rev.set_mpos(1000);
+    rev.set_insert_size(-220);
+    let _ = rev.push_aux(b"NM", Aux::U8(0));
+
+    let mut unmapped = bam::Record::new();
+    unmapped.set(b"synthetic_unmapped", None, seq, &qual);
+    unmapped.set_tid(-1);
+    unmapped.set_pos(-1);
+    unmapped.set_mapq(0);
+    unmapped.set_flags(0x4);
+    unmapped.set_mtid(-1);
+    unmapped.set_mpos(-1);
+
+    vec![
+        ("mapped forward read, NM=1", fwd),
+        ("reverse read with soft and hard clips, MAPQ 3", rev),
+        ("unmapped read, no aux tags", unmapped),
+    ]
+}
+
+/// FASTQ reads covering the shapes a FASTQ filter sees.
+pub fn synthetic_fastq_records() -> Vec<(&'static str, FastqRecord)> {
+    let read = |name: &[u8], comment: &[u8], seq: &[u8], qual: &[u8]| FastqRecord {
+        name: name.to_vec(),
+        comment: comment.to_vec(),
+        seq: seq.to_vec(),
+        qual: qual.to_vec(),
+    };
+    vec![
+        (
+            "read with a comment",
+            read(
+                b"synthetic_1",
+                b"1:N:0:ACGTACGT",
+                b"ACGTACGTACGTACGTACGT",
+                b"IIIIIIIIIIIIIIIIIIII",
+            ),
+        ),
+        (
+            "low-quality read with an N, no comment",
+            read(b"synthetic_2", b"", b"ACGNT", b"##5##"),
+        ),
+        ("empty read", read(b"synthetic_empty", b"", b"", b"")),
+    ]
+}
+
+/// Variants on `chr1` with samples `sample1` and `sample2`, DP in INFO and
+/// GT/DP in FORMAT.
+pub fn synthetic_variants() -> Result<Vec<(&'static str, bcf::Record)>> {
+    let mut header = bcf::Header::new();
+    header.push_record(b"##contig=<ID=chr1,length=248956422>");
+    header.push_record(b"##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">");
+    header.push_record(b"##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">");
+    header.push_record(b"##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">");
+    header.push_sample(b"sample1").push_sample(b"sample2");
+    // A synced copy, as bcf::Writer makes one.
+    let view =
+        bcf::header::HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(header.inner) });
+
+    let mut snv = view.empty_record();
+    snv.set_rid(Some(0));
+    snv.set_pos(1000);
+    snv.set_id(b"rs1")?;
+    snv.set_alleles(&[b"A", b"C"])?;
+    snv.set_qual(50.0);
+    snv.push_filter(b"PASS".as_slice())?;
+    snv.push_info_integer(b"DP", &[30])?;
+    snv.push_genotypes(&[
+        GenotypeAllele::Unphased(0),
+        GenotypeAllele::Unphased(1),
+        GenotypeAllele::Unphased(1),
+        GenotypeAllele::Phased(1),
+    ])?;
+    snv.push_format_integer(b"DP", &[12, 18])?;
+
+    let mut indel = view.empty_record();
+    indel.set_rid(Some(0));
+    indel.set_pos(2000);
+    indel.set_alleles(&[b"TTG", b"T", b"TTGG"])?;
+    indel.set_qual(f32::missing());
+    indel.push_genotypes(&[
+        GenotypeAllele::UnphasedMissing,
+        GenotypeAllele::UnphasedMissing,
+        GenotypeAllele::Unphased(1),
+        GenotypeAllele::Unphased(2),
+    ])?;
+
+    Ok(vec![
+        ("biallelic SNV with INFO, FILTER and genotypes", snv),
+        ("multiallelic indel without QUAL, FILTER or INFO", indel),
+    ])
+}
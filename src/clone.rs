@@ -0,0 +1,119 @@
+//! `aln.clone()`: a plain JS copy of the current record that stays valid
+//! after the `filter()` call, for scripts that compare neighbouring reads:
+//!
+//! ```js
+//! const prev = globalThis.prev;
+//! globalThis.prev = aln.clone();
+//! return !prev || prev.chrom !== aln.chrom || aln.pos - prev.end > 100;
+//! ```
+//!
+//! The copy has the core fields (`qname`, `flag`, `mapq`, `chrom`, `pos`,
+//! `start`, `end`, `cigar`), the aux tags as a `tags` object and an
+//! `aux(tag)` method, so it can stand in for `aln` in most expressions.
+
+use crate::record::AlignmentRecord;
+use crate::{alignment_from_obj, aux_to_js_value, cigar_to_js};
+
+/// Prototype shared by all copies, holding `aux()`. Created on first use and
+/// stored in an isolate slot.
+struct ClonePrototype(v8::Global<v8::Object>);
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_clone_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let copy = clone_to_js(scope, rec);
+    rv.set(copy.into());
+}
+
+/// Materialize `rec` as a plain object (see the module docs).
+pub(crate) fn clone_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    rec: &dyn AlignmentRecord,
+) -> v8::Local<'s, v8::Object> {
+    let tags = v8::Object::new(scope);
+    for (tag, aux) in rec.aux_tags() {
+        let key = v8::String::new_from_one_byte(scope, &tag, v8::NewStringType::Normal).unwrap();
+        let value = aux_to_js_value(scope, aux);
+        tags.set(scope, key.into(), value);
+    }
+
+    // Same conversions as the `aln` getters, so `copy.end === aln.end` etc.
+    let chrom = match rec.chrom() {
+        Some(name) => std::str::from_utf8(name).unwrap_or(""),
+        None => "*",
+    };
+    let pos = v8::Integer::new(scope, rec.pos() as i32);
+    let fields: [(&str, v8::Local<v8::Value>); 9] = [
+        (
+            "qname",
+            v8::String::new(scope, std::str::from_utf8(rec.qname()).unwrap_or(""))
+                .unwrap()
+                .into(),
+        ),
+        (
+            "flag",
+            v8::Integer::new_from_unsigned(scope, rec.flags() as u32).into(),
+        ),
+        (
+            "mapq",
+            v8::Integer::new_from_unsigned(scope, rec.mapq() as u32).into(),
+        ),
+        ("chrom", v8::String::new(scope, chrom).unwrap().into()),
+        ("pos", pos.into()),
+        ("start", pos.into()),
+        (
+            "end",
+            v8::Integer::new_from_unsigned(scope, rec.end() as u32).into(),
+        ),
+        ("cigar", cigar_to_js(scope, &rec.cigar()).into()),
+        ("tags", tags.into()),
+    ];
+    let mut names = Vec::with_capacity(fields.len());
+    let mut values = Vec::with_capacity(fields.len());
+    for (name, value) in fields {
+        names.push(v8::String::new(scope, name).unwrap().into());
+        values.push(value);
+    }
+    let proto = prototype(scope);
+    v8::Object::with_prototype_and_properties(scope, proto.into(), &names, &values)
+}
+
+fn prototype<'s>(scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Object> {
+    if let Some(ClonePrototype(proto)) = scope.get_slot::<ClonePrototype>() {
+        let proto = proto.clone();
+        return v8::Local::new(scope, &proto);
+    }
+    let proto = v8::Object::new(scope);
+    let aux = v8::Function::new(scope, cloned_aux_method).unwrap();
+    let key = v8::String::new(scope, "aux").unwrap();
+    proto.set(scope, key.into(), aux.into());
+    let global = v8::Global::new(scope, proto);
+    scope.set_slot(ClonePrototype(global));
+    proto
+}
+
+/// `copy.aux(tag)`: `copy.tags[tag]`, or null if missing.
+#[allow(clippy::needless_pass_by_value)]
+fn cloned_aux_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let key = v8::String::new(scope, "tags").unwrap();
+    let value = args
+        .this()
+        .get(scope, key.into())
+        .and_then(|tags| v8::Local::<v8::Object>::try_from(tags).ok())
+        .and_then(|tags| tags.get(scope, args.get(0)))
+        .filter(|v| !v.is_undefined());
+    match value {
+        Some(v) => rv.set(v),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
@@ -0,0 +1,144 @@
+//! `console.log/info/warn/error/debug` and `print` for scripts.
+//!
+//! Messages go to the `log` crate under the `v8bam::script` target, so they
+//! end up on stderr next to v8bam's own logging. An optional limit caps the
+//! number of messages so a per-record `console.log` can't flood the terminal.
+
+use log::{Level, log, warn};
+
+/// Log target used for script output.
+pub const LOG_TARGET: &str = "v8bam::script";
+
+/// Per-isolate message counter, stored in an isolate slot.
+pub(crate) struct ScriptLog {
+    limit: Option<u64>,
+    count: u64,
+}
+
+impl ScriptLog {
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self { limit, count: 0 }
+    }
+}
+
+/// Install `console` and `print` on `global`.
+pub(crate) fn install(
+    scope: &mut v8::ContextScope<'_, '_, v8::HandleScope<'_>>,
+    global: v8::Local<v8::Object>,
+) {
+    let console = v8::Object::new(scope);
+    let log_fn = v8::Function::new(scope, console_log_callback).unwrap();
+    let warn_fn = v8::Function::new(scope, console_warn_callback).unwrap();
+    let error_fn = v8::Function::new(scope, console_error_callback).unwrap();
+    let debug_fn = v8::Function::new(scope, console_debug_callback).unwrap();
+    let methods = [
+        ("log", log_fn),
+        ("info", log_fn),
+        ("warn", warn_fn),
+        ("error", error_fn),
+        ("debug", debug_fn),
+    ];
+    for (name, func) in methods {
+        let name = v8::String::new(scope, name).unwrap();
+        console.set(scope, name.into(), func.into());
+    }
+    let name = v8::String::new(scope, "console").unwrap();
+    global.set(scope, name.into(), console.into());
+
+    let name = v8::String::new(scope, "print").unwrap();
+    global.set(scope, name.into(), log_fn.into());
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn console_log_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    _rv: v8::ReturnValue,
+) {
+    script_log(scope, &args, Level::Info);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn console_warn_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    _rv: v8::ReturnValue,
+) {
+    script_log(scope, &args, Level::Warn);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn console_error_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    _rv: v8::ReturnValue,
+) {
+    script_log(scope, &args, Level::Error);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn console_debug_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    _rv: v8::ReturnValue,
+) {
+    script_log(scope, &args, Level::Debug);
+}
+
+fn script_log(scope: &mut v8::PinScope, args: &v8::FunctionCallbackArguments, level: Level) {
+    if !log::log_enabled!(target: LOG_TARGET, level) {
+        return;
+    }
+    if let Some(state) = scope.get_slot_mut::<ScriptLog>() {
+        state.count += 1;
+        match state.limit {
+            Some(limit) if state.count == limit + 1 => {
+                warn!(
+                    target: LOG_TARGET,
+                    "script log limit ({limit}) reached; suppressing further messages"
+                );
+                return;
+            }
+            Some(limit) if state.count > limit => return,
+            _ => {}
+        }
+    }
+    let msg = format_args_for_log(scope, args);
+    log!(target: LOG_TARGET, level, "{msg}");
+}
+
+/// Join arguments with spaces like browsers do: strings as-is, objects and
+/// arrays as JSON, typed arrays (e.g. `aln.seqBytes`) as arrays of their
+/// elements, everything else via `String()`.
+fn format_args_for_log(scope: &mut v8::PinScope, args: &v8::FunctionCallbackArguments) -> String {
+    let mut out = String::new();
+    for i in 0..args.length() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let value = args.get(i);
+        if let Ok(array) = v8::Local::<v8::TypedArray>::try_from(value) {
+            // JSON.stringify would give {"0":65,"1":67,...}.
+            out.push('[');
+            for j in 0..array.length() {
+                if j > 0 {
+                    out.push(',');
+                }
+                if let Some(element) = array.get_index(scope, j as u32) {
+                    out.push_str(&element.to_rust_string_lossy(scope));
+                }
+            }
+            out.push(']');
+            continue;
+        }
+        if value.is_object() && !value.is_function() {
+            v8::tc_scope!(let tc, scope);
+            if let Some(json) = v8::json::stringify(tc, value) {
+                out.push_str(&json.to_rust_string_lossy(tc));
+                continue;
+            }
+        }
+        out.push_str(&value.to_rust_string_lossy(scope));
+    }
+    out
+}
@@ -0,0 +1,118 @@
+//! Building blocks for duplicate marking in scripts: unclipped and 5'
+//! positions on `aln`, and a global `seen(key)` set kept in Rust.
+//!
+//! A UMI-aware dedup over a coordinate-sorted stream can then be written as
+//!
+//! ```js
+//! !seen([aln.chrom, aln.fivePrime, hasFlag(aln.flag, 0x10), aln.aux("RX")].join(":"))
+//! ```
+//!
+//! The set only grows; scripts that know when keys can no longer repeat
+//! (e.g. on a new reference of a sorted stream) call `seen.clear()`.
+
+use std::collections::HashSet;
+
+use rust_htslib::bam::record::Cigar;
+
+use crate::alignment_from_obj;
+use crate::record::AlignmentRecord;
+
+/// Keys passed to `seen()`, stored in an isolate slot.
+#[derive(Default)]
+pub(crate) struct SeenKeys(HashSet<Box<str>>);
+
+/// Total length of the clips at the start of `ops`.
+fn clipped(ops: impl Iterator<Item = Cigar>) -> i64 {
+    ops.map_while(|op| match op {
+        Cigar::SoftClip(n) | Cigar::HardClip(n) => Some(n as i64),
+        _ => None,
+    })
+    .sum()
+}
+
+fn unclipped_start(rec: &dyn AlignmentRecord) -> i64 {
+    rec.pos() - clipped(rec.cigar().0.into_iter())
+}
+
+/// 0-based exclusive, like `aln.end`.
+fn unclipped_end(rec: &dyn AlignmentRecord) -> i64 {
+    rec.end() + clipped(rec.cigar().0.into_iter().rev())
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_unclipped_start_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let v = v8::Number::new(scope, unclipped_start(rec) as f64);
+    rv.set(v.into());
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_unclipped_end_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let v = v8::Number::new(scope, unclipped_end(rec) as f64);
+    rv.set(v.into());
+}
+
+/// 0-based position of the read's 5' end including clips: the unclipped
+/// start on the forward strand, the last unclipped base on the reverse.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_five_prime_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let pos = if rec.flags() & 0x10 != 0 {
+        unclipped_end(rec) - 1
+    } else {
+        unclipped_start(rec)
+    };
+    let v = v8::Number::new(scope, pos as f64);
+    rv.set(v.into());
+}
+
+/// `seen(key)`: false the first time `key` (converted to a string) is
+/// passed, true afterwards. The set lives as long as the engine unless the
+/// script empties it with `seen.clear()`.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn seen_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let key = args.get(0).to_rust_string_lossy(scope);
+    let seen = match scope.get_slot_mut::<SeenKeys>() {
+        Some(keys) => !keys.0.insert(key.into_boxed_str()),
+        None => false,
+    };
+    rv.set(v8::Boolean::new(scope, seen).into());
+}
+
+/// `seen.clear()`: forget all keys passed to `seen()` so far.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn seen_clear_callback(
+    scope: &mut v8::PinScope,
+    _args: v8::FunctionCallbackArguments,
+    _rv: v8::ReturnValue,
+) {
+    if let Some(keys) = scope.get_slot_mut::<SeenKeys>() {
+        keys.0 = HashSet::new();
+    }
+}
@@ -0,0 +1,181 @@
+//! Running coverage over coordinate-sorted input: the `depth(chrom, pos)`
+//! helper and the `--max-depth N` cap.
+//!
+//! Coverage counts the reads read so far, like `samtools depth` without
+//! unmapped, secondary, QC-fail and duplicate records. The cap keeps a read
+//! whose start is covered by `depth > N` reads with probability `N / depth`,
+//! chosen by a hash of the qname, so high-depth regions are thinned to about
+//! N reads, the same reads are kept on every run, and mates are kept or
+//! dropped together.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use anyhow::{Result, bail};
+use rust_htslib::bam;
+
+use crate::sample::QnameSampler;
+
+/// Whether a filter script mentions the `depth` helper, so coverage only
+/// needs tracking for scripts that can read it.
+pub fn script_uses_depth(expr: &str) -> bool {
+    expr.match_indices("depth").any(|(i, _)| {
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+        !expr[..i].ends_with(is_ident) && !expr[i + "depth".len()..].starts_with(is_ident)
+    })
+}
+
+/// Records that don't count towards coverage.
+const EXCLUDED_FLAGS: u16 = 0x4 | 0x100 | 0x200 | 0x400;
+
+/// Reads overlapping the start of the last counted read, stored in an
+/// isolate slot for `depth()`, or kept by the caller when no script runs.
+#[derive(Default)]
+pub struct Coverage {
+    tid: i32,
+    chrom: Vec<u8>,
+    /// Start of the last counted read.
+    start: i64,
+    /// Ends (exclusive) of the reads overlapping `start`.
+    ends: BinaryHeap<Reverse<i64>>,
+}
+
+impl Coverage {
+    /// Count `rec`, which must not start before the previous read.
+    pub fn add(&mut self, rec: &bam::Record, header: &bam::HeaderView) -> Result<()> {
+        if rec.flags() & EXCLUDED_FLAGS != 0 || rec.tid() < 0 {
+            return Ok(());
+        }
+        if rec.tid() != self.tid || self.chrom.is_empty() {
+            if !self.chrom.is_empty() && rec.tid() < self.tid {
+                bail!("depth tracking needs coordinate-sorted input");
+            }
+            self.tid = rec.tid();
+            self.chrom = header.tid2name(rec.tid() as u32).to_vec();
+            self.ends.clear();
+        } else if rec.pos() < self.start {
+            bail!(
+                "depth tracking needs coordinate-sorted input, but {} starts before the previous read",
+                String::from_utf8_lossy(rec.qname())
+            );
+        }
+        self.start = rec.pos();
+        while self
+            .ends
+            .peek()
+            .is_some_and(|Reverse(end)| *end <= self.start)
+        {
+            self.ends.pop();
+        }
+        let end = rec.cigar().end_pos().max(self.start + 1);
+        self.ends.push(Reverse(end));
+        Ok(())
+    }
+
+    /// Reads covering `pos` on `chrom`; `None` before the start of the last
+    /// counted read or on another reference, where coverage is no longer
+    /// (or not yet) tracked.
+    pub(crate) fn depth(&self, chrom: &[u8], pos: i64) -> Option<usize> {
+        if self.chrom.is_empty() || chrom != self.chrom || pos < self.start {
+            return None;
+        }
+        if pos == self.start {
+            return Some(self.ends.len());
+        }
+        Some(self.ends.iter().filter(|Reverse(end)| *end > pos).count())
+    }
+
+    /// Reads covering the start of `rec`, the last record added; 0 for
+    /// records without a position.
+    pub fn depth_at(&self, rec: &bam::Record) -> usize {
+        if self.chrom.is_empty() || rec.tid() != self.tid || rec.pos() < self.start {
+            return 0;
+        }
+        if rec.pos() == self.start {
+            return self.ends.len();
+        }
+        let pos = rec.pos();
+        self.ends.iter().filter(|Reverse(end)| *end > pos).count()
+    }
+}
+
+/// `--max-depth`: decides which reads to keep given the coverage at their
+/// start.
+pub struct DepthCap {
+    max: usize,
+    seed: u64,
+    /// Decisions for primary reads whose mate comes later in the input.
+    pending_mates: HashMap<Vec<u8>, bool>,
+    /// Keys of `pending_mates` by mate position, to forget decisions whose
+    /// mate never reached [`Self::keep`] (filtered out or missing) once the
+    /// input has moved past it.
+    by_mate_pos: BTreeMap<(i32, i64), Vec<Vec<u8>>>,
+}
+
+impl DepthCap {
+    pub fn new(max: usize, seed: u64) -> Self {
+        Self {
+            max,
+            seed,
+            pending_mates: HashMap::new(),
+            by_mate_pos: BTreeMap::new(),
+        }
+    }
+
+    /// Whether to keep `rec`, whose start is covered by `depth` reads.
+    pub fn keep(&mut self, rec: &bam::Record, depth: usize) -> bool {
+        if rec.tid() >= 0 {
+            self.forget_before((rec.tid(), rec.pos()));
+        }
+        let primary = rec.flags() & (0x100 | 0x800) == 0;
+        if primary && let Some(keep) = self.pending_mates.remove(rec.qname()) {
+            return keep;
+        }
+        let keep = depth <= self.max
+            || QnameSampler::new(self.max as f64 / depth as f64, self.seed).keep(rec.qname());
+        let mate_later = rec.is_paired()
+            && !rec.is_mate_unmapped()
+            && (rec.mtid(), rec.mpos()) >= (rec.tid(), rec.pos())
+            && rec.tid() >= 0;
+        if primary && mate_later {
+            self.pending_mates.insert(rec.qname().to_vec(), keep);
+            self.by_mate_pos
+                .entry((rec.mtid(), rec.mpos()))
+                .or_default()
+                .push(rec.qname().to_vec());
+        }
+        keep
+    }
+
+    /// Drop the decisions for mates positioned before `pos`.
+    fn forget_before(&mut self, pos: (i32, i64)) {
+        while let Some(entry) = self.by_mate_pos.first_entry() {
+            if *entry.key() >= pos {
+                break;
+            }
+            for qname in entry.remove() {
+                self.pending_mates.remove(&qname);
+            }
+        }
+    }
+}
+
+/// `depth(chrom, pos)`: reads covering the 0-based `pos`, counting the
+/// reads read so far (the current one included); null where coverage is not
+/// tracked (see [`Coverage::depth`]).
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn depth_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let chrom = args.get(0).to_rust_string_lossy(scope);
+    let pos = args.get(1).integer_value(scope).unwrap_or(-1);
+    let depth = scope
+        .get_slot::<Coverage>()
+        .and_then(|c| c.depth(chrom.as_bytes(), pos));
+    match depth {
+        Some(d) => rv.set(v8::Number::new(scope, d as f64).into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
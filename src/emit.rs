@@ -0,0 +1,60 @@
+//! Serialization of script return values for `--emit` mode.
+//!
+//! - strings are written as-is
+//! - arrays are tab-joined; `null`/`undefined` elements become `.` and
+//!   object elements are written as JSON
+//! - other objects are written as a JSON line
+//! - numbers and `true` are written via `String()`
+//! - `undefined`, `null` and `false` write nothing and drop the record
+//! - a value that throws while being serialized fails the call
+
+use anyhow::{Result, bail};
+
+use crate::exception_message;
+
+/// Append the line for `value` to `line` (without a trailing newline).
+/// Returns false if the value drops the record, and an error if
+/// serializing it threw (e.g. a cyclic object, or a getter of an `aln`
+/// kept from an earlier call).
+pub(crate) fn value_to_line(
+    scope: &mut v8::PinScope,
+    value: v8::Local<v8::Value>,
+    line: &mut String,
+) -> Result<bool> {
+    if value.is_null_or_undefined() || value.is_false() {
+        return Ok(false);
+    }
+    v8::tc_scope!(let tc, scope);
+    if let Ok(arr) = v8::Local::<v8::Array>::try_from(value) {
+        for i in 0..arr.length() {
+            if i > 0 {
+                line.push('\t');
+            }
+            match arr.get_index(tc, i) {
+                Some(v) if !v.is_null_or_undefined() => push_scalar(tc, v, line),
+                _ => line.push('.'),
+            }
+        }
+    } else {
+        push_scalar(tc, value, line);
+    }
+    if tc.has_caught() {
+        let (exception, message) = (tc.exception(), tc.message());
+        bail!(
+            "failed to serialize the return value: {}",
+            exception_message(tc, exception, message)
+        );
+    }
+    Ok(true)
+}
+
+/// Strings and primitives via `String()`, objects as JSON.
+fn push_scalar(scope: &mut v8::PinScope, value: v8::Local<v8::Value>, line: &mut String) {
+    if value.is_object() && !value.is_function() {
+        if let Some(json) = v8::json::stringify(scope, value) {
+            line.push_str(&json.to_rust_string_lossy(scope));
+        }
+        return;
+    }
+    line.push_str(&value.to_rust_string_lossy(scope));
+}
@@ -0,0 +1,601 @@
+//! FASTQ input and output.
+//!
+//! Output converts passing alignment records to FASTQ. Input is filtered by
+//! [`JsFastqFilterEngine`], which runs the same V8 machinery as
+//! [`crate::JsBamFilterEngine`] with a `read` object instead of `aln`.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use log::warn;
+use rust_htslib::bam;
+use rust_htslib::bam::record::Aux;
+
+use crate::{
+    EngineOptions, ScriptObject, ScriptRuntime, bound_pointer, decompress_if_gzip, emit,
+    open_text_input, snapshot,
+};
+
+/// How records are converted to FASTQ.
+#[derive(Debug, Clone)]
+pub struct FastqOptions {
+    /// Tag holding the original qualities (e.g. `OQ`), used instead of the
+    /// record's qualities when present.
+    pub orig_qual_tag: Option<[u8; 2]>,
+    /// Aux tags copied into the header line as `TAG:TYPE:VALUE`, tab
+    /// separated, like `samtools fastq -T`.
+    pub tags: Vec<[u8; 2]>,
+    /// Phred quality written when a record has no qualities.
+    pub default_qual: u8,
+}
+
+impl Default for FastqOptions {
+    fn default() -> Self {
+        Self {
+            orig_qual_tag: None,
+            tags: Vec::new(),
+            default_qual: 1,
+        }
+    }
+}
+
+/// Append `rec` as a FASTQ record to `out`. Reverse-strand reads are
+/// reverse-complemented (and their qualities reversed) so the original read
+/// is restored.
+pub fn format_fastq(rec: &bam::Record, opts: &FastqOptions, out: &mut Vec<u8>) {
+    let reverse = rec.is_reverse();
+
+    out.push(b'@');
+    out.extend_from_slice(rec.qname());
+    for tag in &opts.tags {
+        if let Ok(aux) = rec.aux(tag) {
+            out.push(b'\t');
+            push_sam_aux(tag, &aux, out);
+        }
+    }
+    out.push(b'\n');
+
+    let seq = rec.seq();
+    let len = seq.len();
+    if reverse {
+        out.extend((0..len).rev().map(|i| complement(seq[i])));
+    } else {
+        out.extend((0..len).map(|i| seq[i]));
+    }
+    out.extend_from_slice(b"\n+\n");
+
+    let orig = opts.orig_qual_tag.and_then(|tag| match rec.aux(&tag) {
+        Ok(Aux::String(s)) if s.len() == len => Some(s.as_bytes()),
+        _ => None,
+    });
+    match orig {
+        // Original qualities are phred+33 text in the same (aligned)
+        // orientation as QUAL.
+        Some(q) if reverse => out.extend(q.iter().rev()),
+        Some(q) => out.extend_from_slice(q),
+        None => {
+            let qual = rec.qual();
+            if qual.first().is_none_or(|&q| q == 0xff) {
+                out.extend(std::iter::repeat_n(phred33(opts.default_qual), len));
+            } else if reverse {
+                out.extend(qual.iter().rev().map(|&q| phred33(q)));
+            } else {
+                out.extend(qual.iter().map(|&q| phred33(q)));
+            }
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Phred+33 character for quality `q`, capped at 93 (`~`), the highest
+/// quality FASTQ can represent.
+#[inline]
+fn phred33(q: u8) -> u8 {
+    q.min(93) + 33
+}
+
+#[inline]
+pub(crate) fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => b'N',
+    }
+}
+
+/// Write an aux field in SAM text form, e.g. `NM:i:3` or `RG:Z:grp1`.
+fn push_sam_aux(tag: &[u8; 2], aux: &Aux<'_>, out: &mut Vec<u8>) {
+    out.extend_from_slice(tag);
+    let _ = match aux {
+        Aux::I8(v) => write!(out, ":i:{v}"),
+        Aux::U8(v) => write!(out, ":i:{v}"),
+        Aux::I16(v) => write!(out, ":i:{v}"),
+        Aux::U16(v) => write!(out, ":i:{v}"),
+        Aux::I32(v) => write!(out, ":i:{v}"),
+        Aux::U32(v) => write!(out, ":i:{v}"),
+        Aux::Float(v) => write!(out, ":f:{v}"),
+        Aux::Double(v) => write!(out, ":f:{v}"),
+        Aux::Char(v) => write!(out, ":A:{}", *v as char),
+        Aux::String(v) => write!(out, ":Z:{v}"),
+        Aux::HexByteArray(v) => write!(out, ":H:{v}"),
+        Aux::ArrayI8(arr) => write_b_array(out, 'c', arr.iter()),
+        Aux::ArrayU8(arr) => write_b_array(out, 'C', arr.iter()),
+        Aux::ArrayI16(arr) => write_b_array(out, 's', arr.iter()),
+        Aux::ArrayU16(arr) => write_b_array(out, 'S', arr.iter()),
+        Aux::ArrayI32(arr) => write_b_array(out, 'i', arr.iter()),
+        Aux::ArrayU32(arr) => write_b_array(out, 'I', arr.iter()),
+        Aux::ArrayFloat(arr) => write_b_array(out, 'f', arr.iter()),
+    };
+}
+
+fn write_b_array<T: std::fmt::Display>(
+    out: &mut Vec<u8>,
+    subtype: char,
+    values: impl Iterator<Item = T>,
+) -> io::Result<()> {
+    write!(out, ":B:{subtype}")?;
+    for v in values {
+        write!(out, ",{v}")?;
+    }
+    Ok(())
+}
+
+/// Destination(s) for FASTQ records: a single (interleaved) stream, or
+/// separate read 1 / read 2 files with an optional file for unpaired reads.
+///
+/// Mates are only written as complete pairs, so the two files (or the
+/// interleaved stream) stay in sync whatever order the input is in and
+/// whichever mates the filter dropped. A mate is held in memory until its
+/// partner arrives: with name-collated input that is the next record, with
+/// coordinate-sorted input it can be many. Mates whose partner never
+/// arrives are written to the singletons stream by [`Self::finish`].
+pub struct FastqWriter {
+    single: Option<Box<dyn Write>>,
+    read1: Option<Box<dyn Write>>,
+    read2: Option<Box<dyn Write>>,
+    singletons: Option<Box<dyn Write>>,
+    opts: FastqOptions,
+    /// Formatted mates waiting for their partner, by qname.
+    pending: HashMap<Vec<u8>, PendingMate>,
+    /// Number of mates buffered so far, to keep orphans in input order.
+    pending_seq: u64,
+}
+
+struct PendingMate {
+    seq: u64,
+    first: bool,
+    fastq: Vec<u8>,
+}
+
+impl FastqWriter {
+    /// All records to one stream, each pair as read 1 then read 2. Mates
+    /// without a partner are dropped.
+    pub fn interleaved(out: Box<dyn Write>, opts: FastqOptions) -> Self {
+        Self {
+            single: Some(out),
+            read1: None,
+            read2: None,
+            singletons: None,
+            opts,
+            pending: HashMap::new(),
+            pending_seq: 0,
+        }
+    }
+
+    /// First and second mates to separate streams. Unpaired reads go to
+    /// `singletons`, or are dropped if it is `None`.
+    pub fn paired(
+        read1: Box<dyn Write>,
+        read2: Box<dyn Write>,
+        singletons: Option<Box<dyn Write>>,
+        opts: FastqOptions,
+    ) -> Self {
+        Self {
+            single: None,
+            read1: Some(read1),
+            read2: Some(read2),
+            singletons,
+            opts,
+            pending: HashMap::new(),
+            pending_seq: 0,
+        }
+    }
+
+    /// Write `rec`, or hold it until its mate arrives. Returns the number of
+    /// records written by this call: 2 when `rec` completes a pair. Secondary
+    /// and supplementary alignments are always skipped, since their sequence
+    /// duplicates (part of) the primary record.
+    pub fn write(&mut self, rec: &bam::Record) -> Result<usize> {
+        if rec.is_secondary() || rec.is_supplementary() {
+            return Ok(0);
+        }
+        let mut fastq = Vec::new();
+        format_fastq(rec, &self.opts, &mut fastq);
+        let first = rec.is_first_in_template();
+        if !rec.is_paired() || first == rec.is_last_in_template() {
+            return self.write_unpaired(&fastq);
+        }
+
+        match self.pending.remove(rec.qname()) {
+            Some(mate) if mate.first != first => {
+                let (r1, r2) = if first {
+                    (&fastq, &mate.fastq)
+                } else {
+                    (&mate.fastq, &fastq)
+                };
+                match &mut self.single {
+                    Some(out) => {
+                        out.write_all(r1)?;
+                        out.write_all(r2)?;
+                    }
+                    None => {
+                        self.read1.as_mut().unwrap().write_all(r1)?;
+                        self.read2.as_mut().unwrap().write_all(r2)?;
+                    }
+                }
+                Ok(2)
+            }
+            mate => {
+                // A second record for the same mate: the earlier one can't
+                // be paired any more.
+                let written = match mate {
+                    Some(mate) => self.write_orphan(&mate.fastq)?,
+                    None => 0,
+                };
+                self.pending_seq += 1;
+                let mate = PendingMate {
+                    seq: self.pending_seq,
+                    first,
+                    fastq,
+                };
+                self.pending.insert(rec.qname().to_vec(), mate);
+                Ok(written)
+            }
+        }
+    }
+
+    /// Unpaired reads go to the interleaved stream or the singletons file.
+    fn write_unpaired(&mut self, fastq: &[u8]) -> Result<usize> {
+        let out = match (&mut self.single, &mut self.singletons) {
+            (Some(out), _) | (None, Some(out)) => out,
+            (None, None) => return Ok(0),
+        };
+        out.write_all(fastq)?;
+        Ok(1)
+    }
+
+    /// Mates without a partner go to the singletons file; the interleaved
+    /// stream has none, as they would break its pairing.
+    fn write_orphan(&mut self, fastq: &[u8]) -> Result<usize> {
+        match &mut self.singletons {
+            Some(out) => {
+                out.write_all(fastq)?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Write mates whose partner never arrived to the singletons stream and
+    /// flush all streams. Returns the number of records written.
+    pub fn finish(&mut self) -> Result<usize> {
+        let mut orphans: Vec<PendingMate> = self.pending.drain().map(|(_, mate)| mate).collect();
+        orphans.sort_unstable_by_key(|mate| mate.seq);
+        let mut written = 0;
+        for mate in &orphans {
+            written += self.write_orphan(&mate.fastq)?;
+        }
+        if written < orphans.len() {
+            warn!(
+                "dropped {} mates without a partner (removed by the filter or missing)",
+                orphans.len() - written
+            );
+        }
+        for out in [
+            &mut self.single,
+            &mut self.read1,
+            &mut self.read2,
+            &mut self.singletons,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            out.flush()?;
+        }
+        Ok(written)
+    }
+}
+
+// ========== Input ==========
+
+/// A FASTQ record. Buffers are reused when reading into the same record.
+#[derive(Debug, Default, Clone)]
+pub struct FastqRecord {
+    /// Read name: the header up to the first whitespace, without `@`.
+    pub name: Vec<u8>,
+    /// Rest of the header line after the name (may be empty).
+    pub comment: Vec<u8>,
+    pub seq: Vec<u8>,
+    /// Phred+33 quality string.
+    pub qual: Vec<u8>,
+}
+
+impl FastqRecord {
+    /// Append the record as FASTQ text to `out`.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        out.push(b'@');
+        out.extend_from_slice(&self.name);
+        if !self.comment.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(&self.comment);
+        }
+        out.push(b'\n');
+        out.extend_from_slice(&self.seq);
+        out.extend_from_slice(b"\n+\n");
+        out.extend_from_slice(&self.qual);
+        out.push(b'\n');
+    }
+}
+
+/// Streaming reader of 4-line FASTQ; gzip input is detected from the magic
+/// bytes.
+pub struct FastqReader {
+    inner: Box<dyn BufRead>,
+    line: Vec<u8>,
+    records: u64,
+}
+
+impl FastqReader {
+    /// Open `path` ("-" for stdin).
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Ok(Self::from_buf_read(open_text_input(path)?))
+    }
+
+    pub fn new(reader: impl Read + 'static) -> Result<Self> {
+        Ok(Self::from_buf_read(decompress_if_gzip(reader)?))
+    }
+
+    fn from_buf_read(inner: Box<dyn BufRead>) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+            records: 0,
+        }
+    }
+
+    /// Read the next record into `rec`. Like `bam::Read::read`, returns
+    /// `None` at end of input.
+    pub fn read(&mut self, rec: &mut FastqRecord) -> Option<Result<()>> {
+        match self.read_record(rec) {
+            Ok(true) => Some(Ok(())),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn read_record(&mut self, rec: &mut FastqRecord) -> Result<bool> {
+        // Tolerate blank lines between records (e.g. at the end of the file).
+        loop {
+            if !next_line(&mut self.inner, &mut self.line)? {
+                return Ok(false);
+            }
+            if !self.line.is_empty() {
+                break;
+            }
+        }
+        self.records += 1;
+        let n = self.records;
+
+        let Some(header) = self.line.strip_prefix(b"@") else {
+            bail!("FASTQ record {n} does not start with '@'");
+        };
+        rec.name.clear();
+        rec.comment.clear();
+        match header.iter().position(|b| b.is_ascii_whitespace()) {
+            Some(i) => {
+                rec.name.extend_from_slice(&header[..i]);
+                rec.comment.extend_from_slice(&header[i + 1..]);
+            }
+            None => rec.name.extend_from_slice(header),
+        }
+
+        if !next_line(&mut self.inner, &mut rec.seq)? {
+            bail!("FASTQ record {n} is truncated");
+        }
+        if !next_line(&mut self.inner, &mut self.line)? || !self.line.starts_with(b"+") {
+            bail!("FASTQ record {n} has no '+' separator line");
+        }
+        if !next_line(&mut self.inner, &mut rec.qual)? {
+            bail!("FASTQ record {n} is truncated");
+        }
+        if rec.qual.len() != rec.seq.len() {
+            bail!("FASTQ record {n} has sequence and quality of different lengths");
+        }
+        Ok(true)
+    }
+}
+
+/// Read one line into `buf` without the line terminator; false at EOF.
+fn next_line(reader: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<bool> {
+    buf.clear();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(false);
+    }
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
+    }
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    Ok(true)
+}
+
+// ========== Engine ==========
+
+pub(crate) const READ_OBJECT: ScriptObject = ScriptObject {
+    param: "read",
+    extra_params: &[],
+    make_template: make_read_template,
+    snapshot_index: snapshot::READ_TEMPLATE_INDEX,
+};
+
+/// Engine that filters FASTQ records with a JS expression over a `read`
+/// object exposing `name`, `comment`, `seq`, `qual` and `length`.
+pub struct JsFastqFilterEngine {
+    rt: ScriptRuntime,
+}
+
+impl JsFastqFilterEngine {
+    /// Create a new engine with a JS filter expression or body, e.g.
+    /// `"read.length >= 50 && !read.seq.includes('NNNN')"`.
+    pub fn new(expr: &str) -> Result<Self> {
+        Self::with_options(expr, EngineOptions::default())
+    }
+
+    pub fn with_options(expr: &str, opts: EngineOptions) -> Result<Self> {
+        Ok(Self {
+            rt: ScriptRuntime::new(expr, &READ_OBJECT, opts)?,
+        })
+    }
+
+    /// Run the JS filter on a single FASTQ record.
+    pub fn record_passes(&mut self, rec: &FastqRecord) -> Result<bool> {
+        let ptr = rec as *const FastqRecord as *mut c_void;
+        self.rt
+            .call(&[ptr], |scope, result| result.boolean_value(scope))
+    }
+
+    /// Like [`crate::JsBamFilterEngine::record_emit`] for FASTQ records.
+    pub fn record_emit(&mut self, rec: &FastqRecord, line: &mut String) -> Result<bool> {
+        let ptr = rec as *const FastqRecord as *mut c_void;
+        self.rt.call(&[ptr], |scope, result| {
+            emit::value_to_line(scope, result, line)
+        })?
+    }
+
+    /// Bytes currently used on the V8 heap.
+    pub fn used_heap_size(&mut self) -> usize {
+        self.rt.used_heap_size()
+    }
+
+    /// Names of unknown `read` properties read so far.
+    pub fn unknown_properties(&self) -> Vec<String> {
+        self.rt.unknown_properties()
+    }
+}
+
+/// Create an ObjectTemplate for `read` with lazy accessors.
+pub(crate) fn make_read_template<'s>(
+    scope: &mut v8::ContextScope<'s, '_, v8::HandleScope<'_>>,
+) -> v8::Local<'s, v8::ObjectTemplate> {
+    let tmpl = v8::ObjectTemplate::new(scope);
+    // 0: FastqRecord
+    tmpl.set_internal_field_count(1);
+
+    let name = v8::String::new(scope, "name").unwrap();
+    tmpl.set_accessor(name.into(), read_name_getter);
+
+    let comment = v8::String::new(scope, "comment").unwrap();
+    tmpl.set_accessor(comment.into(), read_comment_getter);
+
+    let seq = v8::String::new(scope, "seq").unwrap();
+    tmpl.set_accessor(seq.into(), read_seq_getter);
+
+    let qual = v8::String::new(scope, "qual").unwrap();
+    tmpl.set_accessor(qual.into(), read_qual_getter);
+
+    let length = v8::String::new(scope, "length").unwrap();
+    tmpl.set_accessor(length.into(), read_length_getter);
+
+    tmpl
+}
+
+#[inline(always)]
+fn fastq_record_from_obj<'s>(
+    scope: &mut v8::PinScope,
+    obj: v8::Local<v8::Object>,
+) -> Option<&'s FastqRecord> {
+    let ptr = bound_pointer::<FastqRecord>(scope, obj, 0)?;
+    Some(unsafe { &*ptr })
+}
+
+fn one_byte_string<'s>(scope: &mut v8::PinScope<'s, '_>, bytes: &[u8]) -> v8::Local<'s, v8::Value> {
+    v8::String::new_from_one_byte(scope, bytes, v8::NewStringType::Normal)
+        .unwrap()
+        .into()
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn read_name_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = fastq_record_from_obj(scope, args.this()) else {
+        return;
+    };
+    rv.set(one_byte_string(scope, &rec.name));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn read_comment_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = fastq_record_from_obj(scope, args.this()) else {
+        return;
+    };
+    rv.set(one_byte_string(scope, &rec.comment));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn read_seq_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = fastq_record_from_obj(scope, args.this()) else {
+        return;
+    };
+    rv.set(one_byte_string(scope, &rec.seq));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn read_qual_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = fastq_record_from_obj(scope, args.this()) else {
+        return;
+    };
+    rv.set(one_byte_string(scope, &rec.qual));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn read_length_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = fastq_record_from_obj(scope, args.this()) else {
+        return;
+    };
+    let v = v8::Integer::new_from_unsigned(scope, rec.seq.len() as u32);
+    rv.set(v.into());
+}
@@ -0,0 +1,313 @@
+//! Output header edits returned by a script's `header(hdr)` hook.
+//!
+//! The hook receives the input header as a plain object and returns an
+//! object describing edits:
+//!
+//! ```js
+//! {
+//!   renameReferences: { "1": "chr1" },
+//!   removeReferences: ["chrUn_gl000220"],
+//!   addReadGroups: [{ ID: "rg2", SM: "NA12878" }],
+//!   removeReadGroups: ["rg1"],
+//!   addComments: ["filtered with v8bam"],
+//!   removeComments: true, // or a list of exact comment texts
+//! }
+//! ```
+//!
+//! Records are renumbered to the edited reference list before they are
+//! written; see [`RecordRemap`].
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+use rust_htslib::bam;
+use serde_json::{Map, Value, json};
+
+/// Which `@CO` lines to drop.
+#[derive(Debug, Default)]
+enum CommentRemoval {
+    #[default]
+    None,
+    All,
+    Matching(HashSet<String>),
+}
+
+/// Parsed header edits.
+#[derive(Debug, Default)]
+pub struct HeaderEdits {
+    rename_references: HashMap<String, String>,
+    remove_references: HashSet<String>,
+    add_read_groups: Vec<Vec<(String, String)>>,
+    remove_read_groups: HashSet<String>,
+    add_comments: Vec<String>,
+    remove_comments: CommentRemoval,
+}
+
+/// Per-record changes that follow from the header edits.
+#[derive(Debug)]
+pub struct RecordRemap {
+    /// Input tid → output tid, -1 for removed references.
+    tid_map: Vec<i32>,
+    removed_read_groups: HashSet<String>,
+}
+
+/// The `hdr` object passed to `header(hdr)`.
+pub fn header_to_json(header: &bam::HeaderView) -> Value {
+    let mut hd = Value::Null;
+    let mut read_groups = Vec::new();
+    let mut programs = Vec::new();
+    let mut comments = Vec::new();
+    for line in String::from_utf8_lossy(header.as_bytes()).lines() {
+        match line.get(..3) {
+            Some("@HD") => hd = tags_to_json(line),
+            Some("@RG") => read_groups.push(tags_to_json(line)),
+            Some("@PG") => programs.push(tags_to_json(line)),
+            Some("@CO") => comments.push(Value::from(line.get(4..).unwrap_or(""))),
+            _ => {}
+        }
+    }
+    let references: Vec<Value> = (0..header.target_count())
+        .map(|tid| {
+            json!({
+                "name": String::from_utf8_lossy(header.tid2name(tid)),
+                "length": header.target_len(tid),
+            })
+        })
+        .collect();
+    json!({
+        "hd": hd,
+        "references": references,
+        "readGroups": read_groups,
+        "programs": programs,
+        "comments": comments,
+    })
+}
+
+fn tags_to_json(line: &str) -> Value {
+    let tags: Map<String, Value> = line
+        .split('\t')
+        .skip(1)
+        .filter_map(|field| field.split_once(':'))
+        .map(|(k, v)| (k.to_string(), Value::from(v)))
+        .collect();
+    Value::Object(tags)
+}
+
+fn string_list(value: &Value, key: &str) -> Result<Vec<String>> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(s.clone()),
+                other => bail!("header(): {key} must contain strings, got {other}"),
+            })
+            .collect(),
+        Some(other) => bail!("header(): {key} must be an array, got {other}"),
+    }
+}
+
+fn value_to_tag(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl HeaderEdits {
+    /// Parse the hook's return value; `null`/`undefined` means no edits.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+        if !value.is_object() {
+            bail!("header() must return an object, got {value}");
+        }
+
+        let rename_references = match value.get("renameReferences") {
+            None | Some(Value::Null) => HashMap::new(),
+            Some(Value::Object(map)) => map
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_tag(v)))
+                .collect(),
+            Some(other) => bail!("header(): renameReferences must be an object, got {other}"),
+        };
+
+        let mut add_read_groups = Vec::new();
+        if let Some(groups) = value.get("addReadGroups").filter(|v| !v.is_null()) {
+            let Some(groups) = groups.as_array() else {
+                bail!("header(): addReadGroups must be an array, got {groups}");
+            };
+            for group in groups {
+                let Some(tags) = group.as_object() else {
+                    bail!("header(): addReadGroups entries must be objects, got {group}");
+                };
+                if !tags.contains_key("ID") {
+                    bail!("header(): read group {group} has no ID");
+                }
+                // ID first, as samtools writes it.
+                let mut fields = vec![("ID".to_string(), value_to_tag(&tags["ID"]))];
+                fields.extend(
+                    tags.iter()
+                        .filter(|(k, _)| *k != "ID")
+                        .map(|(k, v)| (k.clone(), value_to_tag(v))),
+                );
+                add_read_groups.push(fields);
+            }
+        }
+
+        let remove_comments = match value.get("removeComments") {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => CommentRemoval::None,
+            Some(Value::Bool(true)) => CommentRemoval::All,
+            Some(_) => CommentRemoval::Matching(
+                string_list(value, "removeComments")?.into_iter().collect(),
+            ),
+        };
+
+        Ok(Self {
+            rename_references,
+            remove_references: string_list(value, "removeReferences")?
+                .into_iter()
+                .collect(),
+            add_read_groups,
+            remove_read_groups: string_list(value, "removeReadGroups")?
+                .into_iter()
+                .collect(),
+            add_comments: string_list(value, "addComments")?,
+            remove_comments,
+        })
+    }
+
+    /// Build the output header and the matching record remapping.
+    pub fn apply(&self, header: &bam::HeaderView) -> Result<(bam::HeaderView, RecordRemap)> {
+        let names: Vec<String> = (0..header.target_count())
+            .map(|tid| String::from_utf8_lossy(header.tid2name(tid)).into_owned())
+            .collect();
+        for name in self.rename_references.keys().chain(&self.remove_references) {
+            if !names.contains(name) {
+                bail!("header(): unknown reference {name:?}");
+            }
+        }
+
+        // Output names of the kept references, and the tid map.
+        let mut tid_map = Vec::with_capacity(names.len());
+        let mut out_names = HashSet::new();
+        for name in &names {
+            if self.remove_references.contains(name) {
+                tid_map.push(-1);
+                continue;
+            }
+            let out = self.rename_references.get(name).unwrap_or(name);
+            if !out_names.insert(out.clone()) {
+                bail!("header(): reference name {out:?} would appear twice");
+            }
+            tid_map.push(out_names.len() as i32 - 1);
+        }
+
+        let mut read_group_ids = HashSet::new();
+        let mut text = String::new();
+        for line in String::from_utf8_lossy(header.as_bytes()).lines() {
+            let kind = line.get(..3).unwrap_or("");
+            let id = |tag: &str| {
+                line.split('\t')
+                    .find_map(|f| f.strip_prefix(tag))
+                    .unwrap_or("")
+                    .to_string()
+            };
+            match kind {
+                "@SQ" => {
+                    let name = id("SN:");
+                    if self.remove_references.contains(&name) {
+                        continue;
+                    }
+                    if let Some(new_name) = self.rename_references.get(&name) {
+                        let fields: Vec<String> = line
+                            .split('\t')
+                            .map(|f| match f.strip_prefix("SN:") {
+                                Some(_) => format!("SN:{new_name}"),
+                                None => f.to_string(),
+                            })
+                            .collect();
+                        text.push_str(&fields.join("\t"));
+                        text.push('\n');
+                        continue;
+                    }
+                }
+                "@RG" => {
+                    let rg = id("ID:");
+                    if self.remove_read_groups.contains(&rg) {
+                        continue;
+                    }
+                    read_group_ids.insert(rg);
+                }
+                "@CO" => {
+                    let comment = line.get(4..).unwrap_or("");
+                    match &self.remove_comments {
+                        CommentRemoval::All => continue,
+                        CommentRemoval::Matching(set) if set.contains(comment) => continue,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+            if !line.is_empty() {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        for group in &self.add_read_groups {
+            let id = &group[0].1;
+            if !read_group_ids.insert(id.clone()) {
+                bail!("header(): read group {id:?} already exists");
+            }
+            text.push_str("@RG");
+            for (k, v) in group {
+                text.push_str(&format!("\t{k}:{v}"));
+            }
+            text.push('\n');
+        }
+        for comment in &self.add_comments {
+            text.push_str(&format!("@CO\t{comment}\n"));
+        }
+
+        let remap = RecordRemap {
+            tid_map,
+            removed_read_groups: self.remove_read_groups.clone(),
+        };
+        Ok((bam::HeaderView::from_bytes(text.as_bytes()), remap))
+    }
+}
+
+impl RecordRemap {
+    /// Renumber `rec` for the output header. Returns false if the record is
+    /// on a removed reference and must be dropped; a mate on a removed
+    /// reference is marked unmapped (no position, TLEN 0, not a proper pair,
+    /// no MC tag), and the RG tag of removed read groups is stripped.
+    pub fn apply(&self, rec: &mut bam::Record) -> bool {
+        if rec.tid() >= 0 {
+            let tid = self.tid_map[rec.tid() as usize];
+            if tid < 0 {
+                return false;
+            }
+            rec.set_tid(tid);
+        }
+        if rec.mtid() >= 0 {
+            let mtid = self.tid_map[rec.mtid() as usize];
+            if mtid < 0 {
+                rec.set_mpos(-1);
+                rec.set_insert_size(0);
+                rec.set_mate_unmapped();
+                rec.unset_proper_pair();
+                let _ = rec.remove_aux(b"MC");
+            }
+            rec.set_mtid(mtid);
+        }
+        if !self.removed_read_groups.is_empty()
+            && let Ok(bam::record::Aux::String(rg)) = rec.aux(b"RG")
+            && self.removed_read_groups.contains(rg)
+        {
+            let _ = rec.remove_aux(b"RG");
+        }
+        true
+    }
+}
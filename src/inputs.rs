@@ -0,0 +1,402 @@
+//! Reading several BAM/SAM/CRAM inputs as one stream.
+//!
+//! The inputs' headers are reconciled into one: reference sequences and
+//! `@RG`/`@PG` lines are united by name/ID, `@CO` lines are kept once each.
+//! `@SQ` lines keep the first input's tags (`M5`, `UR`, ...), and an `@RG`
+//! or `@PG` line whose ID clashes with a different earlier one is renamed to
+//! `ID.1`, `ID.2`, ... as `samtools merge` does.
+//! Records are rewritten to the merged reference numbering and renamed
+//! `RG`/`PG` tags, so the writer and the `aln` object only ever see the
+//! merged header.
+
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use rust_htslib::bam;
+use rust_htslib::bam::Read;
+use rust_htslib::bam::record::Aux;
+use rust_htslib::tpool::ThreadPool;
+
+/// How records from several inputs are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Combine {
+    /// All records of the first input, then the second, and so on.
+    #[default]
+    Concat,
+    /// Interleave coordinate-sorted inputs into one coordinate-sorted stream.
+    Merge,
+}
+
+struct Input {
+    reader: bam::Reader,
+    /// Input tid → merged tid.
+    tid_map: Vec<i32>,
+    renames: IdRenames,
+}
+
+/// An input's `@RG`/`@PG` IDs that were renamed in the merged header.
+#[derive(Debug, Default)]
+struct IdRenames {
+    read_groups: HashMap<String, String>,
+    programs: HashMap<String, String>,
+}
+
+impl IdRenames {
+    fn is_empty(&self) -> bool {
+        self.read_groups.is_empty() && self.programs.is_empty()
+    }
+
+    /// Rewrite the record's `RG` and `PG` tags to the renamed IDs.
+    fn apply(&self, rec: &mut bam::Record) -> Result<()> {
+        for (tag, renames) in [(b"RG", &self.read_groups), (b"PG", &self.programs)] {
+            let new_id = match rec.aux(tag) {
+                Ok(Aux::String(id)) => renames.get(id).cloned(),
+                _ => None,
+            };
+            if let Some(new_id) = new_id {
+                rec.remove_aux(tag)?;
+                rec.push_aux(tag, Aux::String(&new_id))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A set of BAM/SAM/CRAM readers presented as one stream of records.
+pub struct BamInputs {
+    inputs: Vec<Input>,
+    names: Vec<String>,
+    header: bam::HeaderView,
+    combine: Combine,
+    /// Concat: index of the input being read.
+    current: usize,
+    /// Merge: the next record of each input, `None` once it is exhausted.
+    heads: Vec<Option<bam::Record>>,
+    /// Merge: sort key of the last record taken from each input.
+    last_keys: Vec<(u32, i64)>,
+}
+
+impl BamInputs {
+    /// Open `paths` ("-" for stdin) and reconcile their headers.
+    pub fn open(paths: &[PathBuf], combine: Combine, tpool: Option<&ThreadPool>) -> Result<Self> {
+        let mut readers = Vec::with_capacity(paths.len());
+        for path in paths {
+            let mut reader = open_reader(path)?;
+            if let Some(tpool) = tpool {
+                reader.set_thread_pool(tpool)?;
+            }
+            readers.push(reader);
+        }
+        let names = paths.iter().map(|p| p.display().to_string()).collect();
+        let headers: Vec<&bam::HeaderView> = readers.iter().map(|r| r.header()).collect();
+        let (header, mappings) = reconcile_headers(&headers, paths, combine)?;
+
+        let inputs: Vec<Input> = readers
+            .into_iter()
+            .zip(mappings)
+            .map(|(reader, (tid_map, renames))| Input {
+                reader,
+                tid_map,
+                renames,
+            })
+            .collect();
+        let n = inputs.len();
+        let mut this = Self {
+            inputs,
+            names,
+            header,
+            combine,
+            current: 0,
+            heads: Vec::new(),
+            last_keys: vec![(0, i64::MIN); n],
+        };
+        if combine == Combine::Merge {
+            for i in 0..n {
+                let mut rec = bam::Record::new();
+                let head = this.read_from(i, &mut rec)?.then_some(rec);
+                this.heads.push(head);
+            }
+        }
+        Ok(this)
+    }
+
+    /// The reconciled header that all records refer to.
+    pub fn header(&self) -> &bam::HeaderView {
+        &self.header
+    }
+
+    /// Whether the header declares coordinate order (`@HD SO:coordinate`).
+    pub fn coordinate_sorted(&self) -> bool {
+        String::from_utf8_lossy(self.header.as_bytes())
+            .lines()
+            .find(|line| line.starts_with("@HD"))
+            .is_some_and(|hd| hd.split('\t').any(|f| f == "SO:coordinate"))
+    }
+
+    /// Input paths as given, for `aln.sourceFile`.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Read the next record into `rec`, returning the index of the input it
+    /// came from.
+    pub fn read(&mut self, rec: &mut bam::Record) -> Option<Result<usize>> {
+        match self.combine {
+            Combine::Concat => {
+                while self.current < self.inputs.len() {
+                    match self.read_from(self.current, rec) {
+                        Ok(true) => return Some(Ok(self.current)),
+                        Ok(false) => self.current += 1,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                None
+            }
+            Combine::Merge => {
+                let (index, _) = self
+                    .heads
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, head)| head.as_ref().map(|r| (i, sort_key(r))))
+                    .min_by_key(|&(_, key)| key)?;
+                let mut head = self.heads[index].take().unwrap();
+                mem::swap(rec, &mut head);
+                match self.read_from(index, &mut head) {
+                    Ok(true) => self.heads[index] = Some(head),
+                    Ok(false) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                Some(Ok(index))
+            }
+        }
+    }
+
+    /// Read the next record of input `index` into `rec` and remap its tids.
+    fn read_from(&mut self, index: usize, rec: &mut bam::Record) -> Result<bool> {
+        let input = &mut self.inputs[index];
+        match input.reader.read(rec) {
+            None => return Ok(false),
+            Some(r) => r.with_context(|| format!("failed to read {}", self.names[index]))?,
+        }
+        if rec.tid() >= 0 {
+            rec.set_tid(input.tid_map[rec.tid() as usize]);
+        }
+        if rec.mtid() >= 0 {
+            rec.set_mtid(input.tid_map[rec.mtid() as usize]);
+        }
+        if !input.renames.is_empty() {
+            input.renames.apply(rec)?;
+        }
+        if self.combine == Combine::Merge {
+            let key = sort_key(rec);
+            if key < self.last_keys[index] {
+                bail!(
+                    "{} is not coordinate-sorted; cannot merge (use concatenation instead)",
+                    self.names[index]
+                );
+            }
+            self.last_keys[index] = key;
+        }
+        Ok(true)
+    }
+}
+
+fn open_reader(path: &Path) -> Result<bam::Reader> {
+    if path.to_string_lossy() == "-" {
+        bam::Reader::from_stdin().context("failed to open stdin as BAM")
+    } else {
+        bam::Reader::from_path(path)
+            .with_context(|| format!("failed to open BAM {}", path.display()))
+    }
+}
+
+/// Coordinate order with unmapped reads (tid -1) last.
+fn sort_key(rec: &bam::Record) -> (u32, i64) {
+    (rec.tid() as u32, rec.pos())
+}
+
+/// Build the merged header and each input's tid map and ID renames. For a
+/// merge, the reference order of every input must agree with the merged
+/// order.
+fn reconcile_headers(
+    headers: &[&bam::HeaderView],
+    paths: &[PathBuf],
+    combine: Combine,
+) -> Result<(bam::HeaderView, Vec<(Vec<i32>, IdRenames)>)> {
+    if headers.len() == 1 {
+        let tid_map = (0..headers[0].target_count() as i32).collect();
+        return Ok((headers[0].clone(), vec![(tid_map, IdRenames::default())]));
+    }
+
+    let mut hd_line = None;
+    // Name, length and the full @SQ line of the first input that has it.
+    let mut targets: Vec<(Vec<u8>, u64, Option<String>)> = Vec::new();
+    let mut target_index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut other_lines: Vec<String> = Vec::new();
+    let mut ids: HashMap<(String, String), String> = HashMap::new();
+    let mut mappings = Vec::with_capacity(headers.len());
+
+    for (header, path) in headers.iter().zip(paths) {
+        let text = String::from_utf8_lossy(header.as_bytes()).into_owned();
+        let sq_lines: HashMap<&str, &str> = text
+            .lines()
+            .filter(|line| line.starts_with("@SQ\t"))
+            .filter_map(|line| Some((header_field(line, "SN")?, line)))
+            .collect();
+        let mut tid_map = Vec::with_capacity(header.target_count() as usize);
+        for tid in 0..header.target_count() {
+            let name = header.tid2name(tid).to_vec();
+            let len = header.target_len(tid).unwrap_or(0);
+            let merged = match target_index.get(&name) {
+                Some(&i) => {
+                    if targets[i].1 != len {
+                        bail!(
+                            "{}: reference {} has length {} but {} in an earlier input",
+                            path.display(),
+                            String::from_utf8_lossy(&name),
+                            len,
+                            targets[i].1
+                        );
+                    }
+                    i
+                }
+                None => {
+                    target_index.insert(name.clone(), targets.len());
+                    let line = sq_lines
+                        .get(String::from_utf8_lossy(&name).as_ref())
+                        .map(|line| line.to_string());
+                    targets.push((name, len, line));
+                    targets.len() - 1
+                }
+            };
+            if combine == Combine::Merge && tid_map.last().is_some_and(|&prev| prev > merged as i32)
+            {
+                bail!(
+                    "{}: reference order conflicts with earlier inputs; cannot merge",
+                    path.display()
+                );
+            }
+            tid_map.push(merged as i32);
+        }
+
+        // This input's @RG/@PG IDs that clash with a different earlier line.
+        let renames = IdRenames {
+            read_groups: clashing_ids(&text, "@RG", &ids, path),
+            programs: clashing_ids(&text, "@PG", &ids, path),
+        };
+
+        for line in text.lines() {
+            let renamed;
+            let line = if line.starts_with("@RG") && !renames.read_groups.is_empty() {
+                renamed = rename_ids(line, &renames.read_groups, &["ID:"]);
+                renamed.as_str()
+            } else if line.starts_with("@PG") && !renames.programs.is_empty() {
+                renamed = rename_ids(line, &renames.programs, &["ID:", "PP:"]);
+                renamed.as_str()
+            } else {
+                line
+            };
+            let kind = line.get(..3).unwrap_or("");
+            match kind {
+                "@HD" => {
+                    hd_line.get_or_insert_with(|| line.to_string());
+                }
+                "@SQ" => {}
+                "@RG" | "@PG" => {
+                    let id = header_field(line, "ID").unwrap_or("").to_string();
+                    ids.entry((kind.to_string(), id)).or_insert_with(|| {
+                        other_lines.push(line.to_string());
+                        line.to_string()
+                    });
+                }
+                _ if !line.is_empty() && !other_lines.iter().any(|l| l == line) => {
+                    other_lines.push(line.to_string());
+                }
+                _ => {}
+            }
+        }
+        mappings.push((tid_map, renames));
+    }
+
+    let sort_order = match combine {
+        Combine::Concat => "unsorted",
+        Combine::Merge => "coordinate",
+    };
+    let mut text = String::new();
+    let hd = hd_line.unwrap_or_else(|| "@HD\tVN:1.6".to_string());
+    let hd_fields: Vec<&str> = hd.split('\t').filter(|f| !f.starts_with("SO:")).collect();
+    text.push_str(&hd_fields.join("\t"));
+    text.push_str(&format!("\tSO:{sort_order}\n"));
+    for (name, len, line) in &targets {
+        match line {
+            Some(line) => text.push_str(line),
+            None => text.push_str(&format!(
+                "@SQ\tSN:{}\tLN:{}",
+                String::from_utf8_lossy(name),
+                len
+            )),
+        }
+        text.push('\n');
+    }
+    for line in &other_lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    Ok((bam::HeaderView::from_bytes(text.as_bytes()), mappings))
+}
+
+/// Value of the `tag` field of a header line.
+fn header_field<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.split('\t')
+        .find_map(|f| f.strip_prefix(tag)?.strip_prefix(':'))
+}
+
+/// New IDs for the `kind` (`@RG` or `@PG`) lines of `text` whose ID is
+/// already taken by a different line: `ID.1`, `ID.2`, ...
+fn clashing_ids(
+    text: &str,
+    kind: &str,
+    ids: &HashMap<(String, String), String>,
+    path: &Path,
+) -> HashMap<String, String> {
+    let taken = |id: &str| ids.contains_key(&(kind.to_string(), id.to_string()));
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for line in text.lines().filter(|line| line.starts_with(kind)) {
+        let id = header_field(line, "ID").unwrap_or("");
+        if ids
+            .get(&(kind.to_string(), id.to_string()))
+            .is_none_or(|existing| existing == line)
+        {
+            continue;
+        }
+        let new_id = (1..)
+            .map(|n| format!("{id}.{n}"))
+            .find(|candidate| !taken(candidate) && !renames.values().any(|v| v == candidate))
+            .unwrap();
+        warn!(
+            "{}: {kind} ID {id} differs from an earlier input, renamed to {new_id}",
+            path.display()
+        );
+        renames.insert(id.to_string(), new_id);
+    }
+    renames
+}
+
+/// `line` with the ID-valued fields `tags` (e.g. `PP:`) renamed per
+/// `renames`.
+fn rename_ids(line: &str, renames: &HashMap<String, String>, tags: &[&str]) -> String {
+    line.split('\t')
+        .map(|field| {
+            for tag in tags {
+                if let Some(new) = field.strip_prefix(tag).and_then(|id| renames.get(id)) {
+                    return format!("{tag}{new}");
+                }
+            }
+            field.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
@@ -1,15 +1,53 @@
+use std::collections::BTreeSet;
 use std::ffi::c_void;
-use std::sync::Once;
-
-use anyhow::{Result, anyhow};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Once};
+
+use anyhow::{Context, Result, anyhow};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
 use rust_htslib::bam;
 use rust_htslib::bam::record::{Aux, Cigar};
 
+use log::{debug, warn};
 use v8::{self, Global};
 
+pub mod barcode;
+pub mod check;
+mod clone;
+pub mod console;
+mod dedup;
+pub mod depth;
+pub mod emit;
+pub mod fastq;
+pub mod header_edit;
+pub mod inputs;
+mod longread;
+pub mod native;
+mod read_group;
+pub mod record;
+pub mod regions;
+pub mod sample;
+pub mod snapshot;
+pub mod stats;
+pub mod sv;
+mod typed;
+pub mod vcf;
+pub mod window;
+
+pub use record::{AlignmentRecord, HtslibAlignment};
+pub use sample::QnameSampler;
+pub use snapshot::CodeCache;
+
+/// TypeScript declarations for the scripting API, for editor support.
+pub const TYPE_DECLARATIONS: &str = include_str!("../v8bam.d.ts");
+
 static INIT_V8: Once = Once::new();
 
-fn init_v8_once() {
+pub(crate) fn init_v8_once() {
     INIT_V8.call_once(|| {
         let platform = v8::new_default_platform(0, false).make_shared();
         v8::V8::initialize_platform(platform);
@@ -17,131 +55,550 @@ fn init_v8_once() {
     });
 }
 
-/// Engine that owns a V8 isolate, context, compiled filter function,
-/// and a reusable `aln` object.
-pub struct JsBamFilterEngine {
+/// Options for constructing an engine ([`JsBamFilterEngine`],
+/// [`fastq::JsFastqFilterEngine`]).
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    /// Startup snapshot blob from [`snapshot::create_startup_snapshot`].
+    /// When set, the helper globals and object templates are deserialized
+    /// instead of being built from scratch.
+    pub snapshot: Option<Vec<u8>>,
+    /// On-disk cache of compiled filter code.
+    pub code_cache: Option<CodeCache>,
+    /// What to do when a script reads a property the scripted object does
+    /// not have.
+    pub unknown_properties: UnknownPropertyMode,
+    /// Maximum number of `console.*`/`print` messages a script may emit.
+    pub log_limit: Option<u64>,
+    /// Named region sets, available to scripts as `regions.<name>`.
+    pub regions: Vec<(String, Arc<regions::RegionSet>)>,
+    /// Sources of `aln.umi`/`aln.cellBarcode` and the `barcodes` whitelist.
+    pub barcodes: barcode::BarcodeOptions,
+}
+
+/// Handling of reads of unknown properties on the scripted object (e.g. a
+/// typo like `aln.mapQ`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownPropertyMode {
+    /// Plain JS semantics: the read evaluates to `undefined`.
+    #[default]
+    Ignore,
+    /// Log a warning the first time each unknown name is read.
+    Warn,
+    /// Throw a `TypeError` from the read.
+    Error,
+}
+
+/// Unknown property names seen so far, stored in an isolate slot.
+struct UnknownProperties {
+    /// Name of the scripted object (`aln`, `read`, ...) for messages.
+    object: &'static str,
+    seen: BTreeSet<String>,
+}
+
+/// Names of the input files and the input of the current record, stored in
+/// an isolate slot for `aln.sourceFile`/`aln.sourceIndex`.
+#[derive(Default)]
+struct InputSources {
+    names: Vec<String>,
+    current: usize,
+}
+
+/// Builds the ObjectTemplate for the object passed to `filter()`.
+pub(crate) type TemplateBuilder = for<'s, 'a, 'b, 'c> fn(
+    &'a mut v8::ContextScope<'s, 'b, v8::HandleScope<'c>>,
+) -> v8::Local<'s, v8::ObjectTemplate>;
+
+/// Describes the object a [`ScriptRuntime`] passes to `filter()`.
+pub(crate) struct ScriptObject {
+    /// Parameter name of `filter(<param>)`, e.g. `aln`.
+    pub(crate) param: &'static str,
+    /// Further parameters, passed with [`ScriptRuntime::call_with`] and
+    /// `undefined` otherwise.
+    pub(crate) extra_params: &'static [&'static str],
+    pub(crate) make_template: TemplateBuilder,
+    /// Index of the template in the startup snapshot's isolate data.
+    pub(crate) snapshot_index: usize,
+}
+
+/// The machinery shared by all engines: an isolate, a context with the Rust
+/// helpers, the compiled `filter(<param>)` function and the template of the
+/// object passed to it. Each call gets a new object whose internal fields
+/// point at the current record, so a reference kept from an earlier call
+/// never sees a later record.
+pub(crate) struct ScriptRuntime {
     isolate: v8::OwnedIsolate,
     context: Global<v8::Context>,
     filter_fn: Global<v8::Function>,
-    aln_obj: Global<v8::Object>,
+    template: Global<v8::ObjectTemplate>,
 }
 
-impl JsBamFilterEngine {
-    /// Create a new engine with a JS filter expression or body.
-    ///
-    /// `expr` can be:
-    ///   "aln.mapq > 10 && aln.qname.startsWith('q23')"
-    /// or:
-    ///   "return aln.mapq > 10 && aln.qname.startsWith('q23');"
-    pub fn new(expr: &str) -> Result<Self> {
+impl ScriptRuntime {
+    pub(crate) fn new(expr: &str, object: &ScriptObject, opts: EngineOptions) -> Result<Self> {
         init_v8_once();
 
-        let mut isolate = v8::Isolate::new(Default::default());
+        let from_snapshot = opts.snapshot.is_some();
+        let mut isolate = match opts.snapshot {
+            Some(blob) => v8::Isolate::new(
+                v8::CreateParams::default()
+                    .snapshot_blob(blob)
+                    .external_references(snapshot::external_references()),
+            ),
+            None => v8::Isolate::new(Default::default()),
+        };
+        isolate.set_slot(UnknownProperties {
+            object: object.param,
+            seen: BTreeSet::new(),
+        });
+        isolate.set_slot(console::ScriptLog::new(opts.log_limit));
+        isolate.set_slot(dedup::SeenKeys::default());
+        isolate.set_slot(opts.barcodes.clone());
+        isolate.set_slot(regions::RegionSets(
+            opts.regions.iter().map(|(_, set)| set.clone()).collect(),
+        ));
 
         // Create locals first, then convert to globals
-        let (ctx_global, filter_global, aln_obj_global) = {
+        let (ctx_global, filter_global, tmpl_global) = {
             // Pinned handle scope
             v8::scope!(let hs, &mut isolate);
 
-            // Context
+            // Context (helpers are already present when restored from a snapshot)
             let context = v8::Context::new(hs, Default::default());
             v8::scope_with_context!(let scope, hs, context);
 
-            // Build full JS source: define `filter(aln)` and helper function(s)
-            let source = make_filter_source(expr);
-            let filter_fn = compile_filter_function(scope, context, &source)?;
-
-            // Make aln template (lazy accessors mapq, qname, flag, pos)
-            let aln_tmpl = make_aln_template(scope);
-
-            // Create a single reusable aln object from the template
-            let aln_obj = aln_tmpl
-                .new_instance(scope)
-                .ok_or_else(|| anyhow!("failed to create aln object"))?;
+            // Build full JS source: define `filter(<param>)` and helper function(s)
+            let params = [&[object.param], object.extra_params].concat().join(", ");
+            let source = make_filter_source(expr, &params);
+            let filter_fn =
+                compile_filter_function(scope, context, &source, opts.code_cache.as_ref())?;
+
+            // Make the object template (lazy accessors, e.g. mapq, qname, flag, pos).
+            // The snapshot template has no interceptor, so build a fresh one
+            // when unknown properties must be reported.
+            let tmpl = if from_snapshot && opts.unknown_properties == UnknownPropertyMode::Ignore {
+                scope
+                    .get_isolate_data_from_snapshot_once::<v8::ObjectTemplate>(
+                        object.snapshot_index,
+                    )
+                    .ok_or_else(|| anyhow!("{} template missing from snapshot", object.param))?
+            } else {
+                (object.make_template)(scope)
+            };
+            if opts.unknown_properties != UnknownPropertyMode::Ignore {
+                install_unknown_property_interceptor(
+                    scope,
+                    tmpl,
+                    opts.unknown_properties == UnknownPropertyMode::Error,
+                );
+            }
 
             // Install global Rust helpers into the context (e.g. hasFlag)
-            install_rust_helpers(scope, context);
+            if !from_snapshot {
+                install_rust_helpers(scope, context);
+            }
+            let region_names: Vec<&str> = opts.regions.iter().map(|(n, _)| n.as_str()).collect();
+            let global = context.global(scope);
+            regions::install(scope, global, &region_names);
+            barcode::install(scope, global, opts.barcodes.whitelist.as_deref());
 
             // Convert to globals
             let ctx_global = Global::new(scope, context);
             let filter_global = Global::new(scope, filter_fn);
-            let aln_obj_global = Global::new(scope, aln_obj);
+            let tmpl_global = Global::new(scope, tmpl);
 
-            (ctx_global, filter_global, aln_obj_global)
+            (ctx_global, filter_global, tmpl_global)
         };
 
         Ok(Self {
             isolate,
             context: ctx_global,
             filter_fn: filter_global,
-            aln_obj: aln_obj_global,
+            template: tmpl_global,
         })
     }
 
-    /// Run the JS filter on a single BAM record.
-    pub fn record_passes(&mut self, rec: &bam::Record, header: &bam::HeaderView) -> Result<bool> {
+    /// Store `fields` in a new object's internal fields, call `filter(obj)`
+    /// and hand the result to `f`.
+    ///
+    /// The pointers are only valid during this call and are cleared before
+    /// it returns, so accessors on an object a script kept around throw
+    /// instead of dereferencing them (see [`bound_pointer`]), during later
+    /// calls as well as outside any.
+    pub(crate) fn call<R>(
+        &mut self,
+        fields: &[*mut c_void],
+        f: impl FnOnce(&mut v8::PinScope, v8::Local<v8::Value>) -> R,
+    ) -> Result<R> {
+        self.call_with(fields, &[], f)
+    }
+
+    /// [`Self::call`], passing `extra` as the further parameters of
+    /// `filter()` (see [`ScriptObject::extra_params`]).
+    pub(crate) fn call_with<R>(
+        &mut self,
+        fields: &[*mut c_void],
+        extra: &[&dyn ExtraArg],
+        f: impl FnOnce(&mut v8::PinScope, v8::Local<v8::Value>) -> R,
+    ) -> Result<R> {
         v8::scope!(let hs, &mut self.isolate);
         let context = v8::Local::new(hs, &self.context);
         v8::scope_with_context!(let scope, hs, context);
 
         let filter_fn = v8::Local::new(scope, &self.filter_fn);
-        let aln_obj = v8::Local::new(scope, &self.aln_obj);
+        let tmpl = v8::Local::new(scope, &self.template);
+        let obj = tmpl
+            .new_instance(scope)
+            .ok_or_else(|| anyhow!("failed to create the filter() argument"))?;
 
-        let hdr_ptr = header as *const bam::HeaderView as *mut c_void;
-        aln_obj.set_aligned_pointer_in_internal_field(0, hdr_ptr);
-
-        // Store pointer to `bam::Record` in internal field 0.
-        // Lifetime: only valid during this call.
-        let ptr = rec as *const bam::Record as *mut c_void;
-        aln_obj.set_aligned_pointer_in_internal_field(1, ptr);
+        for (i, &ptr) in fields.iter().enumerate() {
+            obj.set_aligned_pointer_in_internal_field(i as i32, ptr);
+        }
 
         let undefined = v8::undefined(scope).into();
-        let args = [aln_obj.into()];
-        let result = filter_fn
-            .call(scope, undefined, &args)
-            .ok_or_else(|| anyhow!("filter() threw or returned empty"))?;
+        let mut args = [undefined; 4];
+        args[0] = obj.into();
+        for (i, arg) in extra.iter().enumerate() {
+            args[i + 1] = arg.build(scope);
+        }
+        let args = &args[..1 + extra.len()];
+        v8::tc_scope!(let tc, scope);
+        let out = match filter_fn.call(tc, undefined, args) {
+            Some(result) => Ok(f(tc, result)),
+            None => {
+                let (exception, message) = (tc.exception(), tc.message());
+                let msg = exception_message(tc, exception, message);
+                Err(anyhow!("filter() threw: {msg}"))
+            }
+        };
+
+        for i in 0..fields.len() {
+            obj.set_aligned_pointer_in_internal_field(i as i32, std::ptr::null_mut());
+        }
+        out
+    }
+
+    /// Compile `function <name>(<param>) { <expr> }`, call it once with
+    /// `arg` (passed through JSON) and return its result as JSON; `null`
+    /// when it returned `undefined` or `null`.
+    pub(crate) fn call_hook(
+        &mut self,
+        name: &str,
+        param: &str,
+        expr: &str,
+        arg: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        v8::scope!(let hs, &mut self.isolate);
+        let context = v8::Local::new(hs, &self.context);
+        v8::scope_with_context!(let scope, hs, context);
+        v8::tc_scope!(let tc, scope);
+
+        let source = format!("(function {name}({param}) {{\n{}\n}})", function_body(expr));
+        let code = v8::String::new(tc, &source)
+            .ok_or_else(|| anyhow!("failed to create JS source string"))?;
+        let func = v8::Script::compile(tc, code, None)
+            .and_then(|script| script.run(tc))
+            .and_then(|value| v8::Local::<v8::Function>::try_from(value).ok());
+        let Some(func) = func else {
+            let (exception, message) = (tc.exception(), tc.message());
+            let msg = exception_message(tc, exception, message);
+            return Err(anyhow!("failed to compile {name}(): {msg}"));
+        };
 
-        Ok(result.boolean_value(scope))
+        let arg = v8::String::new(tc, &arg.to_string()).unwrap();
+        let arg = v8::json::parse(tc, arg).ok_or_else(|| anyhow!("failed to pass {param}"))?;
+        let undefined = v8::undefined(tc).into();
+        let Some(result) = func.call(tc, undefined, &[arg]) else {
+            let (exception, message) = (tc.exception(), tc.message());
+            let msg = exception_message(tc, exception, message);
+            return Err(anyhow!("{name}() threw: {msg}"));
+        };
+        if result.is_null_or_undefined() {
+            return Ok(serde_json::Value::Null);
+        }
+        let json = v8::json::stringify(tc, result)
+            .ok_or_else(|| anyhow!("{name}() returned a value that is not JSON-serializable"))?;
+        serde_json::from_str(&json.to_rust_string_lossy(tc))
+            .with_context(|| format!("{name}() returned invalid JSON"))
+    }
+
+    /// Per-isolate state read by callbacks.
+    pub(crate) fn slot_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.isolate.get_slot_mut::<T>()
+    }
+
+    /// Bytes currently used on the V8 heap.
+    pub(crate) fn used_heap_size(&mut self) -> usize {
+        let mut hs = v8::HeapStatistics::default();
+        self.isolate.get_heap_statistics(&mut hs);
+        hs.used_heap_size()
+    }
+
+    /// Unknown property names read so far.
+    pub(crate) fn unknown_properties(&self) -> Vec<String> {
+        self.isolate
+            .get_slot::<UnknownProperties>()
+            .map(|u| u.seen.iter().cloned().collect())
+            .unwrap_or_default()
     }
 }
 
-/// Build the JS source that defines the filter.
-fn make_filter_source(user_expr: &str) -> String {
-    // Allow "and"/"or" as sugar
-    let mut expr = user_expr.to_string();
-    expr = expr.replace(" and ", " && ");
-    expr = expr.replace(" or ", " || ");
+/// A further argument to `filter()`, built in the scope of the call.
+pub(crate) trait ExtraArg {
+    fn build<'s>(&self, scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Value>;
+}
 
-    let body = if expr.contains("return") {
-        expr
-    } else {
-        format!("return {};", expr)
-    };
+pub(crate) const ALN_OBJECT: ScriptObject = ScriptObject {
+    param: "aln",
+    extra_params: &["window"],
+    make_template: make_aln_template,
+    snapshot_index: snapshot::ALN_TEMPLATE_INDEX,
+};
+
+/// Engine that owns a V8 isolate, context, compiled filter function,
+/// and the template of the `aln` object.
+pub struct JsBamFilterEngine {
+    rt: ScriptRuntime,
+}
+
+impl JsBamFilterEngine {
+    /// Create a new engine with a JS filter expression or body.
+    ///
+    /// `expr` can be:
+    ///   "aln.mapq > 10 && aln.qname.startsWith('q23')"
+    /// or:
+    ///   "return aln.mapq > 10 && aln.qname.startsWith('q23');"
+    pub fn new(expr: &str) -> Result<Self> {
+        Self::with_options(expr, EngineOptions::default())
+    }
+
+    /// Create a new engine, optionally starting from a snapshot and using
+    /// a code cache.
+    pub fn with_options(expr: &str, opts: EngineOptions) -> Result<Self> {
+        let mut rt = ScriptRuntime::new(expr, &ALN_OBJECT, opts)?;
+        rt.isolate.set_slot(InputSources::default());
+        rt.isolate.set_slot(read_group::ReadGroupCache::default());
+        rt.isolate.set_slot(typed::ByteViews::default());
+        rt.isolate.set_slot(window::WindowCopies::default());
+        rt.isolate.set_slot(depth::Coverage::default());
+        Ok(Self { rt })
+    }
+
+    /// Names of the input files, reported as `aln.sourceFile` for the
+    /// index given to [`Self::set_current_input`].
+    pub fn set_input_names(&mut self, names: Vec<String>) {
+        if let Some(sources) = self.rt.slot_mut::<InputSources>() {
+            sources.names = names;
+        }
+    }
+
+    /// Reparse the `@RG` lines behind `aln.sample`/`library`/`platform`
+    /// from `header`. Call it when switching to a new header, e.g. per
+    /// file: headers are otherwise told apart by address, and a new one can
+    /// reuse the memory of one that was dropped.
+    pub fn set_header(&mut self, header: &bam::HeaderView) {
+        if let Some(cache) = self.rt.slot_mut::<read_group::ReadGroupCache>() {
+            cache.reset(header);
+        }
+    }
+
+    /// Index of the input the next records come from (`aln.sourceIndex`).
+    #[inline]
+    pub fn set_current_input(&mut self, index: usize) {
+        if let Some(sources) = self.rt.slot_mut::<InputSources>() {
+            sources.current = index;
+        }
+    }
+
+    /// Run the JS filter on a single BAM record.
+    pub fn record_passes(&mut self, rec: &bam::Record, header: &bam::HeaderView) -> Result<bool> {
+        self.alignment_passes(&HtslibAlignment::new(rec, header))
+    }
+
+    /// Run the JS filter on any [`AlignmentRecord`], e.g. a
+    /// `record::NoodlesAlignment`.
+    pub fn alignment_passes(&mut self, aln: &dyn AlignmentRecord) -> Result<bool> {
+        self.call_filter(aln, |scope, result| result.boolean_value(scope))
+    }
+
+    /// Run the script on a single BAM record and serialize its return value
+    /// into `line` (see [`emit`] for the format). Returns false, leaving
+    /// `line` untouched, when the script returned `undefined`, `null` or
+    /// `false`.
+    pub fn record_emit(
+        &mut self,
+        rec: &bam::Record,
+        header: &bam::HeaderView,
+        line: &mut String,
+    ) -> Result<bool> {
+        self.alignment_emit(&HtslibAlignment::new(rec, header), line)
+    }
+
+    /// [`Self::record_emit`] for any [`AlignmentRecord`].
+    pub fn alignment_emit(&mut self, aln: &dyn AlignmentRecord, line: &mut String) -> Result<bool> {
+        self.call_filter(aln, |scope, result| {
+            emit::value_to_line(scope, result, line)
+        })?
+    }
+
+    /// Count `rec` in the running coverage reported by `depth(chrom, pos)`.
+    /// Call it for each input record, in coordinate order, before filtering
+    /// it; fails when a record starts before the previous one.
+    pub fn add_to_depth(&mut self, rec: &bam::Record, header: &bam::HeaderView) -> Result<()> {
+        match self.rt.slot_mut::<depth::Coverage>() {
+            Some(coverage) => coverage.add(rec, header),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads covering the start of `rec`, the last record passed to
+    /// [`Self::add_to_depth`].
+    pub fn depth_at(&mut self, rec: &bam::Record) -> usize {
+        self.rt
+            .slot_mut::<depth::Coverage>()
+            .map_or(0, |coverage| coverage.depth_at(rec))
+    }
+
+    /// Run the JS filter on the current record of `window` (copied into
+    /// `rec` by [`window::RecordWindow::advance`]), passing its neighbours
+    /// as the `window` argument of `filter(aln, window)`.
+    pub fn record_passes_in(
+        &mut self,
+        rec: &bam::Record,
+        header: &bam::HeaderView,
+        window: &window::RecordWindow,
+    ) -> Result<bool> {
+        let arg = window::WindowArg { window, header };
+        self.call_filter_with(
+            &HtslibAlignment::new(rec, header),
+            &[&arg],
+            |scope, result| result.boolean_value(scope),
+        )
+    }
+
+    /// [`Self::record_emit`] with a `window` argument, as for
+    /// [`Self::record_passes_in`].
+    pub fn record_emit_in(
+        &mut self,
+        rec: &bam::Record,
+        header: &bam::HeaderView,
+        window: &window::RecordWindow,
+        line: &mut String,
+    ) -> Result<bool> {
+        let arg = window::WindowArg { window, header };
+        self.call_filter_with(
+            &HtslibAlignment::new(rec, header),
+            &[&arg],
+            |scope, result| emit::value_to_line(scope, result, line),
+        )?
+    }
+
+    /// Run a `header(hdr)` hook with the function body or expression
+    /// `expr` on `header` and parse the edits it returns (see
+    /// [`header_edit`]).
+    pub fn header_edits(
+        &mut self,
+        expr: &str,
+        header: &bam::HeaderView,
+    ) -> Result<header_edit::HeaderEdits> {
+        let hdr = header_edit::header_to_json(header);
+        let edits = self.rt.call_hook("header", "hdr", expr, &hdr)?;
+        header_edit::HeaderEdits::from_json(&edits)
+    }
+
+    /// Bind `aln` to the `aln` object, call `filter(aln)` and hand the
+    /// result to `f`.
+    fn call_filter<R>(
+        &mut self,
+        aln: &dyn AlignmentRecord,
+        f: impl FnOnce(&mut v8::PinScope, v8::Local<v8::Value>) -> R,
+    ) -> Result<R> {
+        self.call_filter_with(aln, &[], f)
+    }
+
+    fn call_filter_with<R>(
+        &mut self,
+        aln: &dyn AlignmentRecord,
+        extra: &[&dyn ExtraArg],
+        f: impl FnOnce(&mut v8::PinScope, v8::Local<v8::Value>) -> R,
+    ) -> Result<R> {
+        // 0: header view, 1: record (null unless htslib-backed),
+        // 2: the &dyn AlignmentRecord
+        let (hdr_ptr, ptr) = match aln.htslib() {
+            Some((rec, header)) => (
+                header as *const bam::HeaderView as *mut c_void,
+                rec as *const bam::Record as *mut c_void,
+            ),
+            None => (std::ptr::null_mut(), std::ptr::null_mut()),
+        };
+        let aln_ptr = &aln as *const &dyn AlignmentRecord as *mut c_void;
+        self.rt.call_with(&[hdr_ptr, ptr, aln_ptr], extra, f)
+    }
+
+    /// Bytes currently used on the V8 heap.
+    pub fn used_heap_size(&mut self) -> usize {
+        self.rt.used_heap_size()
+    }
+
+    /// Names of unknown `aln` properties read so far. Only tracked when the
+    /// engine was built with [`UnknownPropertyMode::Warn`] or
+    /// [`UnknownPropertyMode::Error`].
+    pub fn unknown_properties(&self) -> Vec<String> {
+        self.rt.unknown_properties()
+    }
+}
+
+/// Build the JS source that defines the filter.
+fn make_filter_source(user_expr: &str, params: &str) -> String {
+    let body = function_body(user_expr);
 
     // We also expose Rust helpers (installed separately as globals),
     // e.g. hasFlag(flag, mask).
     //
-    // This string only needs to define filter(aln).
+    // This string only needs to define filter(aln) (or filter(read), ...).
     format!(
         r#"
-        function filter(aln) {{
+        function filter({params}) {{
             {body}
         }}
         "#,
+        params = params,
         body = body
     )
 }
 
-/// Compile `filter(aln)` and return the function handle.
+/// Turn a user expression or function body into a function body.
+fn function_body(user_expr: &str) -> String {
+    let expr = expand_sugar(user_expr);
+    if expr.contains("return") {
+        expr
+    } else {
+        format!("return {};", expr)
+    }
+}
+
+/// Allow "and"/"or" as sugar for `&&`/`||`.
+pub(crate) fn expand_sugar(user_expr: &str) -> String {
+    user_expr.replace(" and ", " && ").replace(" or ", " || ")
+}
+
+/// Compile `filter(<param>)` and return the function handle.
+///
+/// With a code cache, previously compiled code for the same source is
+/// consumed, and missing or rejected entries are (re)generated.
 fn compile_filter_function<'s>(
     scope: &mut v8::ContextScope<'s, '_, v8::HandleScope<'_>>,
     context: v8::Local<'s, v8::Context>,
     source: &str,
+    code_cache: Option<&CodeCache>,
 ) -> Result<v8::Local<'s, v8::Function>> {
     let code = v8::String::new(scope, source)
         .ok_or_else(|| anyhow!("failed to create JS source string"))?;
-    let script =
-        v8::Script::compile(scope, code, None).ok_or_else(|| anyhow!("failed to compile JS"))?;
+    let script = match code_cache {
+        None => {
+            v8::Script::compile(scope, code, None).ok_or_else(|| anyhow!("failed to compile JS"))?
+        }
+        Some(cache) => compile_with_code_cache(scope, code, source, cache)?,
+    };
     script
         .run(scope)
         .ok_or_else(|| anyhow!("failed to run JS"))?;
@@ -156,14 +613,63 @@ fn compile_filter_function<'s>(
     Ok(func)
 }
 
+fn compile_with_code_cache<'s>(
+    scope: &mut v8::ContextScope<'s, '_, v8::HandleScope<'_>>,
+    code: v8::Local<'s, v8::String>,
+    source: &str,
+    cache: &CodeCache,
+) -> Result<v8::Local<'s, v8::Script>> {
+    use v8::script_compiler::{self, CachedData, CompileOptions, NoCacheReason};
+
+    let cached = cache.load(source);
+    let (script, needs_store) = match &cached {
+        Some(data) => {
+            let mut src =
+                script_compiler::Source::new_with_cached_data(code, None, CachedData::new(data));
+            let script = script_compiler::compile(
+                scope,
+                &mut src,
+                CompileOptions::ConsumeCodeCache,
+                NoCacheReason::NoReason,
+            )
+            .ok_or_else(|| anyhow!("failed to compile JS"))?;
+            let rejected = src.get_cached_data().is_some_and(|c| c.rejected());
+            if rejected {
+                debug!("code cache rejected, regenerating");
+                cache.remove(source);
+            }
+            (script, rejected)
+        }
+        None => {
+            // Compile eagerly so the body of filter() ends up in the cache too.
+            let mut src = script_compiler::Source::new(code, None);
+            let script = script_compiler::compile(
+                scope,
+                &mut src,
+                CompileOptions::EagerCompile,
+                NoCacheReason::NoReason,
+            )
+            .ok_or_else(|| anyhow!("failed to compile JS"))?;
+            (script, true)
+        }
+    };
+
+    if needs_store && let Some(data) = script.get_unbound_script(scope).create_code_cache() {
+        if let Err(e) = cache.store(source, &data) {
+            warn!("failed to write code cache: {e:#}");
+        }
+    }
+    Ok(script)
+}
+
 /// Create an ObjectTemplate for `aln` with lazy accessors:
 /// for chrom, mapq, qname, flag, pos, start, end, aux(tag), etc
-fn make_aln_template<'s>(
+pub(crate) fn make_aln_template<'s>(
     scope: &mut v8::ContextScope<'s, '_, v8::HandleScope<'_>>,
 ) -> v8::Local<'s, v8::ObjectTemplate> {
     let tmpl = v8::ObjectTemplate::new(scope);
-    // 0: header view, 1: record
-    tmpl.set_internal_field_count(2);
+    // 0: header view, 1: record, 2: &dyn AlignmentRecord
+    tmpl.set_internal_field_count(3);
 
     let mapq = v8::String::new(scope, "mapq").unwrap();
     tmpl.set_accessor(mapq.into(), aln_mapq_getter);
@@ -188,17 +694,127 @@ fn make_aln_template<'s>(
     let cigar = v8::String::new(scope, "cigar").unwrap();
     tmpl.set_accessor(cigar.into(), aln_cigar_getter);
 
+    let source_file = v8::String::new(scope, "sourceFile").unwrap();
+    tmpl.set_accessor(source_file.into(), aln_source_file_getter);
+
+    let source_index = v8::String::new(scope, "sourceIndex").unwrap();
+    tmpl.set_accessor(source_index.into(), aln_source_index_getter);
+
+    let read_group = v8::String::new(scope, "readGroup").unwrap();
+    tmpl.set_accessor(read_group.into(), read_group::aln_read_group_getter);
+
+    let sample = v8::String::new(scope, "sample").unwrap();
+    tmpl.set_accessor(sample.into(), read_group::aln_sample_getter);
+
+    let library = v8::String::new(scope, "library").unwrap();
+    tmpl.set_accessor(library.into(), read_group::aln_library_getter);
+
+    let platform = v8::String::new(scope, "platform").unwrap();
+    tmpl.set_accessor(platform.into(), read_group::aln_platform_getter);
+
+    let unclipped_start = v8::String::new(scope, "unclippedStart").unwrap();
+    tmpl.set_accessor(unclipped_start.into(), dedup::aln_unclipped_start_getter);
+
+    let unclipped_end = v8::String::new(scope, "unclippedEnd").unwrap();
+    tmpl.set_accessor(unclipped_end.into(), dedup::aln_unclipped_end_getter);
+
+    let five_prime = v8::String::new(scope, "fivePrime").unwrap();
+    tmpl.set_accessor(five_prime.into(), dedup::aln_five_prime_getter);
+
+    let umi = v8::String::new(scope, "umi").unwrap();
+    tmpl.set_accessor(umi.into(), barcode::aln_umi_getter);
+
+    let cell_barcode = v8::String::new(scope, "cellBarcode").unwrap();
+    tmpl.set_accessor(cell_barcode.into(), barcode::aln_cell_barcode_getter);
+
+    let seq_bytes = v8::String::new(scope, "seqBytes").unwrap();
+    tmpl.set_accessor(seq_bytes.into(), typed::aln_seq_bytes_getter);
+
+    let qual_bytes = v8::String::new(scope, "qualBytes").unwrap();
+    tmpl.set_accessor(qual_bytes.into(), typed::aln_qual_bytes_getter);
+
+    let is_split = v8::String::new(scope, "isSplit").unwrap();
+    tmpl.set_accessor(is_split.into(), sv::aln_is_split_getter);
+
+    let clip_side = v8::String::new(scope, "clipSide").unwrap();
+    tmpl.set_accessor(clip_side.into(), sv::aln_clip_side_getter);
+
+    let mate_end = v8::String::new(scope, "mateEndEstimate").unwrap();
+    tmpl.set_accessor(mate_end.into(), sv::aln_mate_end_estimate_getter);
+
+    for (name, _) in FLAG_PROPERTIES {
+        let name = v8::String::new(scope, name).unwrap();
+        tmpl.set_accessor(name.into(), aln_flag_bit_getter);
+    }
+
     // Add aux(tag) method
     let aux_fn = v8::FunctionTemplate::new(scope, aln_aux_method);
     let aux_name = v8::String::new(scope, "aux").unwrap();
     tmpl.set(aux_name.into(), aux_fn.into());
 
+    let overlaps_fn = v8::FunctionTemplate::new(scope, regions::aln_overlaps_method);
+    let overlaps_name = v8::String::new(scope, "overlaps").unwrap();
+    tmpl.set(overlaps_name.into(), overlaps_fn.into());
+
+    let sa_fn = v8::FunctionTemplate::new(scope, longread::aln_supplementary_alignments_method);
+    let sa_name = v8::String::new(scope, "supplementaryAlignments").unwrap();
+    tmpl.set(sa_name.into(), sa_fn.into());
+
+    let mods_fn = v8::FunctionTemplate::new(scope, longread::aln_modifications_method);
+    let mods_name = v8::String::new(scope, "modifications").unwrap();
+    tmpl.set(mods_name.into(), mods_fn.into());
+
+    let clone_fn = v8::FunctionTemplate::new(scope, clone::aln_clone_method);
+    let clone_name = v8::String::new(scope, "clone").unwrap();
+    tmpl.set(clone_name.into(), clone_fn.into());
+
+    let discordant_fn = v8::FunctionTemplate::new(scope, sv::aln_is_discordant_method);
+    let discordant_name = v8::String::new(scope, "isDiscordant").unwrap();
+    tmpl.set(discordant_name.into(), discordant_fn.into());
+
     tmpl
 }
 
+/// Add a non-masking named-property interceptor to an object template. It is
+/// only consulted for names not found on the object or its prototype chain,
+/// so the regular accessors are unaffected.
+fn install_unknown_property_interceptor(
+    scope: &mut v8::ContextScope<'_, '_, v8::HandleScope<'_>>,
+    tmpl: v8::Local<v8::ObjectTemplate>,
+    strict: bool,
+) {
+    let data = v8::Boolean::new(scope, strict);
+    tmpl.set_named_property_handler(
+        v8::NamedPropertyHandlerConfiguration::new()
+            .getter(unknown_property_getter)
+            .data(data.into())
+            .flags(
+                v8::PropertyHandlerFlags::NON_MASKING
+                    | v8::PropertyHandlerFlags::ONLY_INTERCEPT_STRINGS,
+            ),
+    );
+}
+
+/// Text of a caught exception, including the source line when V8 provides
+/// one.
+pub(crate) fn exception_message(
+    scope: &mut v8::PinScope,
+    exception: Option<v8::Local<v8::Value>>,
+    message: Option<v8::Local<v8::Message>>,
+) -> String {
+    let Some(exception) = exception else {
+        return "no exception (execution terminated?)".to_string();
+    };
+    let mut msg = exception.to_rust_string_lossy(scope);
+    if let Some(line) = message.and_then(|m| m.get_line_number(scope)) {
+        msg.push_str(&format!(" (line {line})"));
+    }
+    msg
+}
+
 /// Install global helper functions implemented in Rust.
 /// Example: `hasFlag(flag, mask)` → boolean.
-fn install_rust_helpers(
+pub(crate) fn install_rust_helpers(
     scope: &mut v8::ContextScope<'_, '_, v8::HandleScope<'_>>,
     context: v8::Local<v8::Context>,
 ) {
@@ -208,44 +824,103 @@ fn install_rust_helpers(
     let name = v8::String::new(scope, "hasFlag").unwrap();
     let func = v8::Function::new(scope, has_flag_callback).unwrap();
     global.set(scope, name.into(), func.into());
+
+    // seen(key) => whether key was passed before (Rust hash set)
+    // seen.clear() => forget all keys
+    let name = v8::String::new(scope, "seen").unwrap();
+    let func = v8::Function::new(scope, dedup::seen_callback).unwrap();
+    let clear_name = v8::String::new(scope, "clear").unwrap();
+    let clear = v8::Function::new(scope, dedup::seen_clear_callback).unwrap();
+    func.set(scope, clear_name.into(), clear.into());
+    global.set(scope, name.into(), func.into());
+
+    // depth(chrom, pos) => running coverage (see depth.rs)
+    let name = v8::String::new(scope, "depth").unwrap();
+    let func = v8::Function::new(scope, depth::depth_callback).unwrap();
+    global.set(scope, name.into(), func.into());
+
+    // hamming(a, b) => number of differing characters
+    let name = v8::String::new(scope, "hamming").unwrap();
+    let func = v8::Function::new(scope, barcode::hamming_callback).unwrap();
+    global.set(scope, name.into(), func.into());
+
+    // console.log/warn/error/... and print(...) => log crate
+    console::install(scope, global);
 }
 
+/// Pointer stored in internal field `index` of `obj`. Throws a `TypeError`
+/// and returns `None` once it has been cleared, i.e. when a script kept the
+/// object and uses it after the `filter()` call it was passed to.
 #[inline(always)]
-fn record_from_obj<'s>(obj: v8::Local<v8::Object>) -> &'s bam::Record {
-    let ptr = unsafe { obj.get_aligned_pointer_from_internal_field(1) } as *const bam::Record;
-    unsafe { &*ptr }
+pub(crate) fn bound_pointer<T>(
+    scope: &mut v8::PinScope,
+    obj: v8::Local<v8::Object>,
+    index: i32,
+) -> Option<*const T> {
+    let ptr = unsafe { obj.get_aligned_pointer_from_internal_field(index) } as *const T;
+    if ptr.is_null() {
+        let object = scope
+            .get_slot::<UnknownProperties>()
+            .map_or("record", |u| u.object);
+        throw_type_error(
+            scope,
+            &format!("{object} is detached: it is only valid during the call it was passed to"),
+        );
+        return None;
+    }
+    Some(ptr)
+}
+
+pub(crate) fn throw_type_error(scope: &mut v8::PinScope, msg: &str) {
+    let msg = v8::String::new(scope, msg).unwrap();
+    let exc = v8::Exception::type_error(scope, msg);
+    scope.throw_exception(exc);
+}
+
+/// Throw a plain `Error`, for malformed record data.
+pub(crate) fn throw_error(scope: &mut v8::PinScope, msg: &str) {
+    let msg = v8::String::new(scope, msg).unwrap();
+    let exc = v8::Exception::error(scope, msg);
+    scope.throw_exception(exc);
 }
 
 #[inline(always)]
-fn header_from_obj<'s>(obj: v8::Local<v8::Object>) -> &'s bam::HeaderView {
-    let ptr = unsafe { obj.get_aligned_pointer_from_internal_field(0) } as *const bam::HeaderView;
-    unsafe { &*ptr as &bam::HeaderView }
+pub(crate) fn alignment_from_obj<'s>(
+    scope: &mut v8::PinScope,
+    obj: v8::Local<v8::Object>,
+) -> Option<&'s dyn AlignmentRecord> {
+    let ptr = bound_pointer::<&'s dyn AlignmentRecord>(scope, obj, 2)?;
+    Some(unsafe { *ptr })
 }
 
 // ========== Accessors: aln.mapq, aln.qname, aln.flag, aln.pos ==========
 
 #[allow(clippy::needless_pass_by_value)]
-fn aln_mapq_getter(
+pub(crate) fn aln_mapq_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
     let v = v8::Integer::new_from_unsigned(scope, rec.mapq() as u32);
     rv.set(v.into());
 }
 
 #[allow(clippy::needless_pass_by_value)]
-fn aln_qname_getter(
+pub(crate) fn aln_qname_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
     let qname_bytes = rec.qname();
     let qname = std::str::from_utf8(qname_bytes).unwrap_or("");
     let s = v8::String::new(scope, qname).unwrap();
@@ -253,72 +928,165 @@ fn aln_qname_getter(
 }
 
 #[allow(clippy::needless_pass_by_value)]
-fn aln_flag_getter(
+pub(crate) fn aln_flag_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
     let flags = rec.flags() as u32;
     let v = v8::Integer::new_from_unsigned(scope, flags);
     rv.set(v.into());
 }
 
-fn aln_chrom_getter(
+/// Boolean `aln` properties for single SAM flag bits.
+pub(crate) const FLAG_PROPERTIES: [(&str, u16); 12] = [
+    ("paired", 0x1),
+    ("properPair", 0x2),
+    ("unmapped", 0x4),
+    ("mateUnmapped", 0x8),
+    ("reverse", 0x10),
+    ("mateReverse", 0x20),
+    ("read1", 0x40),
+    ("read2", 0x80),
+    ("secondary", 0x100),
+    ("qcFail", 0x200),
+    ("duplicate", 0x400),
+    ("supplementary", 0x800),
+];
+
+/// Getter shared by the [`FLAG_PROPERTIES`], which looks up the bit by
+/// property name.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_flag_bit_getter(
+    scope: &mut v8::PinScope,
+    name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let this = args.this();
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    let name = name.to_rust_string_lossy(scope);
+    let Some((_, bit)) = FLAG_PROPERTIES.iter().find(|(n, _)| *n == name) else {
+        return;
+    };
+    let v = v8::Boolean::new(scope, rec.flags() & bit != 0);
+    rv.set(v.into());
+}
+
+pub(crate) fn aln_chrom_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
-    let header = header_from_obj(this);
-    let tid = rec.tid() as u32;
-    let chrom = header.tid2name(tid);
-    let chrom = std::str::from_utf8(chrom).unwrap_or("");
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    // Unmapped reads have no reference; report "*" as SAM does.
+    let chrom = match rec.chrom() {
+        Some(name) => std::str::from_utf8(name).unwrap_or(""),
+        None => "*",
+    };
     let s = v8::String::new(scope, chrom).unwrap();
     rv.set(s.into());
 }
 
-fn aln_end_getter(
+pub(crate) fn aln_end_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
-    let end = rec.cigar().end_pos() as u32;
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    let end = rec.end() as u32;
     let v = v8::Integer::new_from_unsigned(scope, end);
     rv.set(v.into());
 }
 
 #[allow(clippy::needless_pass_by_value)]
-fn aln_pos_getter(
+pub(crate) fn aln_pos_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
     // BAM pos is 0-based; we expose that directly.
     let pos = rec.pos() as i32;
     let v = v8::Integer::new(scope, pos);
     rv.set(v.into());
 }
 
-fn aln_cigar_getter(
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_source_file_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    // Only valid during a call, like the record fields.
+    if alignment_from_obj(scope, args.this()).is_none() {
+        return;
+    }
+    let name = scope
+        .get_slot::<InputSources>()
+        .and_then(|s| s.names.get(s.current).cloned());
+    match name {
+        Some(name) => rv.set(v8::String::new(scope, &name).unwrap().into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_source_index_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    if alignment_from_obj(scope, args.this()).is_none() {
+        return;
+    }
+    let index = scope
+        .get_slot::<InputSources>()
+        .map_or(0, |s| s.current as u32);
+    let v = v8::Integer::new_from_unsigned(scope, index);
+    rv.set(v.into());
+}
+
+pub(crate) fn aln_cigar_getter(
     scope: &mut v8::PinScope,
     _name: v8::Local<v8::Name>,
     args: v8::PropertyCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
     let this = args.this();
-    let rec = record_from_obj(this);
-    let cigar = rec.cigar();
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    let js_arr = cigar_to_js(scope, &rec.cigar());
+    rv.set(js_arr.into());
+}
+
+/// `aln.cigar` as an array of `{length, op, consumes_ref, consumes_query}`.
+pub(crate) fn cigar_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    cigar: &[Cigar],
+) -> v8::Local<'s, v8::Array> {
     let js_arr = v8::Array::new(scope, cigar.len() as i32);
     let len_key = v8::String::new(scope, "length").unwrap();
     let op_key = v8::String::new(scope, "op").unwrap();
@@ -342,8 +1110,7 @@ fn aln_cigar_getter(
 
         js_arr.set_index(scope, i as u32, obj.into());
     }
-
-    rv.set(js_arr.into());
+    js_arr
 }
 
 fn cigar_op_info(op: &Cigar) -> (&'static str, bool, bool, u32) {
@@ -360,17 +1127,63 @@ fn cigar_op_info(op: &Cigar) -> (&'static str, bool, bool, u32) {
     }
 }
 
+// ========== Interceptor: unknown properties ==========
+
+/// Names that built-ins and common idioms look up on any object, e.g.
+/// `toJSON` from `JSON.stringify(aln)` and `then` from `await aln`; reading
+/// them is not a typo.
+const PROBED_PROPERTIES: [&str; 7] = [
+    "toJSON",
+    "then",
+    "constructor",
+    "toString",
+    "valueOf",
+    "toLocaleString",
+    "__proto__",
+];
+
+pub(crate) fn unknown_property_getter(
+    scope: &mut v8::PinScope,
+    key: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    _rv: v8::ReturnValue,
+) -> v8::Intercepted {
+    let name = key.to_rust_string_lossy(scope);
+    if PROBED_PROPERTIES.contains(&name.as_str()) {
+        return v8::Intercepted::No;
+    }
+    let strict = args.data().boolean_value(scope);
+
+    let Some(unknown) = scope.get_slot_mut::<UnknownProperties>() else {
+        return v8::Intercepted::No;
+    };
+    let object = unknown.object;
+    if unknown.seen.insert(name.clone()) && !strict {
+        warn!("script read unknown property {object}.{name} (undefined)");
+    }
+
+    if strict {
+        let msg = v8::String::new(scope, &format!("unknown property {object}.{name}")).unwrap();
+        let exc = v8::Exception::type_error(scope, msg);
+        scope.throw_exception(exc);
+        return v8::Intercepted::Yes;
+    }
+    v8::Intercepted::No
+}
+
 // ========== Method: aln.aux(tag) ==========
 
 #[allow(clippy::needless_pass_by_value)]
-fn aln_aux_method(
+pub(crate) fn aln_aux_method(
     scope: &mut v8::PinScope,
     args: v8::FunctionCallbackArguments,
     mut rv: v8::ReturnValue,
 ) {
-    // Get the aln object (this) and extract the BAM record
+    // Get the aln object (this) and the record behind it
     let this = args.this();
-    let rec = record_from_obj(this);
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
 
     // Get tag name from first argument
     let tag_arg = args.get(0);
@@ -379,21 +1192,19 @@ fn aln_aux_method(
         return;
     }
     let tag_str = tag_arg.to_rust_string_lossy(scope);
-    let tag_bytes = tag_str.as_bytes();
-
     // Tag must be exactly 2 characters
-    if tag_bytes.len() != 2 {
+    let Ok(tag) = <[u8; 2]>::try_from(tag_str.as_bytes()) else {
         rv.set(v8::null(scope).into());
         return;
-    }
+    };
 
     // Look up the aux tag
-    match rec.aux(tag_bytes) {
-        Ok(aux) => {
+    match rec.aux(&tag) {
+        Some(aux) => {
             let js_val = aux_to_js_value(scope, aux);
             rv.set(js_val);
         }
-        Err(_) => {
+        None => {
             // Tag not found - return null
             rv.set(v8::null(scope).into());
         }
@@ -401,7 +1212,7 @@ fn aln_aux_method(
 }
 
 /// Convert a rust_htslib Aux value to a V8 value
-fn aux_to_js_value<'s, 'i>(
+pub(crate) fn aux_to_js_value<'s, 'i>(
     scope: &mut v8::PinScope<'s, 'i>,
     aux: Aux<'_>,
 ) -> v8::Local<'s, v8::Value> {
@@ -424,70 +1235,63 @@ fn aux_to_js_value<'s, 'i>(
             // HexByteArray is already a hex-encoded string view
             v8::String::new(scope, hex_view).unwrap().into()
         }
-        // Array types - return as JS arrays
-        Aux::ArrayI8(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new(scope, v as i32);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayU8(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new_from_unsigned(scope, v as u32);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayI16(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new(scope, v as i32);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayU16(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new_from_unsigned(scope, v as u32);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayI32(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new(scope, v);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayU32(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Integer::new_from_unsigned(scope, v);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
-        }
-        Aux::ArrayFloat(arr) => {
-            let js_arr = v8::Array::new(scope, arr.len() as i32);
-            for (i, v) in arr.iter().enumerate() {
-                let js_v = v8::Number::new(scope, v as f64);
-                js_arr.set_index(scope, i as u32, js_v.into());
-            }
-            js_arr.into()
+        // B arrays - return as typed arrays of the matching element type
+        arr => typed::aux_array_to_js(scope, &arr).unwrap(),
+    }
+}
+
+/// 64-bit FNV-1a over the concatenation of `parts`. Unlike `DefaultHasher`
+/// the result is stable across builds, so it is safe to persist.
+pub(crate) fn fnv1a64(parts: &[&[u8]]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for &b in *part {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x0100_0000_01b3);
         }
     }
+    h
+}
+
+/// Open a text output: "-" is stdout, a `.gz` suffix selects gzip.
+pub fn create_text_output(path: &Path) -> Result<Box<dyn Write>> {
+    if path.to_string_lossy() == "-" {
+        return Ok(Box::new(BufWriter::new(io::stdout().lock())));
+    }
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufWriter::new(GzEncoder::new(
+            file,
+            Compression::default(),
+        ))))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+/// Open a text input: "-" is stdin; gzip is detected from the magic bytes.
+pub fn open_text_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    if path.to_string_lossy() == "-" {
+        return decompress_if_gzip(io::stdin().lock());
+    }
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    decompress_if_gzip(file)
+}
+
+pub(crate) fn decompress_if_gzip(reader: impl Read + 'static) -> Result<Box<dyn BufRead>> {
+    let mut buffered = BufReader::new(reader);
+    if buffered.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(buffered))))
+    } else {
+        Ok(Box::new(buffered))
+    }
 }
 
 // ========== Rust helper: hasFlag(flag, mask) ==========
 
 #[allow(clippy::needless_pass_by_value)]
-fn has_flag_callback(
+pub(crate) fn has_flag_callback(
     scope: &mut v8::PinScope,
     args: v8::FunctionCallbackArguments,
     mut rv: v8::ReturnValue,
@@ -0,0 +1,233 @@
+//! Long-read accessors: `aln.supplementaryAlignments()` (parsed SA tag) and
+//! `aln.modifications()` (MM/ML base modification calls).
+//!
+//! MM/ML are decoded by htslib; records of other types are copied into a
+//! minimal `bam::Record` first.
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar};
+
+use crate::record::AlignmentRecord;
+use crate::{alignment_from_obj, throw_error};
+
+/// One entry of an SA tag: `rname,pos,strand,CIGAR,mapQ,NM;`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SaEntry<'a> {
+    pub(crate) chrom: &'a str,
+    /// 0-based, like `aln.pos`.
+    pub(crate) pos: i64,
+    pub(crate) reverse: bool,
+    pub(crate) cigar: &'a str,
+    pub(crate) mapq: u8,
+    pub(crate) nm: i64,
+}
+
+fn parse_sa_entry(entry: &str) -> Option<SaEntry<'_>> {
+    let mut f = entry.split(',');
+    let e = SaEntry {
+        chrom: f.next().filter(|c| !c.is_empty())?,
+        pos: f.next()?.parse::<i64>().ok().filter(|&p| p > 0)? - 1,
+        reverse: match f.next()? {
+            "+" => false,
+            "-" => true,
+            _ => return None,
+        },
+        cigar: f.next().filter(|c| !c.is_empty())?,
+        mapq: f.next()?.parse().ok()?,
+        nm: f.next()?.parse().ok()?,
+    };
+    f.next().is_none().then_some(e)
+}
+
+/// Parse an SA tag value. The error names the first malformed entry.
+pub(crate) fn parse_sa(sa: &str) -> Result<Vec<SaEntry<'_>>, String> {
+    sa.split(';')
+        .filter(|e| !e.is_empty())
+        .map(|entry| parse_sa_entry(entry).ok_or_else(|| format!("malformed SA entry '{entry}'")))
+        .collect()
+}
+
+/// The record's SA entries; empty without an SA tag.
+pub(crate) fn sa_entries(rec: &dyn AlignmentRecord) -> Result<Vec<SaEntry<'_>>, String> {
+    match rec.aux(b"SA") {
+        Some(Aux::String(sa)) => parse_sa(sa),
+        Some(_) => Err("SA tag is not a string".to_string()),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn set<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    obj: v8::Local<'s, v8::Object>,
+    key: &str,
+    value: v8::Local<'s, v8::Value>,
+) {
+    let key = v8::String::new(scope, key).unwrap();
+    obj.set(scope, key.into(), value);
+}
+
+/// `aln.supplementaryAlignments()`: the SA tag as an array of
+/// `{chrom, pos, strand, cigar, mapq, nm}` with 0-based `pos`. Throws on a
+/// malformed tag.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_supplementary_alignments_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let entries = match sa_entries(rec) {
+        Ok(entries) => entries,
+        Err(e) => {
+            let qname = String::from_utf8_lossy(rec.qname());
+            throw_error(scope, &format!("{qname}: {e}"));
+            return;
+        }
+    };
+    let js_arr = v8::Array::new(scope, entries.len() as i32);
+    for (i, e) in entries.iter().enumerate() {
+        let obj = v8::Object::new(scope);
+        let v = v8::String::new(scope, e.chrom).unwrap();
+        set(scope, obj, "chrom", v.into());
+        let v = v8::Number::new(scope, e.pos as f64);
+        set(scope, obj, "pos", v.into());
+        let v = v8::String::new(scope, if e.reverse { "-" } else { "+" }).unwrap();
+        set(scope, obj, "strand", v.into());
+        let v = v8::String::new(scope, e.cigar).unwrap();
+        set(scope, obj, "cigar", v.into());
+        let v = v8::Integer::new_from_unsigned(scope, e.mapq as u32);
+        set(scope, obj, "mapq", v.into());
+        let v = v8::Number::new(scope, e.nm as f64);
+        set(scope, obj, "nm", v.into());
+        js_arr.set_index(scope, i as u32, obj.into());
+    }
+    rv.set(js_arr.into());
+}
+
+/// A `bam::Record` with the fields htslib's MM/ML decoder reads: flags,
+/// sequence and the MM/ML/MN tags.
+fn basemods_record(rec: &dyn AlignmentRecord) -> bam::Record {
+    let seq = rec.seq();
+    let qual = match rec.qual() {
+        q if q.len() == seq.len() => q.to_vec(),
+        _ => vec![255; seq.len()],
+    };
+    let mut out = bam::Record::new();
+    out.set(rec.qname(), None, &seq, &qual);
+    out.set_flags(rec.flags());
+    for tag in [b"MM", b"Mm", b"ML", b"Ml", b"MN"] {
+        if let Some(aux) = rec.aux(tag) {
+            // Only fails for tags already present, and each is copied once.
+            let _ = out.push_aux(tag, aux);
+        }
+    }
+    out
+}
+
+/// Reference position of each query base, `None` for inserted and clipped
+/// bases and for unmapped reads.
+fn query_to_ref(rec: &dyn AlignmentRecord) -> Vec<Option<i64>> {
+    let mut map = vec![None; rec.seq_len()];
+    if rec.flags() & 0x4 != 0 {
+        return map;
+    }
+    let (mut q, mut r) = (0usize, rec.pos());
+    for op in rec.cigar().iter() {
+        match *op {
+            Cigar::Match(n) | Cigar::Equal(n) | Cigar::Diff(n) => {
+                for _ in 0..n {
+                    if let Some(slot) = map.get_mut(q) {
+                        *slot = Some(r);
+                    }
+                    q += 1;
+                    r += 1;
+                }
+            }
+            Cigar::Ins(n) | Cigar::SoftClip(n) => q += n as usize,
+            Cigar::Del(n) | Cigar::RefSkip(n) => r += n as i64,
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+    map
+}
+
+/// `aln.modifications()`: one entry per modification call,
+/// `{queryPos, refPos, base, code, strand, prob}`. `queryPos` indexes the
+/// stored SEQ, `refPos` is null off the reference, `code` is the MM code
+/// (`"m"`, `"h"`, or a ChEBI number) and `prob` is the ML probability, or
+/// null without ML. Empty without an MM tag; throws when MM/ML can't be
+/// decoded (e.g. fewer ML values than MM calls).
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_modifications_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(aln) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let converted;
+    let rec = match aln.htslib() {
+        Some((rec, _)) => rec,
+        None => {
+            converted = basemods_record(aln);
+            &converted
+        }
+    };
+    let qname = || String::from_utf8_lossy(rec.qname()).into_owned();
+    let iter = match rec.basemods_position_iter() {
+        Ok(iter) => iter,
+        // htslib reports any parse failure as a missing tag; a missing MM
+        // tag itself parses fine, as no modifications.
+        Err(_) => {
+            throw_error(scope, &format!("{}: invalid MM/ML tags", qname()));
+            return;
+        }
+    };
+    let js_arr = v8::Array::new(scope, 0);
+    let ref_pos = query_to_ref(aln);
+    let mut n = 0;
+    for item in iter {
+        let (pos, mods) = match item {
+            Ok(item) => item,
+            Err(e) => {
+                throw_error(scope, &format!("{}: invalid MM/ML tags: {e}", qname()));
+                return;
+            }
+        };
+        for m in mods {
+            let obj = v8::Object::new(scope);
+            let v = v8::Integer::new(scope, pos);
+            set(scope, obj, "queryPos", v.into());
+            let v = match ref_pos.get(pos as usize).copied().flatten() {
+                Some(r) => v8::Number::new(scope, r as f64).into(),
+                None => v8::null(scope).into(),
+            };
+            set(scope, obj, "refPos", v);
+            let base = char::from(m.canonical_base as u8).to_string();
+            let v = v8::String::new(scope, &base).unwrap();
+            set(scope, obj, "base", v.into());
+            let code = if m.modified_base > 0 {
+                char::from(m.modified_base as u8).to_string()
+            } else {
+                (-m.modified_base).to_string()
+            };
+            let v = v8::String::new(scope, &code).unwrap();
+            set(scope, obj, "code", v.into());
+            let v = v8::String::new(scope, if m.strand == 0 { "+" } else { "-" }).unwrap();
+            set(scope, obj, "strand", v.into());
+            // ML value N stands for probabilities in [N/256, (N+1)/256).
+            let v = if m.qual >= 0 {
+                v8::Number::new(scope, (m.qual as f64 + 0.5) / 256.0).into()
+            } else {
+                v8::null(scope).into()
+            };
+            set(scope, obj, "prob", v);
+            js_arr.set_index(scope, n, obj.into());
+            n += 1;
+        }
+    }
+    rv.set(js_arr.into());
+}
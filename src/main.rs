@@ -1,116 +1,1048 @@
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{Context, Result, bail};
+use clap::{Parser, ValueEnum};
 use log::info;
-use rust_htslib::bam;
-use rust_htslib::bam::Read;
+use rust_htslib::bcf::Read as _;
 use rust_htslib::tpool::ThreadPool;
+use rust_htslib::{bam, bcf};
 
-use v8bam::JsBamFilterEngine;
+use v8bam::barcode::{self, BarcodeSource};
+use v8bam::depth::{self, Coverage, DepthCap};
+use v8bam::fastq::{FastqOptions, FastqReader, FastqRecord, FastqWriter, JsFastqFilterEngine};
+use v8bam::inputs::{BamInputs, Combine};
+use v8bam::native::NativeFilter;
+use v8bam::regions::RegionSet;
+use v8bam::vcf::JsVcfFilterEngine;
+use v8bam::window::RecordWindow;
+use v8bam::{
+    CodeCache, EngineOptions, JsBamFilterEngine, QnameSampler, UnknownPropertyMode, check, console,
+    create_text_output, snapshot, stats::RunStats, sv,
+};
 
 #[derive(Parser, Debug)]
 struct Args {
-    /// Input BAM ("-" for stdin)
-    input: PathBuf,
+    /// Input BAM/SAM/CRAM, FASTQ or VCF/BCF ("-" for stdin). Several
+    /// BAM/SAM/CRAM inputs are concatenated, or merged with --merge
+    #[arg(required_unless_present = "check")]
+    input: Vec<PathBuf>,
+
+    /// Merge several coordinate-sorted inputs by coordinate instead of
+    /// concatenating them
+    #[arg(long)]
+    merge: bool,
 
     /// Output BAM ("-" for stdout)
-    #[arg(short = 'o', long)]
-    output: PathBuf,
+    #[arg(
+        short = 'o',
+        long,
+        required_unless_present_any = ["check", "count", "any", "none", "emit", "read1"]
+    )]
+    output: Option<PathBuf>,
+
+    /// Input format; "auto" selects FASTQ for .fq/.fastq(.gz) names,
+    /// VCF for .vcf(.gz)/.bcf and BAM/SAM/CRAM otherwise
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_fmt: InputFormat,
+
+    /// Output format for passing records (FASTQ and VCF input are always
+    /// written in their own format)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Bam)]
+    output_fmt: OutputFormat,
+
+    /// FASTQ output for first mates (implies --output-fmt fastq)
+    #[arg(short = '1', long, requires = "read2", conflicts_with = "output")]
+    read1: Option<PathBuf>,
+
+    /// FASTQ output for second mates
+    #[arg(short = '2', long, requires = "read1")]
+    read2: Option<PathBuf>,
+
+    /// FASTQ output for unpaired reads with -1/-2 (otherwise they are dropped)
+    #[arg(short = 's', long, requires = "read1")]
+    singletons: Option<PathBuf>,
+
+    /// For FASTQ output, take qualities from this tag (e.g. OQ) when present
+    #[arg(long, value_name = "TAG", value_parser = parse_tag)]
+    orig_qual_tag: Option<[u8; 2]>,
+
+    /// For FASTQ output, comma-separated aux tags to append to the header line
+    #[arg(long, value_name = "TAGS", value_delimiter = ',', value_parser = parse_tag)]
+    fastq_tags: Vec<[u8; 2]>,
 
     /// JS expression/body, e.g.:
     ///   'aln.mapq > 10 && aln.qname.startsWith("q23")'
     /// or:
     ///   'return aln.mapq > 10 && hasFlag(aln.flag, 0x2);'
-    #[arg(short = 'e', long)]
-    expr: String,
+    #[arg(short = 'e', long, required_unless_present = "preset")]
+    expr: Option<String>,
+
+    /// Use a built-in filter instead of -e: `discordant` keeps primary,
+    /// non-duplicate reads that are split or in a discordant pair (SV
+    /// evidence as extracted by lumpy/smoove)
+    #[arg(long, value_enum, conflicts_with = "expr")]
+    preset: Option<Preset>,
+
+    /// How to evaluate the filter: `native` evaluates simple comparisons
+    /// and boolean combinations of the built-in `aln` fields (e.g.
+    /// 'aln.mapq >= 20 && !aln.duplicate') in Rust without V8, `js` always
+    /// uses V8, and `auto` uses the native evaluator when it supports the
+    /// expression
+    #[arg(long, value_enum, default_value_t = FilterEngine::Auto)]
+    engine: FilterEngine,
 
     /// Number of threads for BAM I/O
     #[arg(short = 't', long, default_value = "3")]
     threads: u32,
+
+    /// Directory for a V8 startup snapshot and compiled-code cache, so
+    /// repeated runs of the same filter skip setup and compilation
+    #[arg(long, env = "V8BAM_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Load a BED file as `regions.NAME` for the script (repeatable)
+    #[arg(long, value_name = "NAME=PATH", value_parser = parse_bed_arg)]
+    bed: Vec<(String, PathBuf)>,
+
+    /// Where `aln.umi` comes from: `tag:UB,RX` (first present tag) or
+    /// `qname:REGEX` (capture group 1) [default: tag:UB,RX]
+    #[arg(long, value_name = "SOURCE", value_parser = parse_barcode_source)]
+    umi_from: Option<BarcodeSource>,
+
+    /// Where `aln.cellBarcode` comes from, as for --umi-from
+    /// [default: tag:CB,CR]
+    #[arg(long, value_name = "SOURCE", value_parser = parse_barcode_source)]
+    cell_barcode_from: Option<BarcodeSource>,
+
+    /// Barcode whitelist (one per line, .gz allowed) for `barcodes.has(bc)`
+    #[arg(long, value_name = "PATH")]
+    barcodes: Option<PathBuf>,
+
+    /// Body of a `header(hdr)` hook run once before writing; it returns
+    /// edits to the output header (rename/remove references, add/remove
+    /// @RG and @CO lines)
+    #[arg(long, value_name = "JS")]
+    header_expr: Option<String>,
+
+    /// Call `filter(aln, window)` with copies of the records starting
+    /// within this many bp of each read (coordinate-sorted input only)
+    #[arg(long, value_name = "BP")]
+    window: Option<u64>,
+
+    /// Compile the expression and run it on a few synthetic records of the
+    /// input's type (BAM without an input), reporting errors and reads of
+    /// unknown properties
+    #[arg(long)]
+    check: bool,
+
+    /// Throw a TypeError when the script reads an unknown `aln` property
+    /// instead of silently getting `undefined`
+    #[arg(long)]
+    strict: bool,
+
+    /// Stop after reading this many input records
+    #[arg(long, value_name = "N")]
+    head: Option<u64>,
+
+    /// Stop after writing this many passing records
+    #[arg(long, value_name = "N")]
+    head_pass: Option<u64>,
+
+    /// Keep only this fraction of reads, chosen by a hash of the qname so
+    /// mates are sampled together; applied before the JS filter
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    sample: Option<f64>,
+
+    /// Seed for --sample and --max-depth
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Thin reads where coverage exceeds N to about N, keeping each read
+    /// with probability N/depth by a hash of its qname (so mates stay
+    /// together); applied after the JS filter. Needs coordinate-sorted input
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Don't write records; print the number of reads and passing reads
+    #[arg(long, group = "mode")]
+    count: bool,
+
+    /// With --count, also print counts per chromosome
+    #[arg(long, requires = "count")]
+    by_chrom: bool,
+
+    /// Exit 0 as soon as any read passes, 1 if none do
+    #[arg(long, group = "mode")]
+    any: bool,
+
+    /// Exit 0 if no read passes, 1 as soon as one does
+    #[arg(long, group = "mode")]
+    none: bool,
+
+    /// Write the script's return value for each record as a line of text
+    /// to this file ("-" for stdout) instead of writing BAM: strings as-is,
+    /// arrays tab-joined, objects as JSON; null/undefined/false skip the record
+    #[arg(long, group = "mode", value_name = "PATH")]
+    emit: Option<PathBuf>,
+
+    /// Maximum number of console.log/print messages from the script
+    #[arg(long, value_name = "N")]
+    log_limit: Option<u64>,
+
+    /// Write run statistics (counts, timings, peak V8 heap) as JSON to
+    /// this file ("-" for stdout)
+    #[arg(long, value_name = "PATH")]
+    stats_json: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Auto,
+    /// BAM, SAM or CRAM via htslib
+    Bam,
+    /// FASTQ, optionally gzipped; the script sees a `read` object
+    Fastq,
+    /// VCF or BCF via htslib; the script sees a `variant` object
+    Vcf,
+}
+
+impl InputFormat {
+    fn resolve(self, input: &Path) -> InputFormat {
+        if self != InputFormat::Auto {
+            return self;
+        }
+        let name = input.to_string_lossy();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        if name.ends_with(".fq") || name.ends_with(".fastq") {
+            InputFormat::Fastq
+        } else if name.ends_with(".vcf") || name.ends_with(".bcf") {
+            InputFormat::Vcf
+        } else {
+            InputFormat::Bam
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Bam,
+    Sam,
+    /// FASTQ; reverse-strand reads are reverse-complemented, secondary and
+    /// supplementary records are skipped. Use a .gz name to compress.
+    Fastq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FilterEngine {
+    Auto,
+    Native,
+    Js,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Preset {
+    Discordant,
+}
+
+/// What to do with passing records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Write,
+    Emit,
+    Count,
+    Any,
+    None,
+}
+
+impl Args {
+    /// The filter expression from -e or --preset.
+    fn filter_expr(&self) -> &str {
+        match (self.preset, &self.expr) {
+            (Some(Preset::Discordant), _) => sv::DISCORDANT_PRESET,
+            (None, Some(expr)) => expr,
+            (None, None) => unreachable!("clap requires -e or --preset"),
+        }
+    }
+
+    fn mode(&self) -> Mode {
+        if self.count {
+            Mode::Count
+        } else if self.any {
+            Mode::Any
+        } else if self.none {
+            Mode::None
+        } else if self.emit.is_some() {
+            Mode::Emit
+        } else {
+            Mode::Write
+        }
+    }
+}
+
+fn parse_tag(s: &str) -> Result<[u8; 2], String> {
+    <[u8; 2]>::try_from(s.as_bytes()).map_err(|_| format!("{s:?} is not a two-character tag"))
+}
+
+fn parse_bed_arg(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("{s:?} is not of the form NAME=PATH")),
+    }
+}
+
+fn parse_barcode_source(s: &str) -> Result<BarcodeSource, String> {
+    BarcodeSource::parse(s).map_err(|e| format!("{e:#}"))
+}
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let f: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&f) {
+        Ok(f)
+    } else {
+        Err(format!("{f} is not in [0, 1]"))
+    }
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+/// Exit status: 0 on success, 1 when `--any`/`--none` did not hold, 2 on
+/// errors.
+fn main() -> ExitCode {
+    // Script output (console.log etc.) is shown unless RUST_LOG says otherwise.
+    env_logger::Builder::new()
+        .filter_module(console::LOG_TARGET, log::LevelFilter::Info)
+        .parse_default_env()
+        .init();
     let args = Args::parse();
+    match run(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    let mode = args.mode();
+
+    let mut engine_opts = EngineOptions::default();
+    if let Some(dir) = &args.cache_dir {
+        engine_opts.snapshot = Some(snapshot::load_or_create_snapshot(
+            &snapshot::snapshot_path(dir),
+        )?);
+        engine_opts.code_cache = Some(CodeCache::new(dir.join("code")));
+    }
+    if args.strict {
+        engine_opts.unknown_properties = UnknownPropertyMode::Error;
+    }
+    engine_opts.log_limit = args.log_limit;
+    if let Some(source) = &args.umi_from {
+        engine_opts.barcodes.umi = source.clone();
+    }
+    if let Some(source) = &args.cell_barcode_from {
+        engine_opts.barcodes.cell_barcode = source.clone();
+    }
+    if let Some(path) = &args.barcodes {
+        let whitelist = barcode::load_whitelist(path)
+            .with_context(|| format!("failed to load barcodes {}", path.display()))?;
+        engine_opts.barcodes.whitelist = Some(Arc::new(whitelist));
+    }
+    for (name, path) in &args.bed {
+        let set = RegionSet::from_bed(path)
+            .with_context(|| format!("failed to load BED {}", path.display()))?;
+        engine_opts.regions.push((name.clone(), Arc::new(set)));
+    }
+
+    if args.check {
+        let input_fmt = match args.input.first() {
+            Some(input) => args.input_fmt.resolve(input),
+            None => args.input_fmt,
+        };
+        return run_check(input_fmt, args.filter_expr(), engine_opts);
+    }
+    if args.stats_json.as_deref() == Some(Path::new("-"))
+        && let Some(other) = stdout_user(&args)
+    {
+        bail!("--stats-json - can't share stdout with {other}; write the stats to a file");
+    }
+    let input = args.input.first().cloned().context("missing input")?;
+    let input_fmt = args.input_fmt.resolve(&input);
+    if input_fmt != InputFormat::Bam
+        && (args.input.len() > 1
+            || args.header_expr.is_some()
+            || args.window.is_some()
+            || args.max_depth.is_some())
+    {
+        bail!(
+            "multiple inputs, --header-expr, --window and --max-depth are only supported for BAM/SAM/CRAM"
+        );
+    }
+    if input_fmt != InputFormat::Bam && args.engine == FilterEngine::Native {
+        bail!("--engine native is only supported for BAM/SAM/CRAM");
+    }
+    match input_fmt {
+        InputFormat::Fastq => {
+            let mut source = FastqSource::open(&args, mode, &input, engine_opts)?;
+            return run_records(&args, mode, &mut source);
+        }
+        InputFormat::Vcf => {
+            let mut source = VcfSource::open(&args, mode, &input, engine_opts)?;
+            return run_records(&args, mode, &mut source);
+        }
+        InputFormat::Auto | InputFormat::Bam => {}
+    }
 
     // Create shared threadpool for BAM I/O
     let tpool = ThreadPool::new(args.threads)?;
 
-    // Open BAM reader & writer
-    let mut reader = if args.input.to_string_lossy() == "-" {
-        bam::Reader::from_stdin().context("failed to open stdin as BAM")?
+    // Open BAM reader(s) & writer
+    let combine = if args.merge {
+        Combine::Merge
     } else {
-        bam::Reader::from_path(&args.input)
-            .with_context(|| format!("failed to open BAM {}", args.input.display()))?
+        Combine::Concat
     };
-    reader.set_thread_pool(&tpool)?;
+    let mut reader = BamInputs::open(&args.input, combine, Some(&tpool))?;
 
-    let header = bam::Header::from_template(reader.header());
-    let mut writer = if args.output.to_string_lossy() == "-" {
-        bam::Writer::from_stdout(&header, bam::Format::Bam).context("failed to open BAM writer")?
+    // Create the JS filter engine, unless the filter is evaluated natively
+    // and no header hook needs V8 either.
+    let native = native_filter(&args, mode)?;
+    let mut engine = if native.is_none() || args.header_expr.is_some() {
+        let mut engine = JsBamFilterEngine::with_options(args.filter_expr(), engine_opts)?;
+        engine.set_input_names(reader.names().to_vec());
+        engine.set_header(reader.header());
+        Some(engine)
     } else {
-        bam::Writer::from_path(&args.output, &header, bam::Format::Bam)
-            .context("failed to open BAM writer")?
+        None
     };
-    writer.set_thread_pool(&tpool)?;
+    // Coverage for --max-depth when there is no engine to track it.
+    let mut coverage = Coverage::default();
+
+    // The filter sees records as read; header edits apply to the output.
+    let (out_header, remap) = match (&args.header_expr, &mut engine) {
+        (Some(expr), Some(engine)) => {
+            let (header, remap) = engine
+                .header_edits(expr, reader.header())?
+                .apply(reader.header())?;
+            (header, Some(remap))
+        }
+        _ => (reader.header().clone(), None),
+    };
+
+    let mut writer = if mode == Mode::Write {
+        let mut sink = open_sink(&args, &out_header)?;
+        if let RecordSink::Bam(w) = &mut sink {
+            w.set_thread_pool(&tpool)?;
+        }
+        Some(sink)
+    } else {
+        None
+    };
+    let mut emitter = args.emit.as_deref().map(create_text_output).transpose()?;
+    let mut line = String::new();
     let header_view = reader.header().clone();
 
-    // Create JS filter engine
-    let mut engine = JsBamFilterEngine::new(&args.expr)?;
+    let sampler = args.sample.map(|f| QnameSampler::new(f, args.seed));
 
     // Reuse record buffer
     let mut record = bam::Record::new();
+    let mut window = args.window.map(RecordWindow::new);
+    // Coverage for --max-depth, and for depth() in scripts that call it on
+    // sorted input.
+    let track_depth = args.max_depth.is_some()
+        || (native.is_none()
+            && depth::script_uses_depth(args.filter_expr())
+            && reader.coordinate_sorted());
+    let mut depth_cap = args.max_depth.map(|n| DepthCap::new(n, args.seed));
+
+    let mut stats = RunStats::new(args.stats_json.is_some());
+    // Per-chromosome (read, passed) counts for --by-chrom; index 0 is unmapped.
+    let mut chrom_counts = vec![(0u64, 0u64); header_view.target_count() as usize + 1];
 
-    let mut records_read = 0;
-    let mut records_written = 0;
-
-    loop {
-        match reader.read(&mut record) {
-            Some(Ok(())) => {
-                records_read += 1;
-
-                // Log progress at intervals
-                let log_message = match records_read {
-                    10_000 => Some("10,000".to_string()),
-                    100_000 => Some("100,000".to_string()),
-                    1_000_000 => Some("1M".to_string()),
-                    _ => {
-                        if records_read % 5_000_000 == 0 {
-                            Some(format!("{}M", records_read / 1_000_000))
-                        } else {
-                            None
+    let mut process = || -> Result<ExitCode> {
+        loop {
+            if args.head.is_some_and(|n| stats.records_read >= n)
+                || args.head_pass.is_some_and(|n| stats.records_passed >= n)
+            {
+                info!("Reached record limit, stopping");
+                break;
+            }
+            let t = stats.timer();
+            let next = match &mut window {
+                Some(window) => window.advance(&mut record, |rec| reader.read(rec)),
+                None => reader.read(&mut record),
+            };
+            stats.add_io_time(t);
+            match next {
+                Some(Ok(source)) => {
+                    if let Some(engine) = &mut engine {
+                        engine.set_current_input(source);
+                    }
+                    if track_depth {
+                        match &mut engine {
+                            Some(engine) => engine.add_to_depth(&record, &header_view)?,
+                            None => coverage.add(&record, &header_view)?,
                         }
                     }
-                };
+                    stats.records_read += 1;
+                    let records_read = stats.records_read;
 
-                if let Some(count_str) = log_message {
-                    let percent = if records_read > 0 {
-                        (records_written as f64 / records_read as f64) * 100.0
-                    } else {
-                        0.0
+                    // Log progress at intervals
+                    let log_message = match records_read {
+                        10_000 => Some("10,000".to_string()),
+                        100_000 => Some("100,000".to_string()),
+                        1_000_000 => Some("1M".to_string()),
+                        _ => {
+                            if records_read % 5_000_000 == 0 {
+                                Some(format!("{}M", records_read / 1_000_000))
+                            } else {
+                                None
+                            }
+                        }
                     };
-                    info!(
-                        "Processed {} records, {:.2}% passed the filter",
-                        count_str, percent
-                    );
-                }
 
-                if engine.record_passes(&record, &header_view)? {
-                    writer.write(&record)?;
-                    records_written += 1;
+                    if let Some(count_str) = log_message {
+                        let percent = (stats.records_passed as f64 / records_read as f64) * 100.0;
+                        info!(
+                            "Processed {} records, {:.2}% passed the filter",
+                            count_str, percent
+                        );
+                    }
+
+                    if let Some(sampler) = &sampler
+                        && !sampler.keep(record.qname())
+                    {
+                        stats.records_sampled_out += 1;
+                        continue;
+                    }
+
+                    let t = stats.timer();
+                    let passes = match (&native, &mut engine, &mut emitter, &window) {
+                        // Only chosen without --emit and --window.
+                        (Some(native), _, _, _) => Ok(native.record_passes(&record, &header_view)),
+                        (None, Some(engine), Some(_), Some(window)) => {
+                            line.clear();
+                            engine.record_emit_in(&record, &header_view, window, &mut line)
+                        }
+                        (None, Some(engine), Some(_), None) => {
+                            line.clear();
+                            engine.record_emit(&record, &header_view, &mut line)
+                        }
+                        (None, Some(engine), None, Some(window)) => {
+                            engine.record_passes_in(&record, &header_view, window)
+                        }
+                        (None, Some(engine), None, None) => {
+                            engine.record_passes(&record, &header_view)
+                        }
+                        (None, None, _, _) => {
+                            unreachable!("the engine is built without a native filter")
+                        }
+                    };
+                    stats.add_js_time(t);
+                    let mut passes = passes.inspect_err(|_| stats.script_errors += 1)?;
+                    if passes && let Some(cap) = &mut depth_cap {
+                        let depth = match &mut engine {
+                            Some(engine) => engine.depth_at(&record),
+                            None => coverage.depth_at(&record),
+                        };
+                        passes = cap.keep(&record, depth);
+                    }
+
+                    if args.stats_json.is_some() {
+                        stats.observe_flags(&record, passes);
+                        if records_read % 4096 == 0
+                            && let Some(engine) = &mut engine
+                        {
+                            stats.observe_heap(engine.used_heap_size());
+                        }
+                    }
+                    if args.by_chrom {
+                        let counts = &mut chrom_counts[(record.tid() + 1) as usize];
+                        counts.0 += 1;
+                        counts.1 += passes as u64;
+                    }
+                    if !passes {
+                        continue;
+                    }
+                    stats.records_passed += 1;
+                    match mode {
+                        Mode::Write => {
+                            if let Some(remap) = &remap
+                                && !remap.apply(&mut record)
+                            {
+                                stats.records_removed_by_header += 1;
+                                continue;
+                            }
+                            let t = stats.timer();
+                            let written = writer.as_mut().unwrap().write(&record)?;
+                            stats.add_io_time(t);
+                            stats.records_written += written as u64;
+                        }
+                        Mode::Emit => {
+                            line.push('\n');
+                            let t = stats.timer();
+                            emitter.as_mut().unwrap().write_all(line.as_bytes())?;
+                            stats.add_io_time(t);
+                            stats.records_written += 1;
+                        }
+                        Mode::Count => {}
+                        Mode::Any => {
+                            info!("Found a passing read after {} records", records_read);
+                            return Ok(ExitCode::SUCCESS);
+                        }
+                        Mode::None => {
+                            info!("Found a passing read after {} records", records_read);
+                            return Ok(ExitCode::FAILURE);
+                        }
+                    }
                 }
+                Some(Err(e)) => return Err(e),
+                None => break,
             }
-            Some(Err(e)) => return Err(e.into()),
-            None => break,
+        }
+        if let Some(out) = &mut emitter {
+            out.flush()?;
+        }
+        if let Some(RecordSink::Fastq(w)) = &mut writer {
+            stats.records_written += w.finish()? as u64;
+        }
+        Ok(match mode {
+            Mode::Any => ExitCode::FAILURE,
+            _ => ExitCode::SUCCESS,
+        })
+    };
+    let result = process();
+    let heap_bytes = engine.as_mut().map_or(0, |engine| engine.used_heap_size());
+    let code = finish_run(&args, mode, &mut stats, result, heap_bytes)?;
+
+    if mode == Mode::Count && args.by_chrom {
+        println!();
+        println!("chrom\tread\tpassed");
+        for (i, (read, passed)) in chrom_counts.iter().enumerate() {
+            if *read == 0 {
+                continue;
+            }
+            let chrom = if i == 0 {
+                "*".to_string()
+            } else {
+                String::from_utf8_lossy(header_view.tid2name(i as u32 - 1)).into_owned()
+            };
+            println!("{}\t{}\t{}", chrom, read, passed);
         }
     }
 
+    Ok(code)
+}
+
+/// The option that writes records or counts to stdout, if any.
+fn stdout_user(args: &Args) -> Option<&'static str> {
+    let is_stdout = |path: &Option<PathBuf>| path.as_deref() == Some(Path::new("-"));
+    if args.count {
+        Some("--count")
+    } else if is_stdout(&args.output) {
+        Some("-o -")
+    } else if is_stdout(&args.emit) {
+        Some("--emit -")
+    } else if is_stdout(&args.read1) || is_stdout(&args.read2) || is_stdout(&args.singletons) {
+        Some("FASTQ output to -")
+    } else {
+        None
+    }
+}
+
+/// The native evaluator to use instead of the JS engine, per `--engine`.
+/// It only decides pass/fail, so `--emit` and `--window` always use JS.
+fn native_filter(args: &Args, mode: Mode) -> Result<Option<NativeFilter>> {
+    if args.engine == FilterEngine::Js {
+        return Ok(None);
+    }
+    let unsupported = if mode == Mode::Emit {
+        Some("--emit")
+    } else if args.window.is_some() {
+        Some("--window")
+    } else {
+        None
+    };
+    let native = match unsupported {
+        Some(_) => None,
+        None => NativeFilter::parse(args.filter_expr()),
+    };
+    match (args.engine, &native, unsupported) {
+        (FilterEngine::Native, None, Some(option)) => {
+            bail!("--engine native can't be combined with {option}")
+        }
+        (FilterEngine::Native, None, None) => bail!(
+            "--engine native only supports comparisons and boolean combinations of the \
+             built-in aln fields; use --engine js or auto for this expression"
+        ),
+        (_, Some(_), _) => info!("Evaluating the filter natively"),
+        _ => {}
+    }
+    Ok(native)
+}
+
+/// Common end of a run: write `--stats-json` (also on failure), log a
+/// summary and print `--count` totals.
+fn finish_run(
+    args: &Args,
+    mode: Mode,
+    stats: &mut RunStats,
+    result: Result<ExitCode>,
+    heap_bytes: usize,
+) -> Result<ExitCode> {
+    if let Some(path) = &args.stats_json {
+        stats.observe_heap(heap_bytes);
+        stats.error = result.as_ref().err().map(|e| format!("{e:#}"));
+        stats.write_json(path)?;
+    }
+    let code = result?;
+
+    let (records_read, records_passed) = (stats.records_read, stats.records_passed);
     info!(
         "Finished processing: {} reads, {} passed the filter ({:.2}%)",
         records_read,
-        records_written,
-        (records_written as f64 / records_read.max(1) as f64 * 100.0).round()
+        records_passed,
+        (records_passed as f64 / records_read.max(1) as f64 * 100.0).round()
     );
-    Ok(())
+
+    if mode == Mode::Count {
+        println!("read\tpassed");
+        println!("{}\t{}", records_read, records_passed);
+    }
+    Ok(code)
+}
+
+/// Per-format part of [`run_records`]: reads records into a reusable buffer,
+/// runs the format's engine on it and writes passing records.
+trait RecordSource {
+    fn read_next(&mut self) -> Option<Result<()>>;
+    /// Whether `--sample` keeps the current record.
+    fn keep(&self, sampler: &QnameSampler) -> bool;
+    /// Run the filter on the current record; with `line`, in emit mode.
+    fn filter(&mut self, line: Option<&mut String>) -> Result<bool>;
+    /// Write the current record in write mode.
+    fn write(&mut self) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+    fn used_heap_size(&mut self) -> usize;
+}
+
+/// Filter non-BAM input. Passing records are written back in the input's
+/// format; `--emit`, `--count`, `--any`/`--none`, the limits and
+/// `--stats-json` work as for BAM input.
+fn run_records(args: &Args, mode: Mode, source: &mut impl RecordSource) -> Result<ExitCode> {
+    let mut emitter = match mode {
+        Mode::Emit => args.emit.as_deref().map(create_text_output).transpose()?,
+        _ => None,
+    };
+    let sampler = args.sample.map(|f| QnameSampler::new(f, args.seed));
+    let mut line = String::new();
+    let mut stats = RunStats::new(args.stats_json.is_some());
+
+    let mut process = || -> Result<ExitCode> {
+        loop {
+            if args.head.is_some_and(|n| stats.records_read >= n)
+                || args.head_pass.is_some_and(|n| stats.records_passed >= n)
+            {
+                info!("Reached record limit, stopping");
+                break;
+            }
+            let t = stats.timer();
+            let next = source.read_next();
+            stats.add_io_time(t);
+            match next {
+                Some(Ok(())) => {}
+                Some(Err(e)) => return Err(e),
+                None => break,
+            }
+            stats.records_read += 1;
+
+            if let Some(sampler) = &sampler
+                && !source.keep(sampler)
+            {
+                stats.records_sampled_out += 1;
+                continue;
+            }
+
+            let t = stats.timer();
+            let passes = if mode == Mode::Emit {
+                line.clear();
+                source.filter(Some(&mut line))
+            } else {
+                source.filter(None)
+            };
+            stats.add_js_time(t);
+            let passes = passes.inspect_err(|_| stats.script_errors += 1)?;
+            if args.stats_json.is_some() && stats.records_read % 4096 == 0 {
+                stats.observe_heap(source.used_heap_size());
+            }
+            if !passes {
+                continue;
+            }
+            stats.records_passed += 1;
+
+            let t = stats.timer();
+            match mode {
+                Mode::Write => source.write()?,
+                Mode::Emit => {
+                    line.push('\n');
+                    emitter.as_mut().unwrap().write_all(line.as_bytes())?;
+                }
+                Mode::Count => {}
+                Mode::Any => return Ok(ExitCode::SUCCESS),
+                Mode::None => return Ok(ExitCode::FAILURE),
+            }
+            stats.add_io_time(t);
+            stats.records_written += matches!(mode, Mode::Write | Mode::Emit) as u64;
+        }
+        if let Some(out) = &mut emitter {
+            out.flush()?;
+        }
+        if mode == Mode::Write {
+            source.finish()?;
+        }
+        Ok(match mode {
+            Mode::Any => ExitCode::FAILURE,
+            _ => ExitCode::SUCCESS,
+        })
+    };
+    let result = process();
+    finish_run(args, mode, &mut stats, result, source.used_heap_size())
+}
+
+/// FASTQ input with a `read` object, written back as FASTQ.
+struct FastqSource {
+    reader: FastqReader,
+    engine: JsFastqFilterEngine,
+    record: FastqRecord,
+    out: Option<Box<dyn Write>>,
+    buf: Vec<u8>,
+}
+
+impl FastqSource {
+    fn open(args: &Args, mode: Mode, input: &Path, engine_opts: EngineOptions) -> Result<Self> {
+        if args.read1.is_some() || args.by_chrom {
+            bail!("-1/-2 and --by-chrom are not supported for FASTQ input");
+        }
+        let out = match mode {
+            Mode::Write => Some(create_text_output(
+                args.output.as_deref().context("missing output")?,
+            )?),
+            _ => None,
+        };
+        Ok(Self {
+            reader: FastqReader::from_path(input)?,
+            engine: JsFastqFilterEngine::with_options(args.filter_expr(), engine_opts)?,
+            record: FastqRecord::default(),
+            out,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl RecordSource for FastqSource {
+    fn read_next(&mut self) -> Option<Result<()>> {
+        self.reader.read(&mut self.record)
+    }
+
+    fn keep(&self, sampler: &QnameSampler) -> bool {
+        sampler.keep(&self.record.name)
+    }
+
+    fn filter(&mut self, line: Option<&mut String>) -> Result<bool> {
+        match line {
+            Some(line) => self.engine.record_emit(&self.record, line),
+            None => self.engine.record_passes(&self.record),
+        }
+    }
+
+    fn write(&mut self) -> Result<()> {
+        self.buf.clear();
+        self.record.write_to(&mut self.buf);
+        self.out.as_mut().unwrap().write_all(&self.buf)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(out) = &mut self.out {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn used_heap_size(&mut self) -> usize {
+        self.engine.used_heap_size()
+    }
+}
+
+/// VCF/BCF input with a `variant` object. Passing records are written as
+/// BCF for a .bcf output name, bgzipped VCF for .vcf.gz and plain VCF
+/// otherwise.
+struct VcfSource {
+    reader: bcf::Reader,
+    engine: JsVcfFilterEngine,
+    record: bcf::Record,
+    writer: Option<bcf::Writer>,
+}
+
+impl VcfSource {
+    fn open(args: &Args, mode: Mode, input: &Path, engine_opts: EngineOptions) -> Result<Self> {
+        if args.read1.is_some() || args.by_chrom || args.sample.is_some() {
+            bail!("-1/-2, --by-chrom and --sample are not supported for VCF input");
+        }
+        let mut reader = if input.to_string_lossy() == "-" {
+            bcf::Reader::from_stdin().context("failed to open stdin as VCF/BCF")?
+        } else {
+            bcf::Reader::from_path(input)
+                .with_context(|| format!("failed to open VCF/BCF {}", input.display()))?
+        };
+        reader.set_threads(args.threads as usize)?;
+
+        let writer = if mode == Mode::Write {
+            let output = args.output.as_ref().context("missing output")?;
+            let header = bcf::Header::from_template(reader.header());
+            let name = output.to_string_lossy();
+            let mut writer = if name == "-" {
+                bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)
+            } else if name.ends_with(".bcf") {
+                bcf::Writer::from_path(output, &header, false, bcf::Format::Bcf)
+            } else {
+                let uncompressed = !name.ends_with(".gz");
+                bcf::Writer::from_path(output, &header, uncompressed, bcf::Format::Vcf)
+            }
+            .context("failed to open VCF/BCF writer")?;
+            writer.set_threads(args.threads as usize)?;
+            Some(writer)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            record: reader.empty_record(),
+            reader,
+            engine: JsVcfFilterEngine::with_options(args.filter_expr(), engine_opts)?,
+            writer,
+        })
+    }
+}
+
+impl RecordSource for VcfSource {
+    fn read_next(&mut self) -> Option<Result<()>> {
+        self.reader
+            .read(&mut self.record)
+            .map(|r| r.context("failed to read VCF/BCF record"))
+    }
+
+    fn keep(&self, _sampler: &QnameSampler) -> bool {
+        true
+    }
+
+    fn filter(&mut self, line: Option<&mut String>) -> Result<bool> {
+        match line {
+            Some(line) => self.engine.record_emit(&self.record, line),
+            None => self.engine.record_passes(&self.record),
+        }
+    }
+
+    fn write(&mut self) -> Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        writer.translate(&mut self.record);
+        writer.write(&self.record)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn used_heap_size(&mut self) -> usize {
+        self.engine.used_heap_size()
+    }
+}
+
+/// Destination for passing records in the default (write) mode.
+enum RecordSink {
+    Bam(bam::Writer),
+    Fastq(FastqWriter),
+}
+
+impl RecordSink {
+    /// Write `rec`; returns the number of records written, which for FASTQ
+    /// pairs is 0 for the first mate and 2 for the second.
+    fn write(&mut self, rec: &bam::Record) -> Result<usize> {
+        match self {
+            RecordSink::Bam(w) => {
+                w.write(rec)?;
+                Ok(1)
+            }
+            RecordSink::Fastq(w) => w.write(rec),
+        }
+    }
+}
+
+fn open_sink(args: &Args, header_view: &bam::HeaderView) -> Result<RecordSink> {
+    let fastq_opts = FastqOptions {
+        orig_qual_tag: args.orig_qual_tag,
+        tags: args.fastq_tags.clone(),
+        ..Default::default()
+    };
+    if let (Some(r1), Some(r2)) = (&args.read1, &args.read2) {
+        let singletons = args
+            .singletons
+            .as_deref()
+            .map(create_text_output)
+            .transpose()?;
+        return Ok(RecordSink::Fastq(FastqWriter::paired(
+            create_text_output(r1)?,
+            create_text_output(r2)?,
+            singletons,
+            fastq_opts,
+        )));
+    }
+
+    let output = args.output.as_ref().context("missing output")?;
+    let format = match args.output_fmt {
+        OutputFormat::Fastq => {
+            return Ok(RecordSink::Fastq(FastqWriter::interleaved(
+                create_text_output(output)?,
+                fastq_opts,
+            )));
+        }
+        OutputFormat::Bam => bam::Format::Bam,
+        OutputFormat::Sam => bam::Format::Sam,
+    };
+    let header = bam::Header::from_template(header_view);
+    let writer = if output.to_string_lossy() == "-" {
+        bam::Writer::from_stdout(&header, format).context("failed to open BAM writer")?
+    } else {
+        bam::Writer::from_path(output, &header, format).context("failed to open BAM writer")?
+    };
+    Ok(RecordSink::Bam(writer))
+}
+
+/// Run the filter on synthetic records of `input_fmt` and print a report;
+/// fails if the script threw or read unknown properties.
+fn run_check(input_fmt: InputFormat, expr: &str, opts: EngineOptions) -> Result<ExitCode> {
+    let report = match input_fmt {
+        InputFormat::Fastq => check::check_fastq_filter(expr, opts)?,
+        InputFormat::Vcf => check::check_vcf_filter(expr, opts)?,
+        InputFormat::Auto | InputFormat::Bam => check::check_filter(expr, opts)?,
+    };
+    for (i, (desc, outcome)) in report.outcomes.iter().enumerate() {
+        match outcome {
+            Ok(true) => println!("record {} ({}): pass", i + 1, desc),
+            Ok(false) => println!("record {} ({}): fail", i + 1, desc),
+            Err(e) => println!("record {} ({}): error: {:#}", i + 1, desc, e),
+        }
+    }
+    for name in &report.unknown_properties {
+        println!("unknown property: {}.{}", report.object, name);
+    }
+    if !report.is_ok() {
+        anyhow::bail!("check failed");
+    }
+    println!("ok");
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,497 @@
+//! Native evaluation of simple filters such as
+//! `aln.mapq >= 20 && !aln.duplicate`, for which entering V8 costs more
+//! than running the script.
+//!
+//! [`NativeFilter::parse`] accepts expressions built from:
+//!
+//! - the `aln` fields `mapq`, `flag`, `pos`, `start`, `end`, `qname`,
+//!   `chrom` and the flag booleans (`duplicate`, `reverse`, ...);
+//! - number (decimal or `0x` hex), string and `true`/`false` literals;
+//! - `<`, `<=`, `>`, `>=` on numbers, `==`, `!=`, `===`, `!==` on values of
+//!   the same type, `&`, `|` and unary `-` on numbers, and
+//!   `hasFlag(flag, mask)`;
+//! - `!`, `&&`, `||` (or `and`/`or`) and parentheses;
+//!
+//! optionally written as `return <expr>;`. Anything else, including
+//! comparisons where JS would convert between types, is rejected and the
+//! filter runs in [`crate::JsBamFilterEngine`] instead, so an accepted
+//! expression gives the same result as the JS filter for every record.
+
+use rust_htslib::bam;
+
+use crate::{AlignmentRecord, FLAG_PROPERTIES, HtslibAlignment};
+
+/// A filter expression evaluated in Rust.
+#[derive(Debug)]
+pub struct NativeFilter {
+    expr: Expr,
+}
+
+impl NativeFilter {
+    /// Parse a `-e` expression; `None` if it is outside the supported
+    /// subset (see the module docs).
+    pub fn parse(user_expr: &str) -> Option<Self> {
+        let source = crate::expand_sugar(user_expr);
+        let mut tokens = tokenize(&source)?;
+        // Mirror the JS wrapping: without "return" anywhere the whole
+        // source is the returned expression.
+        if source.contains("return") {
+            if tokens.first() != Some(&Token::Return) {
+                return None;
+            }
+            tokens.remove(0);
+        }
+        if tokens.last() == Some(&Token::Punct(";")) {
+            tokens.pop();
+        }
+        let mut parser = Parser { tokens, next: 0 };
+        let (expr, _) = parser.or()?;
+        if parser.next != parser.tokens.len() {
+            return None;
+        }
+        Some(Self { expr })
+    }
+
+    /// Whether `aln` passes, i.e. the truthiness of the expression.
+    pub fn passes(&self, aln: &dyn AlignmentRecord) -> bool {
+        self.expr.test(aln)
+    }
+
+    /// [`Self::passes`] for a BAM record.
+    pub fn record_passes(&self, rec: &bam::Record, header: &bam::HeaderView) -> bool {
+        self.passes(&HtslibAlignment::new(rec, header))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Return,
+    Punct(&'static str),
+}
+
+/// Larger literals are left to JS.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// Longest first, so "===" is not read as "==" and "=".
+const PUNCTS: [&str; 19] = [
+    "===", "!==", "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "&", "|", "-", "(", ")", ".",
+    ",", ";",
+];
+
+fn tokenize(source: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            return Some(tokens);
+        };
+        if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(rest.len());
+            let (lit, tail) = rest.split_at(len);
+            let n = match lit.strip_prefix("0x").or_else(|| lit.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()? as f64,
+                // No octal-looking literals like 010.
+                None if lit.len() > 1 && lit.starts_with('0') && !lit.starts_with("0.") => {
+                    return None;
+                }
+                None if lit.bytes().all(|b| b.is_ascii_digit() || b == b'.') => lit.parse().ok()?,
+                None => return None,
+            };
+            // Keep integer conversions exact, as they are in V8.
+            if n > MAX_SAFE_INTEGER {
+                return None;
+            }
+            tokens.push(Token::Num(n));
+            rest = tail;
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..].find(c)? + 1;
+            let lit = &rest[1..end];
+            if lit.contains(['\\', '\n', '\r']) {
+                return None;
+            }
+            tokens.push(Token::Str(lit.to_string()));
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            let (ident, tail) = rest.split_at(len);
+            if ident == "return" {
+                // A line break after `return` makes JS return undefined.
+                let gap = &tail[..tail.len() - tail.trim_start().len()];
+                if gap.contains(['\n', '\r']) {
+                    return None;
+                }
+                tokens.push(Token::Return);
+            } else {
+                tokens.push(Token::Ident(ident.to_string()));
+            }
+            rest = tail;
+        } else {
+            let punct = PUNCTS.iter().find(|p| rest.starts_with(**p))?;
+            // Other operators built from these (`&=`, `<<`, ...) leave a
+            // token the parser rejects, except `--`, which would parse as
+            // two negations.
+            if rest.starts_with("--") {
+                return None;
+            }
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        }
+    }
+}
+
+/// Static type of an expression, used to reject anything JS would coerce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Num,
+    Str,
+    Bool,
+    /// Result of `&&`/`||`, which is one of the operands in JS: only its
+    /// truthiness is known.
+    Truthy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Mapq,
+    Flag,
+    Pos,
+    End,
+    Qname,
+    Chrom,
+    FlagBit(u16),
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "mapq" => Field::Mapq,
+            "flag" => Field::Flag,
+            "pos" | "start" => Field::Pos,
+            "end" => Field::End,
+            "qname" => Field::Qname,
+            "chrom" => Field::Chrom,
+            _ => {
+                let (_, bit) = FLAG_PROPERTIES.iter().find(|(n, _)| *n == name)?;
+                Field::FlagBit(*bit)
+            }
+        })
+    }
+
+    fn ty(self) -> Type {
+        match self {
+            Field::Mapq | Field::Flag | Field::Pos | Field::End => Type::Num,
+            Field::Qname | Field::Chrom => Type::Str,
+            Field::FlagBit(_) => Type::Bool,
+        }
+    }
+
+    /// The value the matching `aln` getter returns.
+    fn value<'a>(self, aln: &'a dyn AlignmentRecord) -> Value<'a> {
+        match self {
+            Field::Mapq => Value::Num(aln.mapq() as f64),
+            Field::Flag => Value::Num(aln.flags() as f64),
+            Field::Pos => Value::Num(aln.pos() as i32 as f64),
+            Field::End => Value::Num(aln.end() as u32 as f64),
+            Field::Qname => Value::Str(utf8_or_empty(aln.qname())),
+            Field::Chrom => Value::Str(aln.chrom().map_or(&b"*"[..], utf8_or_empty)),
+            Field::FlagBit(bit) => Value::Bool(aln.flags() & bit != 0),
+        }
+    }
+}
+
+/// The getters turn invalid UTF-8 into "".
+fn utf8_or_empty(bytes: &[u8]) -> &[u8] {
+    if std::str::from_utf8(bytes).is_ok() {
+        bytes
+    } else {
+        b""
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(f64),
+    Str(Box<[u8]>),
+    Bool(bool),
+    Field(Field),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    HasFlag(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Value<'a> {
+    Num(f64),
+    Str(&'a [u8]),
+    Bool(bool),
+}
+
+impl Value<'_> {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Num(n) => *n != 0.0 && !n.is_nan(),
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+        }
+    }
+
+    fn num(&self) -> f64 {
+        match self {
+            Value::Num(n) => *n,
+            // Ruled out by the type check in the parser.
+            _ => f64::NAN,
+        }
+    }
+}
+
+/// ToInt32 as used by JS bitwise operators.
+fn to_int32(n: f64) -> i32 {
+    if n.is_finite() {
+        (n.trunc() % 4_294_967_296.0) as i64 as i32
+    } else {
+        0
+    }
+}
+
+impl Expr {
+    /// Truthiness of the expression.
+    fn test(&self, aln: &dyn AlignmentRecord) -> bool {
+        match self {
+            Expr::Not(e) => !e.test(aln),
+            Expr::And(a, b) => a.test(aln) && b.test(aln),
+            Expr::Or(a, b) => a.test(aln) || b.test(aln),
+            _ => self.value(aln).truthy(),
+        }
+    }
+
+    fn value<'a>(&'a self, aln: &'a dyn AlignmentRecord) -> Value<'a> {
+        match self {
+            Expr::Num(n) => Value::Num(*n),
+            Expr::Str(s) => Value::Str(s),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Field(field) => field.value(aln),
+            Expr::Neg(e) => Value::Num(-e.value(aln).num()),
+            Expr::Not(_) | Expr::And(..) | Expr::Or(..) => Value::Bool(self.test(aln)),
+            Expr::BitAnd(a, b) => {
+                Value::Num((to_int32(a.value(aln).num()) & to_int32(b.value(aln).num())) as f64)
+            }
+            Expr::BitOr(a, b) => {
+                Value::Num((to_int32(a.value(aln).num()) | to_int32(b.value(aln).num())) as f64)
+            }
+            Expr::HasFlag(flag, mask) => {
+                // hasFlag() takes the integer values as u32.
+                let flag = flag.value(aln).num() as i64 as u32;
+                let mask = mask.value(aln).num() as i64 as u32;
+                Value::Bool(flag & mask != 0)
+            }
+            Expr::Cmp(op, a, b) => {
+                let (a, b) = (a.value(aln), b.value(aln));
+                Value::Bool(match op {
+                    CmpOp::Eq => a == b,
+                    CmpOp::Ne => a != b,
+                    CmpOp::Lt => a.num() < b.num(),
+                    CmpOp::Le => a.num() <= b.num(),
+                    CmpOp::Gt => a.num() > b.num(),
+                    CmpOp::Ge => a.num() >= b.num(),
+                })
+            }
+        }
+    }
+}
+
+/// Recursive descent over JS operator precedence, from `||` down to
+/// primaries. Each level returns the expression and its [`Type`].
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.and()?;
+        while self.eat("||") {
+            let (rhs, _) = self.and()?;
+            expr = Expr::Or(Box::new(expr), Box::new(rhs));
+            ty = Type::Truthy;
+        }
+        Some((expr, ty))
+    }
+
+    fn and(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.bit_or()?;
+        while self.eat("&&") {
+            let (rhs, _) = self.bit_or()?;
+            expr = Expr::And(Box::new(expr), Box::new(rhs));
+            ty = Type::Truthy;
+        }
+        Some((expr, ty))
+    }
+
+    fn bit_or(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.bit_and()?;
+        while self.eat("|") {
+            let (rhs, rhs_ty) = self.bit_and()?;
+            if ty != Type::Num || rhs_ty != Type::Num {
+                return None;
+            }
+            expr = Expr::BitOr(Box::new(expr), Box::new(rhs));
+            ty = Type::Num;
+        }
+        Some((expr, ty))
+    }
+
+    fn bit_and(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.equality()?;
+        while self.eat("&") {
+            let (rhs, rhs_ty) = self.equality()?;
+            if ty != Type::Num || rhs_ty != Type::Num {
+                return None;
+            }
+            expr = Expr::BitAnd(Box::new(expr), Box::new(rhs));
+            ty = Type::Num;
+        }
+        Some((expr, ty))
+    }
+
+    fn equality(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.relational()?;
+        loop {
+            let op = if self.eat("===") || self.eat("==") {
+                CmpOp::Eq
+            } else if self.eat("!==") || self.eat("!=") {
+                CmpOp::Ne
+            } else {
+                return Some((expr, ty));
+            };
+            let (rhs, rhs_ty) = self.relational()?;
+            if ty != rhs_ty || ty == Type::Truthy {
+                return None;
+            }
+            expr = Expr::Cmp(op, Box::new(expr), Box::new(rhs));
+            ty = Type::Bool;
+        }
+    }
+
+    fn relational(&mut self) -> Option<(Expr, Type)> {
+        let (mut expr, mut ty) = self.unary()?;
+        loop {
+            let op = if self.eat("<=") {
+                CmpOp::Le
+            } else if self.eat(">=") {
+                CmpOp::Ge
+            } else if self.eat("<") {
+                CmpOp::Lt
+            } else if self.eat(">") {
+                CmpOp::Gt
+            } else {
+                return Some((expr, ty));
+            };
+            // Strings compare by UTF-16 code units in JS; numbers only.
+            let (rhs, rhs_ty) = self.unary()?;
+            if ty != Type::Num || rhs_ty != Type::Num {
+                return None;
+            }
+            expr = Expr::Cmp(op, Box::new(expr), Box::new(rhs));
+            ty = Type::Bool;
+        }
+    }
+
+    fn unary(&mut self) -> Option<(Expr, Type)> {
+        if self.eat("!") {
+            let (expr, _) = self.unary()?;
+            return Some((Expr::Not(Box::new(expr)), Type::Bool));
+        }
+        if self.eat("-") {
+            let (expr, ty) = self.unary()?;
+            if ty != Type::Num {
+                return None;
+            }
+            return Some((Expr::Neg(Box::new(expr)), Type::Num));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Option<(Expr, Type)> {
+        let token = self.peek()?.clone();
+        self.next += 1;
+        match token {
+            Token::Num(n) => Some((Expr::Num(n), Type::Num)),
+            Token::Str(s) => Some((Expr::Str(s.into_bytes().into()), Type::Str)),
+            Token::Punct("(") => {
+                let inner = self.or()?;
+                self.eat(")").then_some(inner)
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Some((Expr::Bool(true), Type::Bool)),
+                "false" => Some((Expr::Bool(false), Type::Bool)),
+                "aln" => {
+                    if !self.eat(".") {
+                        return None;
+                    }
+                    let Some(Token::Ident(name)) = self.peek() else {
+                        return None;
+                    };
+                    let field = Field::from_name(name)?;
+                    self.next += 1;
+                    // Method calls and properties of fields go to JS.
+                    if matches!(self.peek(), Some(Token::Punct("(" | "."))) {
+                        return None;
+                    }
+                    Some((Expr::Field(field), field.ty()))
+                }
+                "hasFlag" => {
+                    if !self.eat("(") {
+                        return None;
+                    }
+                    let (flag, flag_ty) = self.or()?;
+                    if !self.eat(",") {
+                        return None;
+                    }
+                    let (mask, mask_ty) = self.or()?;
+                    if !self.eat(")") || flag_ty != Type::Num || mask_ty != Type::Num {
+                        return None;
+                    }
+                    Some((Expr::HasFlag(Box::new(flag), Box::new(mask)), Type::Bool))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! `aln.readGroup`, `aln.sample`, `aln.library` and `aln.platform`.
+//!
+//! The record's RG tag is joined against the header's `@RG` lines. For
+//! htslib records the lines are parsed once per header into an isolate slot,
+//! so each access is a tag lookup plus a hash map probe. A header is
+//! recognized by its address and its text buffer; as a dropped header's
+//! memory can be reused by the next one,
+//! [`crate::JsBamFilterEngine::set_header`] reparses explicitly. Other
+//! record types answer through [`AlignmentRecord::read_group_tag`].
+
+use std::collections::HashMap;
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::Aux;
+
+use crate::alignment_from_obj;
+use crate::record::AlignmentRecord;
+
+/// SM, LB and PL of one `@RG` line.
+#[derive(Debug, Default)]
+struct ReadGroup {
+    sample: Option<String>,
+    library: Option<String>,
+    platform: Option<String>,
+}
+
+/// Identity of a header: the view's address, its text buffer and length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeaderKey {
+    view: *const bam::HeaderView,
+    text: *const std::ffi::c_char,
+    len: u32,
+}
+
+impl HeaderKey {
+    fn of(header: &bam::HeaderView) -> Self {
+        let inner = header.inner();
+        Self {
+            view: header,
+            text: inner.text,
+            len: inner.l_text,
+        }
+    }
+}
+
+/// `@RG` lines of the header last seen, stored in an isolate slot.
+#[derive(Default)]
+pub(crate) struct ReadGroupCache {
+    /// Header the groups were parsed from.
+    header: Option<HeaderKey>,
+    groups: HashMap<Vec<u8>, ReadGroup>,
+}
+
+impl ReadGroupCache {
+    fn refresh(&mut self, header: &bam::HeaderView) {
+        if self.header != Some(HeaderKey::of(header)) {
+            self.reset(header);
+        }
+    }
+
+    /// Parse `header` even if it looks like the cached one.
+    pub(crate) fn reset(&mut self, header: &bam::HeaderView) {
+        self.header = Some(HeaderKey::of(header));
+        self.groups = parse_read_groups(header.as_bytes());
+    }
+}
+
+fn parse_read_groups(text: &[u8]) -> HashMap<Vec<u8>, ReadGroup> {
+    let mut groups = HashMap::new();
+    for line in text.split(|&b| b == b'\n') {
+        let Some(fields) = line.strip_prefix(b"@RG\t") else {
+            continue;
+        };
+        let mut id = None;
+        let mut rg = ReadGroup::default();
+        for field in fields.split(|&b| b == b'\t') {
+            let value = || String::from_utf8_lossy(&field[3..]).into_owned();
+            match field.get(..3) {
+                Some(b"ID:") => id = Some(field[3..].to_vec()),
+                Some(b"SM:") => rg.sample = Some(value()),
+                Some(b"LB:") => rg.library = Some(value()),
+                Some(b"PL:") => rg.platform = Some(value()),
+                _ => {}
+            }
+        }
+        if let Some(id) = id {
+            groups.insert(id, rg);
+        }
+    }
+    groups
+}
+
+/// Value of `tag` on the `@RG` line with ID `id` in SAM header `text`.
+pub(crate) fn header_read_group_tag(text: &[u8], id: &[u8], tag: &[u8; 2]) -> Option<String> {
+    text.split(|&b| b == b'\n')
+        .filter_map(|line| line.strip_prefix(b"@RG\t"))
+        .find(|fields| {
+            fields
+                .split(|&b| b == b'\t')
+                .any(|f| f.strip_prefix(b"ID:") == Some(id))
+        })?
+        .split(|&b| b == b'\t')
+        .find(|f| f.len() >= 3 && f[..2] == tag[..] && f[2] == b':')
+        .map(|f| String::from_utf8_lossy(&f[3..]).into_owned())
+}
+
+fn read_group_id(rec: &dyn AlignmentRecord) -> Option<&str> {
+    match rec.aux(b"RG") {
+        Some(Aux::String(id)) => Some(id),
+        _ => None,
+    }
+}
+
+/// Look up one field of the current record's read group and return it, or
+/// `null` when the record has no RG tag or the header lacks the field.
+fn read_group_field(
+    scope: &mut v8::PinScope,
+    args: &v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+    tag: &[u8; 2],
+    field: fn(&ReadGroup) -> &Option<String>,
+) {
+    let this = args.this();
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    let value = read_group_id(rec).and_then(|id| match rec.htslib() {
+        Some((_, header)) => {
+            let cache = scope.get_slot_mut::<ReadGroupCache>()?;
+            cache.refresh(header);
+            field(cache.groups.get(id.as_bytes())?).clone()
+        }
+        None => rec.read_group_tag(id.as_bytes(), tag),
+    });
+    match value {
+        Some(v) => rv.set(v8::String::new(scope, &v).unwrap().into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_read_group_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    match read_group_id(rec) {
+        Some(id) => rv.set(v8::String::new(scope, id).unwrap().into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_sample_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    read_group_field(scope, &args, rv, b"SM", |rg| &rg.sample);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_library_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    read_group_field(scope, &args, rv, b"LB", |rg| &rg.library);
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_platform_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    read_group_field(scope, &args, rv, b"PL", |rg| &rg.platform);
+}
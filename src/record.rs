@@ -0,0 +1,341 @@
+//! The record behind `aln`: [`AlignmentRecord`] is what every `aln`
+//! accessor calls through, so the engine can filter alignment types other
+//! than `bam::Record` without converting them.
+//!
+//! [`HtslibAlignment`] pairs a `bam::Record` with its header. With the
+//! `noodles` feature, [`NoodlesAlignment`] does the same for a noodles
+//! `RecordBuf`. Methods with defaults describe data some record types lack
+//! (mate, sequence, header); the accessors built on them then see an
+//! unpaired read without bases or read groups. A few accessors still take
+//! shortcuts for htslib records, e.g. read groups are looked up in a parsed
+//! copy of the header rather than through [`AlignmentRecord::read_group_tag`].
+
+use std::borrow::Cow;
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+
+use crate::read_group::header_read_group_tag;
+
+/// An aligned read as seen by the scripting API.
+pub trait AlignmentRecord {
+    /// Read name.
+    fn qname(&self) -> &[u8];
+
+    /// SAM FLAG.
+    fn flags(&self) -> u16;
+
+    /// Mapping quality; 255 when unavailable.
+    fn mapq(&self) -> u8;
+
+    /// Name of the reference the read is placed on; `None` without one.
+    fn chrom(&self) -> Option<&[u8]>;
+
+    /// 0-based leftmost position; -1 without one.
+    fn pos(&self) -> i64;
+
+    /// CIGAR operations.
+    fn cigar(&self) -> CigarString;
+
+    /// 0-based exclusive end of the reference span.
+    fn end(&self) -> i64 {
+        let ref_len: i64 = self
+            .cigar()
+            .iter()
+            .map(|op| match *op {
+                Cigar::Match(n)
+                | Cigar::Del(n)
+                | Cigar::RefSkip(n)
+                | Cigar::Equal(n)
+                | Cigar::Diff(n) => n as i64,
+                _ => 0,
+            })
+            .sum();
+        self.pos() + ref_len
+    }
+
+    /// Value of the aux tag `tag`, `None` if absent.
+    fn aux(&self, tag: &[u8; 2]) -> Option<Aux<'_>>;
+
+    /// All aux tags, in record order.
+    fn aux_tags(&self) -> Vec<([u8; 2], Aux<'_>)>;
+
+    /// Name of the reference the mate is placed on; `None` without one.
+    fn mate_chrom(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// 0-based leftmost position of the mate; -1 without one.
+    fn mate_pos(&self) -> i64 {
+        -1
+    }
+
+    /// Observed template length (TLEN); 0 when unavailable.
+    fn insert_size(&self) -> i64 {
+        0
+    }
+
+    /// Read bases as ASCII letters; empty when not stored.
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&[])
+    }
+
+    /// Number of stored bases.
+    fn seq_len(&self) -> usize {
+        self.seq().len()
+    }
+
+    /// Base qualities without the +33 offset; empty when not stored.
+    fn qual(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Value of `tag` (e.g. `SM`) on the header's `@RG` line with ID `id`;
+    /// `None` when the header has no such line or field.
+    fn read_group_tag(&self, id: &[u8], tag: &[u8; 2]) -> Option<String> {
+        let _ = (id, tag);
+        None
+    }
+
+    /// The underlying htslib record and header, for accessors that need
+    /// more than this trait offers.
+    fn htslib(&self) -> Option<(&bam::Record, &bam::HeaderView)> {
+        None
+    }
+}
+
+/// A `bam::Record` and the header its reference ids refer to.
+#[derive(Clone, Copy)]
+pub struct HtslibAlignment<'a> {
+    pub record: &'a bam::Record,
+    pub header: &'a bam::HeaderView,
+}
+
+impl<'a> HtslibAlignment<'a> {
+    pub fn new(record: &'a bam::Record, header: &'a bam::HeaderView) -> Self {
+        Self { record, header }
+    }
+}
+
+impl AlignmentRecord for HtslibAlignment<'_> {
+    fn qname(&self) -> &[u8] {
+        self.record.qname()
+    }
+
+    fn flags(&self) -> u16 {
+        self.record.flags()
+    }
+
+    fn mapq(&self) -> u8 {
+        self.record.mapq()
+    }
+
+    fn chrom(&self) -> Option<&[u8]> {
+        let tid = self.record.tid();
+        (tid >= 0).then(|| self.header.tid2name(tid as u32))
+    }
+
+    fn pos(&self) -> i64 {
+        self.record.pos()
+    }
+
+    fn cigar(&self) -> CigarString {
+        self.record.cigar().take()
+    }
+
+    fn end(&self) -> i64 {
+        self.record.cigar().end_pos()
+    }
+
+    fn aux(&self, tag: &[u8; 2]) -> Option<Aux<'_>> {
+        self.record.aux(tag).ok()
+    }
+
+    fn aux_tags(&self) -> Vec<([u8; 2], Aux<'_>)> {
+        self.record
+            .aux_iter()
+            .filter_map(|field| {
+                let (tag, aux) = field.ok()?;
+                Some((<[u8; 2]>::try_from(tag).ok()?, aux))
+            })
+            .collect()
+    }
+
+    fn mate_chrom(&self) -> Option<&[u8]> {
+        let tid = self.record.mtid();
+        (tid >= 0).then(|| self.header.tid2name(tid as u32))
+    }
+
+    fn mate_pos(&self) -> i64 {
+        self.record.mpos()
+    }
+
+    fn insert_size(&self) -> i64 {
+        self.record.insert_size()
+    }
+
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.record.seq().as_bytes())
+    }
+
+    fn seq_len(&self) -> usize {
+        self.record.seq_len()
+    }
+
+    fn qual(&self) -> &[u8] {
+        self.record.qual()
+    }
+
+    fn read_group_tag(&self, id: &[u8], tag: &[u8; 2]) -> Option<String> {
+        header_read_group_tag(self.header.as_bytes(), id, tag)
+    }
+
+    fn htslib(&self) -> Option<(&bam::Record, &bam::HeaderView)> {
+        Some((self.record, self.header))
+    }
+}
+
+#[cfg(feature = "noodles")]
+pub use self::noodles::NoodlesAlignment;
+
+#[cfg(feature = "noodles")]
+mod noodles {
+    use std::borrow::Cow;
+
+    use noodles_sam::alignment::RecordBuf;
+    use noodles_sam::alignment::record::cigar::op::Kind;
+    use noodles_sam::alignment::record::data::field::Tag;
+    use noodles_sam::alignment::record_buf::data::field::Value;
+    use noodles_sam::alignment::record_buf::data::field::value::Array;
+    use rust_htslib::bam::record::{Aux, AuxArray, Cigar, CigarString};
+
+    use super::AlignmentRecord;
+
+    /// A noodles `RecordBuf` and the header its reference ids refer to.
+    #[derive(Clone, Copy)]
+    pub struct NoodlesAlignment<'a> {
+        pub record: &'a RecordBuf,
+        pub header: &'a noodles_sam::Header,
+    }
+
+    impl<'a> NoodlesAlignment<'a> {
+        pub fn new(record: &'a RecordBuf, header: &'a noodles_sam::Header) -> Self {
+            Self { record, header }
+        }
+    }
+
+    impl AlignmentRecord for NoodlesAlignment<'_> {
+        fn qname(&self) -> &[u8] {
+            self.record.name().map_or(&b"*"[..], |name| &name[..])
+        }
+
+        fn flags(&self) -> u16 {
+            self.record.flags().bits()
+        }
+
+        fn mapq(&self) -> u8 {
+            self.record.mapping_quality().map_or(255, |q| q.get())
+        }
+
+        fn chrom(&self) -> Option<&[u8]> {
+            let id = self.record.reference_sequence_id()?;
+            let (name, _) = self.header.reference_sequences().get_index(id)?;
+            Some(&name[..])
+        }
+
+        fn pos(&self) -> i64 {
+            self.record
+                .alignment_start()
+                .map_or(-1, |p| p.get() as i64 - 1)
+        }
+
+        fn cigar(&self) -> CigarString {
+            let ops = self.record.cigar().as_ref().iter().map(|op| {
+                let n = op.len() as u32;
+                match op.kind() {
+                    Kind::Match => Cigar::Match(n),
+                    Kind::Insertion => Cigar::Ins(n),
+                    Kind::Deletion => Cigar::Del(n),
+                    Kind::Skip => Cigar::RefSkip(n),
+                    Kind::SoftClip => Cigar::SoftClip(n),
+                    Kind::HardClip => Cigar::HardClip(n),
+                    Kind::Pad => Cigar::Pad(n),
+                    Kind::SequenceMatch => Cigar::Equal(n),
+                    Kind::SequenceMismatch => Cigar::Diff(n),
+                }
+            });
+            CigarString(ops.collect())
+        }
+
+        fn aux(&self, tag: &[u8; 2]) -> Option<Aux<'_>> {
+            value_to_aux(self.record.data().get(&Tag::new(tag[0], tag[1]))?)
+        }
+
+        fn aux_tags(&self) -> Vec<([u8; 2], Aux<'_>)> {
+            self.record
+                .data()
+                .iter()
+                .filter_map(|(tag, value)| Some((*tag.as_ref(), value_to_aux(value)?)))
+                .collect()
+        }
+
+        fn mate_chrom(&self) -> Option<&[u8]> {
+            let id = self.record.mate_reference_sequence_id()?;
+            let (name, _) = self.header.reference_sequences().get_index(id)?;
+            Some(&name[..])
+        }
+
+        fn mate_pos(&self) -> i64 {
+            self.record
+                .mate_alignment_start()
+                .map_or(-1, |p| p.get() as i64 - 1)
+        }
+
+        fn insert_size(&self) -> i64 {
+            self.record.template_length() as i64
+        }
+
+        fn seq(&self) -> Cow<'_, [u8]> {
+            Cow::Borrowed(self.record.sequence().as_ref())
+        }
+
+        fn qual(&self) -> &[u8] {
+            self.record.quality_scores().as_ref()
+        }
+
+        fn read_group_tag(&self, id: &[u8], tag: &[u8; 2]) -> Option<String> {
+            let (_, read_group) = self
+                .header
+                .read_groups()
+                .iter()
+                .find(|(name, _)| name[..] == *id)?;
+            let (_, value) = read_group
+                .other_fields()
+                .iter()
+                .find(|(t, _)| t.as_ref() == tag)?;
+            Some(String::from_utf8_lossy(value).into_owned())
+        }
+    }
+
+    fn value_to_aux(value: &Value) -> Option<Aux<'_>> {
+        Some(match value {
+            Value::Character(c) => Aux::Char(*c),
+            Value::Int8(v) => Aux::I8(*v),
+            Value::UInt8(v) => Aux::U8(*v),
+            Value::Int16(v) => Aux::I16(*v),
+            Value::UInt16(v) => Aux::U16(*v),
+            Value::Int32(v) => Aux::I32(*v),
+            Value::UInt32(v) => Aux::U32(*v),
+            Value::Float(v) => Aux::Float(*v),
+            Value::String(s) => Aux::String(std::str::from_utf8(s).ok()?),
+            Value::Hex(s) => Aux::HexByteArray(std::str::from_utf8(s).ok()?),
+            Value::Array(Array::Int8(v)) => Aux::ArrayI8(AuxArray::from(v)),
+            Value::Array(Array::UInt8(v)) => Aux::ArrayU8(AuxArray::from(v)),
+            Value::Array(Array::Int16(v)) => Aux::ArrayI16(AuxArray::from(v)),
+            Value::Array(Array::UInt16(v)) => Aux::ArrayU16(AuxArray::from(v)),
+            Value::Array(Array::Int32(v)) => Aux::ArrayI32(AuxArray::from(v)),
+            Value::Array(Array::UInt32(v)) => Aux::ArrayU32(AuxArray::from(v)),
+            Value::Array(Array::Float(v)) => Aux::ArrayFloat(AuxArray::from(v)),
+        })
+    }
+}
@@ -0,0 +1,212 @@
+//! Named BED region sets exposed to scripts as `regions.<name>`.
+//!
+//! Intervals are merged and sorted per chromosome when loaded, so an overlap
+//! query is a binary search instead of a scan in JS.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+
+use crate::{alignment_from_obj, open_text_input};
+
+/// Sorted, non-overlapping half-open intervals per chromosome.
+#[derive(Debug, Clone, Default)]
+pub struct RegionSet {
+    by_chrom: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl RegionSet {
+    /// Load a BED file (optionally gzipped); only the first three columns
+    /// are used. Columns are tab-separated, or split on whitespace in lines
+    /// without tabs. `track`, `browser` and `#` lines are skipped.
+    pub fn from_bed(path: &Path) -> Result<Self> {
+        let reader = open_text_input(path)?;
+        let mut intervals = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.with_context(|| format!("failed to read {}", path.display()))?;
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+            let mut fields: Box<dyn Iterator<Item = &str>> = if line.contains('\t') {
+                Box::new(line.split('\t'))
+            } else {
+                Box::new(line.split_whitespace())
+            };
+            let (Some(chrom), Some(start), Some(end)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                bail!("{}:{}: expected at least 3 columns", path.display(), i + 1);
+            };
+            let parse = |s: &str| {
+                s.trim().parse::<u64>().with_context(|| {
+                    format!("{}:{}: invalid coordinate {s:?}", path.display(), i + 1)
+                })
+            };
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end < start {
+                bail!(
+                    "{}:{}: end {end} is before start {start}",
+                    path.display(),
+                    i + 1
+                );
+            }
+            intervals.push((chrom.trim().to_string(), start, end));
+        }
+        Ok(Self::from_intervals(intervals))
+    }
+
+    /// Build a set from `(chrom, start, end)` intervals (0-based, half-open).
+    pub fn from_intervals(intervals: impl IntoIterator<Item = (String, u64, u64)>) -> Self {
+        let mut by_chrom: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for (chrom, start, end) in intervals {
+            by_chrom.entry(chrom).or_default().push((start, end));
+        }
+        for ivs in by_chrom.values_mut() {
+            ivs.sort_unstable();
+            let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ivs.len());
+            for &(start, end) in ivs.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *ivs = merged;
+        }
+        Self { by_chrom }
+    }
+
+    /// True if any interval overlaps `[start, end)` on `chrom`. An empty
+    /// range is treated as the single base at `start`.
+    pub fn overlaps(&self, chrom: &str, start: u64, end: u64) -> bool {
+        let Some(ivs) = self.by_chrom.get(chrom) else {
+            return false;
+        };
+        // First interval ending after `start`; ends are sorted because the
+        // intervals are merged.
+        let i = ivs.partition_point(|&(_, e)| e <= start);
+        i < ivs.len() && ivs[i].0 < end.max(start + 1)
+    }
+
+    /// True if `pos` (0-based) lies in an interval on `chrom`.
+    pub fn contains(&self, chrom: &str, pos: u64) -> bool {
+        self.overlaps(chrom, pos, pos + 1)
+    }
+}
+
+/// Region sets of the engine, indexed by the `data` of the JS functions.
+pub(crate) struct RegionSets(pub(crate) Vec<Arc<RegionSet>>);
+
+/// Install the `regions` global with one `{overlaps, contains}` object per
+/// named set.
+pub(crate) fn install(
+    scope: &mut v8::ContextScope<'_, '_, v8::HandleScope<'_>>,
+    global: v8::Local<v8::Object>,
+    names: &[&str],
+) {
+    let regions = v8::Object::new(scope);
+    for (i, name) in names.iter().enumerate() {
+        let set = v8::Object::new(scope);
+        let data = v8::Integer::new_from_unsigned(scope, i as u32);
+        let overlaps = v8::Function::builder(regions_overlaps_callback)
+            .data(data.into())
+            .build(scope)
+            .unwrap();
+        let contains = v8::Function::builder(regions_contains_callback)
+            .data(data.into())
+            .build(scope)
+            .unwrap();
+        let key = v8::String::new(scope, "overlaps").unwrap();
+        set.set(scope, key.into(), overlaps.into());
+        let key = v8::String::new(scope, "contains").unwrap();
+        set.set(scope, key.into(), contains.into());
+        let key = v8::String::new(scope, name).unwrap();
+        regions.set(scope, key.into(), set.into());
+    }
+    let key = v8::String::new(scope, "regions").unwrap();
+    global.set(scope, key.into(), regions.into());
+}
+
+fn region_set(scope: &mut v8::PinScope, args: &v8::FunctionCallbackArguments) -> Arc<RegionSet> {
+    let index = args.data().uint32_value(scope).unwrap_or(0) as usize;
+    scope.get_slot::<RegionSets>().unwrap().0[index].clone()
+}
+
+/// Arguments as `[chrom, start, end)`: either an `aln` object (its
+/// reference span) or explicit chrom/start/end. `None` for unmapped reads.
+fn span_from_args(
+    scope: &mut v8::PinScope,
+    args: &v8::FunctionCallbackArguments,
+) -> Option<(String, u64, u64)> {
+    let first = args.get(0);
+    if let Ok(obj) = v8::Local::<v8::Object>::try_from(first)
+        && obj.internal_field_count() == 3
+    {
+        let rec = alignment_from_obj(scope, obj)?;
+        let chrom = String::from_utf8_lossy(rec.chrom()?).into_owned();
+        return Some((chrom, rec.pos() as u64, rec.end().max(rec.pos()) as u64));
+    }
+    let chrom = first.to_rust_string_lossy(scope);
+    let start = args.get(1).integer_value(scope).unwrap_or(0).max(0) as u64;
+    let end = match args.get(2) {
+        v if v.is_undefined() => start + 1,
+        v => v.integer_value(scope).unwrap_or(0).max(0) as u64,
+    };
+    Some((chrom, start, end))
+}
+
+/// `regions.<name>.overlaps(aln)` or `regions.<name>.overlaps(chrom, start, end)`.
+#[allow(clippy::needless_pass_by_value)]
+fn regions_overlaps_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let set = region_set(scope, &args);
+    let hit = span_from_args(scope, &args)
+        .is_some_and(|(chrom, start, end)| set.overlaps(&chrom, start, end));
+    rv.set(v8::Boolean::new(scope, hit).into());
+}
+
+/// `regions.<name>.contains(chrom, pos)`.
+#[allow(clippy::needless_pass_by_value)]
+fn regions_contains_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let set = region_set(scope, &args);
+    let chrom = args.get(0).to_rust_string_lossy(scope);
+    let hit = args
+        .get(1)
+        .integer_value(scope)
+        .is_some_and(|pos| pos >= 0 && set.contains(&chrom, pos as u64));
+    rv.set(v8::Boolean::new(scope, hit).into());
+}
+
+/// `aln.overlaps(chrom, start, end)`: true if the read's reference span
+/// overlaps `[start, end)` on `chrom`.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_overlaps_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let this = args.this();
+    let Some(rec) = alignment_from_obj(scope, this) else {
+        return;
+    };
+    let chrom = args.get(0).to_rust_string_lossy(scope);
+    let start = args.get(1).integer_value(scope).unwrap_or(0);
+    let end = args.get(2).integer_value(scope).unwrap_or(0);
+    let hit = rec.chrom() == Some(chrom.as_bytes())
+        && rec.pos() < end
+        && rec.end().max(rec.pos() + 1) > start;
+    rv.set(v8::Boolean::new(scope, hit).into());
+}
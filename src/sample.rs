@@ -0,0 +1,42 @@
+//! Deterministic read subsampling by hash of the query name.
+
+/// Keeps a fixed fraction of reads, chosen by hashing the qname with a seed.
+/// Both mates of a pair share a qname, so they are kept or dropped together,
+/// and the same seed selects the same reads on every run.
+#[derive(Debug, Clone, Copy)]
+pub struct QnameSampler {
+    threshold: u64,
+    seed: u64,
+}
+
+impl QnameSampler {
+    /// `fraction` is clamped to `[0, 1]`.
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let threshold = if fraction >= 1.0 {
+            u64::MAX
+        } else {
+            (fraction * u64::MAX as f64) as u64
+        };
+        Self { threshold, seed }
+    }
+
+    /// Whether the read named `qname` is in the sample.
+    #[inline]
+    pub fn keep(&self, qname: &[u8]) -> bool {
+        if self.threshold == u64::MAX {
+            return true;
+        }
+        qname_hash(qname, self.seed) < self.threshold
+    }
+}
+
+/// Well-mixed 64-bit hash of a qname: FNV-1a followed by the splitmix64
+/// finalizer so that thresholds on the full range are uniform.
+#[inline]
+pub fn qname_hash(qname: &[u8], seed: u64) -> u64 {
+    let mut z = crate::fnv1a64(&[&seed.to_le_bytes(), qname]);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,218 @@
+//! Startup snapshots and on-disk code cache.
+//!
+//! Creating an isolate, installing the helper globals and compiling the
+//! filter dominate runtime when v8bam is run over many tiny files. A startup
+//! snapshot captures a context with the helpers and the object templates
+//! already built, and the code cache lets repeated runs of the same filter skip
+//! parsing and compilation.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use v8::MapFnTo;
+
+use crate::{
+    barcode, clone, dedup, depth, fastq, fnv1a64, init_v8_once, install_rust_helpers, longread,
+    make_aln_template, read_group, sv, typed, vcf,
+};
+
+/// Index of the `aln` ObjectTemplate in the snapshot's isolate data.
+pub(crate) const ALN_TEMPLATE_INDEX: usize = 0;
+/// Index of the FASTQ `read` ObjectTemplate in the snapshot's isolate data.
+pub(crate) const READ_TEMPLATE_INDEX: usize = 1;
+/// Index of the VCF `variant` ObjectTemplate in the snapshot's isolate data.
+pub(crate) const VARIANT_TEMPLATE_INDEX: usize = 2;
+
+/// Defines [`external_references`] and the matching list of names, which
+/// [`snapshot_key`] hashes so that adding, removing or reordering a callback
+/// invalidates cached snapshots without a manual version bump.
+macro_rules! external_references {
+    ($($kind:ident: $callback:path,)*) => {
+        /// Rust callbacks referenced from the snapshot. V8 serializes these
+        /// as indices into this list, so it must be identical when creating
+        /// and when consuming a snapshot.
+        pub(crate) fn external_references() -> Cow<'static, [v8::ExternalReference]> {
+            Cow::Owned(vec![
+                $(v8::ExternalReference {
+                    $kind: $callback.map_fn_to(),
+                },)*
+            ])
+        }
+
+        /// `"<kind> <path>"` of each entry of [`external_references`], in order.
+        const EXTERNAL_REFERENCE_NAMES: &[&str] =
+            &[$(concat!(stringify!($kind), " ", stringify!($callback)),)*];
+    };
+}
+
+external_references! {
+    function: crate::has_flag_callback,
+    function: dedup::seen_callback,
+    function: dedup::seen_clear_callback,
+    function: barcode::hamming_callback,
+    function: depth::depth_callback,
+    function: crate::aln_aux_method,
+    function: crate::regions::aln_overlaps_method,
+    function: clone::aln_clone_method,
+    function: longread::aln_supplementary_alignments_method,
+    function: longread::aln_modifications_method,
+    function: sv::aln_is_discordant_method,
+    function: crate::console::console_log_callback,
+    function: crate::console::console_warn_callback,
+    function: crate::console::console_error_callback,
+    function: crate::console::console_debug_callback,
+    getter: crate::aln_mapq_getter,
+    getter: crate::aln_qname_getter,
+    getter: crate::aln_flag_getter,
+    getter: crate::aln_flag_bit_getter,
+    getter: crate::aln_pos_getter,
+    getter: crate::aln_end_getter,
+    getter: crate::aln_chrom_getter,
+    getter: crate::aln_cigar_getter,
+    getter: crate::aln_source_file_getter,
+    getter: crate::aln_source_index_getter,
+    getter: read_group::aln_read_group_getter,
+    getter: read_group::aln_sample_getter,
+    getter: read_group::aln_library_getter,
+    getter: read_group::aln_platform_getter,
+    getter: dedup::aln_unclipped_start_getter,
+    getter: dedup::aln_unclipped_end_getter,
+    getter: dedup::aln_five_prime_getter,
+    getter: barcode::aln_umi_getter,
+    getter: barcode::aln_cell_barcode_getter,
+    getter: typed::aln_seq_bytes_getter,
+    getter: typed::aln_qual_bytes_getter,
+    getter: sv::aln_is_split_getter,
+    getter: sv::aln_clip_side_getter,
+    getter: sv::aln_mate_end_estimate_getter,
+    getter: fastq::read_name_getter,
+    getter: fastq::read_comment_getter,
+    getter: fastq::read_seq_getter,
+    getter: fastq::read_qual_getter,
+    getter: fastq::read_length_getter,
+    function: vcf::variant_info_method,
+    function: vcf::variant_format_method,
+    getter: vcf::variant_chrom_getter,
+    getter: vcf::variant_pos_getter,
+    getter: vcf::variant_end_getter,
+    getter: vcf::variant_id_getter,
+    getter: vcf::variant_ref_getter,
+    getter: vcf::variant_alts_getter,
+    getter: vcf::variant_qual_getter,
+    getter: vcf::variant_filters_getter,
+    getter: vcf::variant_samples_getter,
+}
+
+/// Identifies snapshots this build can consume: the crate version and the
+/// names and order of the external references.
+pub fn snapshot_key() -> u64 {
+    let mut parts: Vec<&[u8]> = vec![env!("CARGO_PKG_VERSION").as_bytes()];
+    for name in EXTERNAL_REFERENCE_NAMES {
+        parts.push(b"\n");
+        parts.push(name.as_bytes());
+    }
+    fnv1a64(&parts)
+}
+
+/// Build a startup snapshot containing the helper globals in the default
+/// context and the `aln`, `read` and `variant` templates as isolate data.
+pub fn create_startup_snapshot() -> Result<Vec<u8>> {
+    init_v8_once();
+
+    let mut creator = v8::Isolate::snapshot_creator(Some(external_references()), None);
+    {
+        v8::scope!(let hs, &mut creator);
+        let context = v8::Context::new(hs, Default::default());
+        v8::scope_with_context!(let scope, hs, context);
+
+        install_rust_helpers(scope, context);
+        let aln_tmpl = make_aln_template(scope);
+        let index = scope.add_isolate_data(aln_tmpl);
+        debug_assert_eq!(index, ALN_TEMPLATE_INDEX);
+        let read_tmpl = fastq::make_read_template(scope);
+        let index = scope.add_isolate_data(read_tmpl);
+        debug_assert_eq!(index, READ_TEMPLATE_INDEX);
+        let variant_tmpl = vcf::make_variant_template(scope);
+        let index = scope.add_isolate_data(variant_tmpl);
+        debug_assert_eq!(index, VARIANT_TEMPLATE_INDEX);
+
+        scope.set_default_context(context);
+    }
+
+    let blob = creator
+        .create_blob(v8::FunctionCodeHandling::Keep)
+        .ok_or_else(|| anyhow!("failed to create V8 startup snapshot"))?;
+    Ok(blob.to_vec())
+}
+
+/// Load the startup snapshot from `path`, creating and writing it first if
+/// it does not exist yet.
+pub fn load_or_create_snapshot(path: &Path) -> Result<Vec<u8>> {
+    if let Ok(blob) = fs::read(path) {
+        return Ok(blob);
+    }
+    let blob = create_startup_snapshot()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    write_atomically(path, &blob)?;
+    Ok(blob)
+}
+
+/// Default snapshot location inside a cache directory. The V8 version and
+/// [`snapshot_key`] are part of the name because snapshots are not portable
+/// across V8 versions or changes to the external references.
+pub fn snapshot_path(cache_dir: &Path) -> PathBuf {
+    cache_dir.join(format!(
+        "snapshot-{}-{:016x}.bin",
+        v8::V8::get_version(),
+        snapshot_key()
+    ))
+}
+
+/// Compiled-code cache stored on disk, keyed by a hash of the script source.
+#[derive(Debug, Clone)]
+pub struct CodeCache {
+    dir: PathBuf,
+}
+
+impl CodeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path_for(&self, source: &str) -> PathBuf {
+        let key = fnv1a64(&[v8::V8::get_version().as_bytes(), source.as_bytes()]);
+        self.dir.join(format!("{:016x}.code", key))
+    }
+
+    /// Cached data for `source`, if present.
+    pub fn load(&self, source: &str) -> Option<Vec<u8>> {
+        fs::read(self.path_for(source)).ok()
+    }
+
+    /// Store cached data for `source`. Failures are not fatal for filtering,
+    /// so callers usually just log them.
+    pub fn store(&self, source: &str, data: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        write_atomically(&self.path_for(source), data)
+    }
+
+    /// Remove a rejected cache entry so it is regenerated on the next run.
+    pub fn remove(&self, source: &str) {
+        let _ = fs::remove_file(self.path_for(source));
+    }
+}
+
+/// Write via a temporary file and rename so concurrent runs never observe a
+/// partially written blob.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&tmp, data).with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
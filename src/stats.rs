@@ -0,0 +1,148 @@
+//! Run statistics, written as JSON with `--stats-json`.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rust_htslib::bam;
+use serde_json::{Value, json};
+
+/// SAM flag bits reported separately in [`RunStats`].
+pub const FLAG_CATEGORIES: [(&str, u16); 8] = [
+    ("paired", 0x1),
+    ("proper_pair", 0x2),
+    ("unmapped", 0x4),
+    ("reverse", 0x10),
+    ("secondary", 0x100),
+    ("qcfail", 0x200),
+    ("duplicate", 0x400),
+    ("supplementary", 0x800),
+];
+
+/// Counters and timings for one filtering run.
+#[derive(Debug)]
+pub struct RunStats {
+    /// Whether JS and I/O time are measured; off by default because
+    /// reading the clock per record is not free.
+    timing: bool,
+    started: Instant,
+
+    pub records_read: u64,
+    /// Records dropped by `--sample` before reaching the filter.
+    pub records_sampled_out: u64,
+    pub records_passed: u64,
+    pub records_written: u64,
+    /// Passing records not written because `--header-expr` removed their
+    /// reference.
+    pub records_removed_by_header: u64,
+    pub script_errors: u64,
+    /// (read, passed) per entry of [`FLAG_CATEGORIES`].
+    pub flag_counts: [(u64, u64); FLAG_CATEGORIES.len()],
+    pub js_time: Duration,
+    pub io_time: Duration,
+    pub peak_heap_bytes: usize,
+    /// Message of the error that ended the run, if any.
+    pub error: Option<String>,
+}
+
+impl RunStats {
+    pub fn new(timing: bool) -> Self {
+        Self {
+            timing,
+            started: Instant::now(),
+            records_read: 0,
+            records_sampled_out: 0,
+            records_passed: 0,
+            records_written: 0,
+            records_removed_by_header: 0,
+            script_errors: 0,
+            flag_counts: [(0, 0); FLAG_CATEGORIES.len()],
+            js_time: Duration::ZERO,
+            io_time: Duration::ZERO,
+            peak_heap_bytes: 0,
+            error: None,
+        }
+    }
+
+    /// Start a timer if timing is enabled.
+    #[inline]
+    pub fn timer(&self) -> Option<Instant> {
+        self.timing.then(Instant::now)
+    }
+
+    #[inline]
+    pub fn add_js_time(&mut self, timer: Option<Instant>) {
+        if let Some(t) = timer {
+            self.js_time += t.elapsed();
+        }
+    }
+
+    #[inline]
+    pub fn add_io_time(&mut self, timer: Option<Instant>) {
+        if let Some(t) = timer {
+            self.io_time += t.elapsed();
+        }
+    }
+
+    /// Count a record that reached the filter in the per-flag categories.
+    #[inline]
+    pub fn observe_flags(&mut self, rec: &bam::Record, passed: bool) {
+        let flags = rec.flags();
+        for (i, (_, mask)) in FLAG_CATEGORIES.iter().enumerate() {
+            if flags & mask != 0 {
+                self.flag_counts[i].0 += 1;
+                self.flag_counts[i].1 += passed as u64;
+            }
+        }
+    }
+
+    pub fn observe_heap(&mut self, used_bytes: usize) {
+        self.peak_heap_bytes = self.peak_heap_bytes.max(used_bytes);
+    }
+
+    pub fn to_json(&self) -> Value {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let filtered = self.records_read - self.records_sampled_out;
+        let flags: serde_json::Map<String, Value> = FLAG_CATEGORIES
+            .iter()
+            .zip(self.flag_counts.iter())
+            .map(|((name, _), (read, passed))| {
+                (name.to_string(), json!({ "read": read, "passed": passed }))
+            })
+            .collect();
+
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "records": {
+                "read": self.records_read,
+                "sampled_out": self.records_sampled_out,
+                "passed": self.records_passed,
+                "failed": filtered - self.records_passed,
+                "written": self.records_written,
+                "removed_by_header": self.records_removed_by_header,
+            },
+            "flags": flags,
+            "script_errors": self.script_errors,
+            "error": self.error,
+            "time": {
+                "wall_seconds": elapsed,
+                "js_seconds": self.timing.then(|| self.js_time.as_secs_f64()),
+                "io_seconds": self.timing.then(|| self.io_time.as_secs_f64()),
+            },
+            "records_per_second": if elapsed > 0.0 { self.records_read as f64 / elapsed } else { 0.0 },
+            "peak_v8_heap_bytes": self.peak_heap_bytes,
+        })
+    }
+
+    /// Write [`Self::to_json`] to `path` ("-" for stdout).
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.to_json())?;
+        if path.to_string_lossy() == "-" {
+            println!("{text}");
+            return Ok(());
+        }
+        fs::write(path, text + "\n")
+            .with_context(|| format!("failed to write stats to {}", path.display()))
+    }
+}
@@ -0,0 +1,142 @@
+//! Structural-variant helpers: `aln.isDiscordant(maxInsert)`, `aln.isSplit`,
+//! `aln.clipSide` and `aln.mateEndEstimate`, and the `--preset discordant`
+//! filter built on them.
+
+use rust_htslib::bam::record::{Aux, Cigar};
+
+use crate::alignment_from_obj;
+use crate::record::AlignmentRecord;
+
+/// Filter for `--preset discordant`: primary (neither secondary nor
+/// supplementary), non-duplicate, QC-passing mapped reads that are split or
+/// part of a discordant pair, i.e. the reads lumpy/smoove extract as SV
+/// evidence.
+pub const DISCORDANT_PRESET: &str = "!hasFlag(aln.flag, 0x4 | 0x100 | 0x200 | 0x400 | 0x800) \
+     && (aln.isSplit || aln.isDiscordant())";
+
+/// Whether both mates are mapped but not as a normal forward/reverse pair
+/// within `max_insert` bases. Without `max_insert`, the aligner's proper-pair
+/// flag decides the insert size check.
+fn is_discordant(rec: &dyn AlignmentRecord, max_insert: Option<i64>) -> bool {
+    let flags = rec.flags();
+    let has = |mask: u16| flags & mask != 0;
+    if !has(0x1) || has(0x4) || has(0x8) {
+        return false;
+    }
+    let reverse = has(0x10);
+    if rec.chrom() != rec.mate_chrom() || reverse == has(0x20) {
+        return true;
+    }
+    // Forward/reverse: the leftmost mate must be on the forward strand.
+    // Mates starting at the same position on opposite strands are FR either
+    // way.
+    let (pos, mpos) = (rec.pos(), rec.mate_pos());
+    if pos != mpos && (pos < mpos) == reverse {
+        return true;
+    }
+    match max_insert {
+        Some(max) => rec.insert_size().abs() > max,
+        None => !has(0x2),
+    }
+}
+
+/// Reference length of a CIGAR string such as the MC tag.
+fn cigar_ref_len(cigar: &str) -> i64 {
+    let mut len = 0;
+    let mut n = 0i64;
+    for c in cigar.bytes() {
+        match c {
+            b'0'..=b'9' => n = n * 10 + (c - b'0') as i64,
+            b'M' | b'D' | b'N' | b'=' | b'X' => {
+                len += n;
+                n = 0;
+            }
+            _ => n = 0,
+        }
+    }
+    len
+}
+
+/// `aln.isDiscordant(maxInsert?)`.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_is_discordant_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let max_insert = match args.get(0) {
+        v if v.is_null_or_undefined() => None,
+        v => v.integer_value(scope),
+    };
+    rv.set(v8::Boolean::new(scope, is_discordant(rec, max_insert)).into());
+}
+
+/// `aln.isSplit`: mapped with an SA tag.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_is_split_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let split = rec.flags() & 0x4 == 0 && matches!(rec.aux(b"SA"), Some(Aux::String(_)));
+    rv.set(v8::Boolean::new(scope, split).into());
+}
+
+/// `aln.clipSide`: `"left"`, `"right"` or `"both"` for soft/hard clips at
+/// the reference-left/right end of the alignment, null if unclipped.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_clip_side_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let cigar = rec.cigar();
+    let is_clip = |op: Option<&Cigar>| matches!(op, Some(Cigar::SoftClip(_) | Cigar::HardClip(_)));
+    let side = match (is_clip(cigar.first()), is_clip(cigar.last())) {
+        (true, true) if cigar.len() > 1 => Some("both"),
+        (true, _) => Some("left"),
+        (false, true) => Some("right"),
+        (false, false) => None,
+    };
+    match side {
+        Some(side) => rv.set(v8::String::new(scope, side).unwrap().into()),
+        None => rv.set(v8::null(scope).into()),
+    }
+}
+
+/// `aln.mateEndEstimate`: 0-based exclusive end of the mate, exact when the
+/// MC tag is present and otherwise assuming the mate aligns over as many
+/// reference bases as this read has query bases. Null if the mate is
+/// unmapped.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_mate_end_estimate_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let mpos = rec.mate_pos();
+    if rec.flags() & 0x1 == 0 || rec.flags() & 0x8 != 0 || mpos < 0 {
+        rv.set(v8::null(scope).into());
+        return;
+    }
+    let len = match rec.aux(b"MC") {
+        Some(Aux::String(mc)) => cigar_ref_len(mc),
+        _ => rec.seq_len() as i64,
+    };
+    rv.set(v8::Number::new(scope, (mpos + len) as f64).into());
+}
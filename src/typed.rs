@@ -0,0 +1,142 @@
+//! Typed-array access to record bytes: `aln.seqBytes`, `aln.qualBytes` and
+//! B-array aux tags.
+//!
+//! `seqBytes`/`qualBytes` are views over one ArrayBuffer each per engine,
+//! grown as needed and overwritten for every record, so reading them costs
+//! a memcpy-sized loop instead of an allocation plus one JS value per base.
+
+use rust_htslib::bam::record::Aux;
+
+use crate::alignment_from_obj;
+
+/// The reusable buffers behind `seqBytes` and `qualBytes`, stored in an
+/// isolate slot.
+#[derive(Default)]
+pub(crate) struct ByteViews {
+    seq: Option<v8::Global<v8::ArrayBuffer>>,
+    qual: Option<v8::Global<v8::ArrayBuffer>>,
+}
+
+/// A `Uint8Array` of `len` bytes over the buffer chosen by `which`, filled
+/// by `fill`. The buffer is replaced when it is too small or the script
+/// detached it (e.g. `aln.seqBytes.buffer.transfer()`). `None` if V8 could
+/// not allocate one.
+fn reusable_view<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    which: fn(&mut ByteViews) -> &mut Option<v8::Global<v8::ArrayBuffer>>,
+    len: usize,
+    fill: impl FnOnce(&mut [u8]),
+) -> Option<v8::Local<'s, v8::Value>> {
+    let existing = scope
+        .get_slot_mut::<ByteViews>()
+        .and_then(|views| which(views).clone())
+        .map(|global| v8::Local::new(scope, &global))
+        .filter(|buf| !buf.was_detached() && buf.byte_length() >= len);
+    let buf = match existing {
+        Some(buf) => buf,
+        None => {
+            let buf = v8::ArrayBuffer::new(scope, len.next_power_of_two().max(1024));
+            let global = v8::Global::new(scope, buf);
+            if let Some(views) = scope.get_slot_mut::<ByteViews>() {
+                *which(views) = Some(global);
+            }
+            buf
+        }
+    };
+    let data = buf.get_backing_store().data()?;
+    let bytes = unsafe { std::slice::from_raw_parts_mut(data.cast::<u8>().as_ptr(), len) };
+    fill(bytes);
+    v8::Uint8Array::new(scope, buf, 0, len).map(Into::into)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_seq_bytes_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    // Decode htslib's 4-bit bases straight into the buffer rather than
+    // through the allocating `AlignmentRecord::seq`.
+    let view = match rec.htslib() {
+        Some((rec, _)) => {
+            let seq = rec.seq();
+            reusable_view(
+                scope,
+                |v| &mut v.seq,
+                seq.len(),
+                |out| {
+                    for (i, b) in out.iter_mut().enumerate() {
+                        *b = seq[i];
+                    }
+                },
+            )
+        }
+        None => {
+            let seq = rec.seq();
+            reusable_view(
+                scope,
+                |v| &mut v.seq,
+                seq.len(),
+                |out| out.copy_from_slice(&seq),
+            )
+        }
+    };
+    if let Some(view) = view {
+        rv.set(view);
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn aln_qual_bytes_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = alignment_from_obj(scope, args.this()) else {
+        return;
+    };
+    let qual = rec.qual();
+    let view = reusable_view(
+        scope,
+        |v| &mut v.qual,
+        qual.len(),
+        |out| out.copy_from_slice(qual),
+    );
+    if let Some(view) = view {
+        rv.set(view);
+    }
+}
+
+/// Copy a B-array aux tag into a new typed array of the matching element
+/// type.
+macro_rules! typed_array {
+    ($scope:expr, $arr:expr, $js:ident) => {{
+        let len = $arr.len();
+        let bytes: Vec<u8> = $arr.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared();
+        let buf = v8::ArrayBuffer::with_backing_store($scope, &store);
+        v8::$js::new($scope, buf, 0, len).unwrap().into()
+    }};
+}
+
+/// Convert a B-array aux value; `None` for scalar types.
+pub(crate) fn aux_array_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    aux: &Aux<'_>,
+) -> Option<v8::Local<'s, v8::Value>> {
+    Some(match aux {
+        Aux::ArrayI8(arr) => typed_array!(scope, arr, Int8Array),
+        Aux::ArrayU8(arr) => typed_array!(scope, arr, Uint8Array),
+        Aux::ArrayI16(arr) => typed_array!(scope, arr, Int16Array),
+        Aux::ArrayU16(arr) => typed_array!(scope, arr, Uint16Array),
+        Aux::ArrayI32(arr) => typed_array!(scope, arr, Int32Array),
+        Aux::ArrayU32(arr) => typed_array!(scope, arr, Uint32Array),
+        Aux::ArrayFloat(arr) => typed_array!(scope, arr, Float32Array),
+        _ => return None,
+    })
+}
@@ -0,0 +1,461 @@
+//! VCF/BCF filtering with a `variant` object.
+//!
+//! [`JsVcfFilterEngine`] runs the same V8 machinery as
+//! [`crate::JsBamFilterEngine`]; the `variant` template follows the lazy
+//! accessor pattern of `aln`, with `info(key)` and `format(key, sample)`
+//! methods that decode values according to the header's declared types.
+
+use std::ffi::c_void;
+
+use anyhow::Result;
+use rust_htslib::bcf;
+use rust_htslib::bcf::header::{TagLength, TagType};
+use rust_htslib::bcf::record::Numeric;
+
+use crate::{EngineOptions, ScriptObject, ScriptRuntime, bound_pointer, emit, snapshot};
+
+const VECTOR_END_INTEGER: i32 = i32::MIN + 1;
+const VECTOR_END_FLOAT_BITS: u32 = 0x7F80_0002;
+
+pub(crate) const VARIANT_OBJECT: ScriptObject = ScriptObject {
+    param: "variant",
+    extra_params: &[],
+    make_template: make_variant_template,
+    snapshot_index: snapshot::VARIANT_TEMPLATE_INDEX,
+};
+
+/// Engine that filters VCF/BCF records with a JS expression over a
+/// `variant` object.
+pub struct JsVcfFilterEngine {
+    rt: ScriptRuntime,
+}
+
+impl JsVcfFilterEngine {
+    /// Create a new engine with a JS filter expression or body, e.g.
+    /// `"variant.qual >= 30 && variant.info('DP') > 10"`.
+    pub fn new(expr: &str) -> Result<Self> {
+        Self::with_options(expr, EngineOptions::default())
+    }
+
+    pub fn with_options(expr: &str, opts: EngineOptions) -> Result<Self> {
+        Ok(Self {
+            rt: ScriptRuntime::new(expr, &VARIANT_OBJECT, opts)?,
+        })
+    }
+
+    /// Run the JS filter on a single VCF/BCF record. The record carries its
+    /// own header, so none is passed separately.
+    pub fn record_passes(&mut self, rec: &bcf::Record) -> Result<bool> {
+        let ptr = rec as *const bcf::Record as *mut c_void;
+        self.rt
+            .call(&[ptr], |scope, result| result.boolean_value(scope))
+    }
+
+    /// Like [`crate::JsBamFilterEngine::record_emit`] for VCF/BCF records.
+    pub fn record_emit(&mut self, rec: &bcf::Record, line: &mut String) -> Result<bool> {
+        let ptr = rec as *const bcf::Record as *mut c_void;
+        self.rt.call(&[ptr], |scope, result| {
+            emit::value_to_line(scope, result, line)
+        })?
+    }
+
+    /// Bytes currently used on the V8 heap.
+    pub fn used_heap_size(&mut self) -> usize {
+        self.rt.used_heap_size()
+    }
+
+    /// Names of unknown `variant` properties read so far.
+    pub fn unknown_properties(&self) -> Vec<String> {
+        self.rt.unknown_properties()
+    }
+}
+
+/// Create an ObjectTemplate for `variant` with lazy accessors:
+/// chrom, pos, end, id, ref, alts, qual, filters, samples, info(key),
+/// format(key, sample).
+pub(crate) fn make_variant_template<'s>(
+    scope: &mut v8::ContextScope<'s, '_, v8::HandleScope<'_>>,
+) -> v8::Local<'s, v8::ObjectTemplate> {
+    let tmpl = v8::ObjectTemplate::new(scope);
+    // 0: bcf::Record
+    tmpl.set_internal_field_count(1);
+
+    let chrom = v8::String::new(scope, "chrom").unwrap();
+    tmpl.set_accessor(chrom.into(), variant_chrom_getter);
+
+    let pos = v8::String::new(scope, "pos").unwrap();
+    tmpl.set_accessor(pos.into(), variant_pos_getter);
+
+    let end = v8::String::new(scope, "end").unwrap();
+    tmpl.set_accessor(end.into(), variant_end_getter);
+
+    let id = v8::String::new(scope, "id").unwrap();
+    tmpl.set_accessor(id.into(), variant_id_getter);
+
+    let ref_ = v8::String::new(scope, "ref").unwrap();
+    tmpl.set_accessor(ref_.into(), variant_ref_getter);
+
+    let alts = v8::String::new(scope, "alts").unwrap();
+    tmpl.set_accessor(alts.into(), variant_alts_getter);
+
+    let qual = v8::String::new(scope, "qual").unwrap();
+    tmpl.set_accessor(qual.into(), variant_qual_getter);
+
+    let filters = v8::String::new(scope, "filters").unwrap();
+    tmpl.set_accessor(filters.into(), variant_filters_getter);
+
+    let samples = v8::String::new(scope, "samples").unwrap();
+    tmpl.set_accessor(samples.into(), variant_samples_getter);
+
+    let info_fn = v8::FunctionTemplate::new(scope, variant_info_method);
+    let info_name = v8::String::new(scope, "info").unwrap();
+    tmpl.set(info_name.into(), info_fn.into());
+
+    let format_fn = v8::FunctionTemplate::new(scope, variant_format_method);
+    let format_name = v8::String::new(scope, "format").unwrap();
+    tmpl.set(format_name.into(), format_fn.into());
+
+    tmpl
+}
+
+#[inline(always)]
+fn variant_from_obj<'s>(
+    scope: &mut v8::PinScope,
+    obj: v8::Local<v8::Object>,
+) -> Option<&'s bcf::Record> {
+    let ptr = bound_pointer::<bcf::Record>(scope, obj, 0)?;
+    Some(unsafe { &*ptr })
+}
+
+fn bytes_to_js<'s>(scope: &mut v8::PinScope<'s, '_>, bytes: &[u8]) -> v8::Local<'s, v8::Value> {
+    let s = String::from_utf8_lossy(bytes);
+    v8::String::new(scope, &s).unwrap().into()
+}
+
+fn strings_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    items: impl ExactSizeIterator<Item = impl AsRef<[u8]>>,
+) -> v8::Local<'s, v8::Value> {
+    let js_arr = v8::Array::new(scope, items.len() as i32);
+    for (i, item) in items.enumerate() {
+        let v = bytes_to_js(scope, item.as_ref());
+        js_arr.set_index(scope, i as u32, v);
+    }
+    js_arr.into()
+}
+
+// ========== Accessors ==========
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_chrom_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let chrom = rec
+        .rid()
+        .and_then(|rid| rec.header().rid2name(rid).ok())
+        .unwrap_or(b".");
+    rv.set(bytes_to_js(scope, chrom));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_pos_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    // 0-based, like aln.pos.
+    let v = v8::Number::new(scope, rec.pos() as f64);
+    rv.set(v.into());
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_end_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    // 0-based exclusive, like aln.end.
+    let v = v8::Number::new(scope, (rec.pos() + rec.rlen()) as f64);
+    rv.set(v.into());
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_id_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    rv.set(bytes_to_js(scope, &rec.id()));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_ref_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let alleles = rec.alleles();
+    rv.set(bytes_to_js(scope, alleles.first().copied().unwrap_or(b"")));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_alts_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let alleles = rec.alleles();
+    rv.set(strings_to_js(scope, alleles.iter().skip(1)));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_qual_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let qual = rec.qual();
+    if qual.is_missing() {
+        rv.set(v8::null(scope).into());
+    } else {
+        rv.set(v8::Number::new(scope, qual as f64).into());
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_filters_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let header = rec.header();
+    let names: Vec<Vec<u8>> = rec.filters().map(|id| header.id_to_name(id)).collect();
+    rv.set(strings_to_js(scope, names.iter()));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_samples_getter(
+    scope: &mut v8::PinScope,
+    _name: v8::Local<v8::Name>,
+    args: v8::PropertyCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let samples = rec.header().samples();
+    rv.set(strings_to_js(scope, samples.iter()));
+}
+
+// ========== Methods: variant.info(key), variant.format(key, sample) ==========
+
+/// `variant.info(key)`: a number/string for single-valued fields, an array
+/// otherwise, a boolean for flags, and `null` if absent, missing (`.`) or
+/// undeclared.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_info_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let key = args.get(0).to_rust_string_lossy(scope);
+    let key = key.as_bytes();
+
+    let Ok((ty, len)) = rec.header().info_type(key) else {
+        rv.set(v8::null(scope).into());
+        return;
+    };
+    let scalar = len == TagLength::Fixed(1);
+    let mut info = rec.info(key);
+    let value = match ty {
+        TagType::Flag => v8::Boolean::new(scope, info.flag().unwrap_or(false)).into(),
+        TagType::Integer => match info.integer() {
+            Ok(Some(vals)) => ints_to_js(scope, &vals, scalar),
+            _ => v8::null(scope).into(),
+        },
+        TagType::Float => match info.float() {
+            Ok(Some(vals)) => floats_to_js(scope, &vals, scalar),
+            _ => v8::null(scope).into(),
+        },
+        TagType::String => match info.string() {
+            Ok(Some(vals)) if scalar => match vals.first() {
+                Some(&v) if v != b"." => bytes_to_js(scope, v),
+                _ => v8::null(scope).into(),
+            },
+            Ok(Some(vals)) => strings_to_js(scope, vals.iter()),
+            _ => v8::null(scope).into(),
+        },
+    };
+    rv.set(value);
+}
+
+/// `variant.format(key, sample)`: the value of a FORMAT field for one sample,
+/// given by index or name, with missing values (`.`) as `null`. `GT` is
+/// returned as a string such as `"0/1"`.
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn variant_format_method(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let Some(rec) = variant_from_obj(scope, args.this()) else {
+        return;
+    };
+    let header = rec.header();
+    let key = args.get(0).to_rust_string_lossy(scope);
+    let key = key.as_bytes();
+
+    let sample_arg = args.get(1);
+    let sample = if sample_arg.is_string() {
+        let name = sample_arg.to_rust_string_lossy(scope);
+        header.sample_id(name.as_bytes())
+    } else {
+        sample_arg
+            .integer_value(scope)
+            .filter(|&i| i >= 0)
+            .map(|i| i as usize)
+    };
+    let Some(sample) = sample.filter(|&i| i < rec.sample_count() as usize) else {
+        rv.set(v8::null(scope).into());
+        return;
+    };
+
+    if key == b"GT" {
+        let value = match rec.genotypes() {
+            Ok(gts) => {
+                let gt = gts.get(sample).to_string();
+                v8::String::new(scope, &gt).unwrap().into()
+            }
+            Err(_) => v8::null(scope).into(),
+        };
+        rv.set(value);
+        return;
+    }
+
+    let Ok((ty, len)) = header.format_type(key) else {
+        rv.set(v8::null(scope).into());
+        return;
+    };
+    let scalar = len == TagLength::Fixed(1);
+    let value = match ty {
+        TagType::Integer => match rec.format(key).integer() {
+            Ok(vals) => ints_to_js(scope, vals[sample], scalar),
+            Err(_) => v8::null(scope).into(),
+        },
+        TagType::Float => match rec.format(key).float() {
+            Ok(vals) => floats_to_js(scope, vals[sample], scalar),
+            Err(_) => v8::null(scope).into(),
+        },
+        TagType::String => match rec.format(key).string() {
+            Ok(vals) if !vals.is_empty() && vals[sample] != b"." => {
+                bytes_to_js(scope, vals[sample])
+            }
+            _ => v8::null(scope).into(),
+        },
+        TagType::Flag => v8::null(scope).into(),
+    };
+    rv.set(value);
+}
+
+/// Integers with BCF missing values as `null`; vectors stop at the
+/// end-of-vector marker.
+fn ints_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    vals: &[i32],
+    scalar: bool,
+) -> v8::Local<'s, v8::Value> {
+    let vals: Vec<i32> = vals
+        .iter()
+        .copied()
+        .take_while(|&v| v != VECTOR_END_INTEGER)
+        .collect();
+    let one = |scope: &mut v8::PinScope<'s, '_>, v: i32| -> v8::Local<'s, v8::Value> {
+        if v.is_missing() {
+            v8::null(scope).into()
+        } else {
+            v8::Integer::new(scope, v).into()
+        }
+    };
+    if scalar {
+        return match vals.first() {
+            Some(&v) => one(scope, v),
+            None => v8::null(scope).into(),
+        };
+    }
+    let js_arr = v8::Array::new(scope, vals.len() as i32);
+    for (i, &v) in vals.iter().enumerate() {
+        let js_v = one(scope, v);
+        js_arr.set_index(scope, i as u32, js_v);
+    }
+    js_arr.into()
+}
+
+/// Floats with BCF missing values as `null`; vectors stop at the
+/// end-of-vector marker.
+fn floats_to_js<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    vals: &[f32],
+    scalar: bool,
+) -> v8::Local<'s, v8::Value> {
+    let vals: Vec<f32> = vals
+        .iter()
+        .copied()
+        .take_while(|v| v.to_bits() != VECTOR_END_FLOAT_BITS)
+        .collect();
+    let one = |scope: &mut v8::PinScope<'s, '_>, v: f32| -> v8::Local<'s, v8::Value> {
+        if v.is_missing() {
+            v8::null(scope).into()
+        } else {
+            v8::Number::new(scope, v as f64).into()
+        }
+    };
+    if scalar {
+        return match vals.first() {
+            Some(&v) => one(scope, v),
+            None => v8::null(scope).into(),
+        };
+    }
+    let js_arr = v8::Array::new(scope, vals.len() as i32);
+    for (i, &v) in vals.iter().enumerate() {
+        let js_v = one(scope, v);
+        js_arr.set_index(scope, i as u32, js_v);
+    }
+    js_arr.into()
+}
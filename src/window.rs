@@ -0,0 +1,212 @@
+//! `--window N`: call `filter(aln, window)` where `window` holds copies (as
+//! from `aln.clone()`) of the records starting within N bp of `aln` on the
+//! same reference, `aln` itself included, for filters that look at
+//! neighbouring reads:
+//!
+//! ```js
+//! window.filter((r) => r.cigar.some((c) => c.op === "SoftClip")).length >= 5
+//! ```
+//!
+//! Input must be coordinate-sorted. Records are buffered until no later
+//! record can start within N bp, so a record is filtered once the reads
+//! after it have been read. Unmapped reads without a position only see
+//! themselves.
+
+use std::collections::VecDeque;
+
+use anyhow::{Result, bail};
+use rust_htslib::bam;
+
+use crate::clone::clone_to_js;
+use crate::{ExtraArg, HtslibAlignment};
+
+struct Buffered {
+    /// Position in the input, identifying the record across calls.
+    id: u64,
+    source: usize,
+    record: bam::Record,
+}
+
+/// Records around the one being filtered, read ahead from a sorted input.
+pub struct RecordWindow {
+    distance: i64,
+    records: VecDeque<Buffered>,
+    /// Index in `records` of the record last returned by [`Self::advance`].
+    current: Option<usize>,
+    next_id: u64,
+    eof: bool,
+}
+
+impl RecordWindow {
+    /// A window of `distance` bp on either side of each record.
+    pub fn new(distance: u64) -> Self {
+        Self {
+            distance: distance as i64,
+            records: VecDeque::new(),
+            current: None,
+            next_id: 0,
+            eof: false,
+        }
+    }
+
+    /// Move on to the next record: read ahead with `read` (which has the
+    /// signature of [`crate::inputs::BamInputs::read`]) until its
+    /// neighbourhood is complete, copy it into `record` and return its input
+    /// index. `None` at the end of the input.
+    pub fn advance(
+        &mut self,
+        record: &mut bam::Record,
+        mut read: impl FnMut(&mut bam::Record) -> Option<Result<usize>>,
+    ) -> Option<Result<usize>> {
+        match self.fill(&mut read) {
+            Ok(Some(i)) => {
+                let next = &self.records[i];
+                *record = next.record.clone();
+                Some(Ok(next.source))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// The records near the one last returned by [`Self::advance`], itself
+    /// included, in input order and with their ids.
+    pub fn neighbours(&self) -> impl Iterator<Item = (u64, &bam::Record)> {
+        let target = self.current.map(|i| {
+            let b = &self.records[i];
+            (b.id, b.record.tid(), b.record.pos())
+        });
+        let distance = self.distance;
+        self.records
+            .iter()
+            .filter(move |b| {
+                let Some((id, tid, pos)) = target else {
+                    return false;
+                };
+                b.id == id
+                    || (tid >= 0
+                        && b.record.tid() == tid
+                        && (b.record.pos() - pos).abs() <= distance)
+            })
+            .map(|b| (b.id, &b.record))
+    }
+
+    fn fill(
+        &mut self,
+        read: &mut impl FnMut(&mut bam::Record) -> Option<Result<usize>>,
+    ) -> Result<Option<usize>> {
+        let mut next = self.current.map_or(0, |i| i + 1);
+        if next >= self.records.len() && !self.read_one(read)? {
+            self.current = None;
+            return Ok(None);
+        }
+        let (tid, pos) = {
+            let r = &self.records[next].record;
+            (r.tid(), r.pos())
+        };
+        if tid >= 0 {
+            while self
+                .records
+                .back()
+                .is_some_and(|b| b.record.tid() == tid && b.record.pos() <= pos + self.distance)
+                && self.read_one(read)?
+            {}
+        }
+        // Sorted input: once the oldest record is in range, all are.
+        while next > 0 {
+            let front = &self.records[0].record;
+            if tid >= 0 && front.tid() == tid && front.pos() >= pos - self.distance {
+                break;
+            }
+            self.records.pop_front();
+            next -= 1;
+        }
+        self.current = Some(next);
+        Ok(Some(next))
+    }
+
+    /// Buffer one more record; false at the end of the input.
+    fn read_one(
+        &mut self,
+        read: &mut impl FnMut(&mut bam::Record) -> Option<Result<usize>>,
+    ) -> Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut record = bam::Record::new();
+        let source = match read(&mut record) {
+            None => {
+                self.eof = true;
+                return Ok(false);
+            }
+            Some(result) => result?,
+        };
+        if let Some(last) = self.records.back() {
+            let (prev, rec) = (&last.record, &record);
+            let unsorted = if rec.tid() < 0 {
+                false
+            } else {
+                prev.tid() < 0 || (prev.tid(), prev.pos()) > (rec.tid(), rec.pos())
+            };
+            if unsorted {
+                bail!(
+                    "--window needs coordinate-sorted input, but {} comes after {}",
+                    String::from_utf8_lossy(rec.qname()),
+                    String::from_utf8_lossy(prev.qname())
+                );
+            }
+        }
+        self.records.push_back(Buffered {
+            id: self.next_id,
+            source,
+            record,
+        });
+        self.next_id += 1;
+        Ok(true)
+    }
+}
+
+/// JS copies of the records of recent windows, oldest first with
+/// consecutive ids, stored in an isolate slot so each record is copied once
+/// however many windows it is in.
+#[derive(Default)]
+pub(crate) struct WindowCopies(VecDeque<(u64, v8::Global<v8::Value>)>);
+
+/// The `window` argument of `filter(aln, window)`.
+pub(crate) struct WindowArg<'a> {
+    pub(crate) window: &'a RecordWindow,
+    pub(crate) header: &'a bam::HeaderView,
+}
+
+impl ExtraArg for WindowArg<'_> {
+    fn build<'s>(&self, scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Value> {
+        let mut copies = scope
+            .get_slot_mut::<WindowCopies>()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        let mut elements = Vec::new();
+        for (id, record) in self.window.neighbours() {
+            while copies.0.front().is_some_and(|(first, _)| *first < id) && elements.is_empty() {
+                copies.0.pop_front();
+            }
+            let cached = copies
+                .0
+                .front()
+                .and_then(|(first, _)| copies.0.get(id.checked_sub(*first)? as usize));
+            let element = match cached {
+                Some((_, copy)) => v8::Local::new(scope, copy),
+                None => {
+                    let aln = HtslibAlignment::new(record, self.header);
+                    let copy: v8::Local<v8::Value> = clone_to_js(scope, &aln).into();
+                    copies.0.push_back((id, v8::Global::new(scope, copy)));
+                    copy
+                }
+            };
+            elements.push(element);
+        }
+        if let Some(slot) = scope.get_slot_mut::<WindowCopies>() {
+            *slot = copies;
+        }
+        v8::Array::new_with_elements(scope, &elements).into()
+    }
+}
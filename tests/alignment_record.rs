@@ -0,0 +1,213 @@
+//! Filtering records that are not `bam::Record`s through
+//! [`AlignmentRecord`].
+
+use std::borrow::Cow;
+
+use rust_htslib::bam::record::{Aux, AuxArray, Cigar, CigarString};
+use v8bam::JsBamFilterEngine;
+use v8bam::record::AlignmentRecord;
+
+/// A minimal non-htslib record: chr7:500 10M2I8M, NM=3.
+struct Plain;
+
+impl AlignmentRecord for Plain {
+    fn qname(&self) -> &[u8] {
+        b"plain"
+    }
+
+    fn flags(&self) -> u16 {
+        0x10
+    }
+
+    fn mapq(&self) -> u8 {
+        42
+    }
+
+    fn chrom(&self) -> Option<&[u8]> {
+        Some(b"chr7")
+    }
+
+    fn pos(&self) -> i64 {
+        500
+    }
+
+    fn cigar(&self) -> CigarString {
+        CigarString(vec![Cigar::Match(10), Cigar::Ins(2), Cigar::Match(8)])
+    }
+
+    fn aux(&self, tag: &[u8; 2]) -> Option<Aux<'_>> {
+        (tag == b"NM").then_some(Aux::I32(3))
+    }
+
+    fn aux_tags(&self) -> Vec<([u8; 2], Aux<'_>)> {
+        vec![(*b"NM", Aux::I32(3))]
+    }
+}
+
+fn passes(expr: &str, aln: &dyn AlignmentRecord) -> bool {
+    JsBamFilterEngine::new(expr)
+        .unwrap()
+        .alignment_passes(aln)
+        .unwrap()
+}
+
+#[test]
+fn core_fields_come_from_the_trait() {
+    assert!(passes(
+        "aln.qname === 'plain' && aln.flag === 16 && aln.mapq === 42 && \
+         aln.chrom === 'chr7' && aln.pos === 500 && aln.end === 518 && \
+         aln.aux('NM') === 3 && aln.aux('XX') === null && \
+         aln.overlaps('chr7', 510, 520) && !aln.overlaps('chr1', 510, 520)",
+        &Plain,
+    ));
+}
+
+#[test]
+fn clone_works_for_other_record_types() {
+    assert!(passes(
+        "const c = aln.clone(); return c.end === aln.end && c.tags.NM === 3",
+        &Plain,
+    ));
+}
+
+#[test]
+fn optional_data_defaults_for_minimal_records() {
+    for expr in [
+        "aln.readGroup === null && aln.sample === null && aln.umi === null",
+        "aln.seqBytes.length === 0 && aln.qualBytes.length === 0",
+        "aln.unclippedStart === 500 && aln.unclippedEnd === 518 && aln.fivePrime === 517",
+        "aln.clipSide === null && !aln.isSplit && aln.supplementaryAlignments().length === 0",
+        "!aln.isDiscordant() && aln.mateEndEstimate === null",
+        "aln.modifications().length === 0",
+    ] {
+        assert!(passes(expr, &Plain), "{expr}");
+    }
+}
+
+/// A read implementing the optional methods too: read1 of a pair at
+/// chr7:500 3S10M2H with its reverse mate at chr7:700.
+struct Full;
+
+const FULL_SEQ: &[u8] = b"ACCGTCACGTTAA";
+
+impl AlignmentRecord for Full {
+    fn qname(&self) -> &[u8] {
+        b"full"
+    }
+
+    fn flags(&self) -> u16 {
+        0x1 | 0x20 | 0x40
+    }
+
+    fn mapq(&self) -> u8 {
+        30
+    }
+
+    fn chrom(&self) -> Option<&[u8]> {
+        Some(b"chr7")
+    }
+
+    fn pos(&self) -> i64 {
+        500
+    }
+
+    fn cigar(&self) -> CigarString {
+        CigarString(vec![
+            Cigar::SoftClip(3),
+            Cigar::Match(10),
+            Cigar::HardClip(2),
+        ])
+    }
+
+    fn aux(&self, tag: &[u8; 2]) -> Option<Aux<'_>> {
+        self.aux_tags()
+            .into_iter()
+            .find_map(|(t, aux)| (&t == tag).then_some(aux))
+    }
+
+    fn aux_tags(&self) -> Vec<([u8; 2], Aux<'_>)> {
+        vec![
+            (*b"RG", Aux::String("grp")),
+            (*b"UB", Aux::String("AACC")),
+            (*b"SA", Aux::String("chr9,1001,-,5M8S,20,1;")),
+            (*b"MC", Aux::String("10M")),
+            (*b"MM", Aux::String("C+m,2;")),
+            (*b"ML", Aux::ArrayU8(AuxArray::from(&[255u8][..]))),
+        ]
+    }
+
+    fn mate_chrom(&self) -> Option<&[u8]> {
+        Some(b"chr7")
+    }
+
+    fn mate_pos(&self) -> i64 {
+        700
+    }
+
+    fn insert_size(&self) -> i64 {
+        215
+    }
+
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(FULL_SEQ)
+    }
+
+    fn qual(&self) -> &[u8] {
+        &[20; 13]
+    }
+
+    fn read_group_tag(&self, id: &[u8], tag: &[u8; 2]) -> Option<String> {
+        match (id, tag) {
+            (b"grp", b"SM") => Some("sampleA".to_string()),
+            (b"grp", b"LB") => Some("libA".to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn htslib_accessors_work_through_the_trait() {
+    for expr in [
+        "aln.readGroup === 'grp' && aln.sample === 'sampleA' && aln.library === 'libA' && \
+         aln.platform === null",
+        "aln.unclippedStart === 497 && aln.unclippedEnd === 512 && aln.fivePrime === 497",
+        "aln.clipSide === 'both' && aln.umi === 'AACC' && aln.cellBarcode === null",
+        "aln.isSplit && aln.supplementaryAlignments()[0].pos === 1000 && \
+         aln.supplementaryAlignments()[0].strand === '-'",
+        // Not flagged as a proper pair, but FR within 1000 bases.
+        "aln.isDiscordant() && !aln.isDiscordant(1000) && aln.mateEndEstimate === 710",
+        "String.fromCharCode(...aln.seqBytes) === 'ACCGTCACGTTAA' && \
+         aln.qualBytes.length === 13 && aln.qualBytes[12] === 20",
+        // The third C, past the soft clip.
+        "const m = aln.modifications(); return m.length === 1 && m[0].queryPos === 5 && \
+         m[0].refPos === 502 && m[0].code === 'm' && m[0].prob > 0.99",
+    ] {
+        assert!(passes(expr, &Full), "{expr}");
+    }
+}
+
+#[cfg(feature = "noodles")]
+#[test]
+fn noodles_record_buf() {
+    use v8bam::record::NoodlesAlignment;
+
+    let sam = b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000000\n@RG\tID:rg1\tSM:s1\n\
+        r1\t99\tchr1\t1001\t60\t5S15M\t=\t1201\t220\tACGTACGTACGTACGTACGT\tIIIIIIIIIIIIIIIIIIII\t\
+        NM:i:1\tRG:Z:rg1\n";
+    let mut reader = noodles_sam::io::Reader::new(&sam[..]);
+    let header = reader.read_header().unwrap();
+    let record = reader.record_bufs(&header).next().unwrap().unwrap();
+
+    assert!(passes(
+        "aln.qname === 'r1' && aln.flag === 99 && aln.mapq === 60 && \
+         aln.chrom === 'chr1' && aln.pos === 1000 && aln.end === 1015 && \
+         aln.cigar.length === 2 && aln.cigar[0].op === 'SoftClip' && \
+         aln.aux('NM') === 1 && aln.aux('RG') === 'rg1'",
+        &NoodlesAlignment::new(&record, &header),
+    ));
+    assert!(passes(
+        "aln.sample === 's1' && aln.unclippedStart === 995 && aln.seqBytes.length === 20 && \
+         aln.qualBytes[0] === 40 && !aln.isDiscordant() && aln.mateEndEstimate === 1220",
+        &NoodlesAlignment::new(&record, &header),
+    ));
+}
@@ -0,0 +1,29 @@
+//! `barcodes.has(bc)` with and without a `--barcodes` whitelist.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::{EngineOptions, JsBamFilterEngine};
+
+const EXPR: &str = "barcodes.has('AAAC') && !barcodes.has('TTTT')";
+
+#[test]
+fn has_checks_the_whitelist() {
+    let mut opts = EngineOptions::default();
+    opts.barcodes.whitelist = Some(Arc::new(HashSet::from(["AAAC".to_string()])));
+    let mut engine = JsBamFilterEngine::with_options(EXPR, opts).unwrap();
+    let (_, rec) = synthetic_records().swap_remove(0);
+    assert!(engine.record_passes(&rec, &synthetic_header()).unwrap());
+}
+
+#[test]
+fn has_throws_without_a_whitelist() {
+    let mut engine = JsBamFilterEngine::new(EXPR).unwrap();
+    let (_, rec) = synthetic_records().swap_remove(0);
+    let err = engine.record_passes(&rec, &synthetic_header()).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("no --barcodes whitelist"),
+        "{err:#}"
+    );
+}
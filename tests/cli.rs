@@ -0,0 +1,240 @@
+//! The `v8bam` binary: counts and exit codes of the non-writing modes, and
+//! `--stats-json`.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Two mapped mates with MAPQ 60, a MAPQ 10 read on chr2 and an unmapped read.
+const SAM: &str = "@HD\tVN:1.6\tSO:coordinate\n\
+@SQ\tSN:chr1\tLN:10000\n\
+@SQ\tSN:chr2\tLN:10000\n\
+pair1\t99\tchr1\t1001\t60\t20M\t=\t1201\t220\tACGTACGTACGTACGTACGT\tIIIIIIIIIIIIIIIIIIII\tNM:i:1\n\
+pair1\t147\tchr1\t1201\t60\t20M\t=\t1001\t-220\tACGTACGTACGTACGTACGT\tIIIIIIIIIIIIIIIIIIII\tNM:i:0\n\
+single\t0\tchr2\t501\t10\t20M\t*\t0\t0\tACGTACGTACGTACGTACGT\tIIIIIIIIIIIIIIIIIIII\n\
+unmapped\t4\t*\t0\t0\t*\t*\t0\t0\tACGTACGTACGTACGTACGT\tIIIIIIIIIIIIIIIIIIII\n";
+
+fn input(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("v8bam-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{test}.sam"));
+    std::fs::write(&path, SAM).unwrap();
+    path
+}
+
+fn v8bam(test: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_v8bam"))
+        .arg(input(test))
+        .args(args)
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn count_prints_read_and_passed() {
+    for engine in ["js", "native"] {
+        let out = v8bam(
+            &format!("count-{engine}"),
+            &["-e", "aln.mapq >= 30", "--count", "--engine", engine],
+        );
+        assert_eq!(out.status.code(), Some(0), "{engine}");
+        assert_eq!(stdout(&out), "read\tpassed\n4\t2\n", "{engine}");
+    }
+}
+
+#[test]
+fn count_by_chrom() {
+    let out = v8bam(
+        "by-chrom",
+        &["-e", "aln.mapq >= 30", "--count", "--by-chrom"],
+    );
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stdout(&out),
+        "read\tpassed\n4\t2\n\nchrom\tread\tpassed\n*\t1\t0\nchr1\t2\t2\nchr2\t1\t0\n"
+    );
+}
+
+#[test]
+fn any_exits_zero_when_a_read_passes() {
+    let out = v8bam("any-hit", &["-e", "aln.qname === 'single'", "--any"]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = v8bam("any-miss", &["-e", "aln.mapq > 60", "--any"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn none_exits_zero_when_no_read_passes() {
+    let out = v8bam("none-miss", &["-e", "aln.mapq > 60", "--none"]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = v8bam("none-hit", &["-e", "aln.qname === 'single'", "--none"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn script_errors_exit_two() {
+    for mode in ["--count", "--any", "--none"] {
+        let out = v8bam(
+            &format!("error{mode}"),
+            &["-e", "throw new Error('boom')", mode],
+        );
+        assert_eq!(out.status.code(), Some(2), "{mode}");
+        assert!(
+            String::from_utf8_lossy(&out.stderr).contains("boom"),
+            "{mode}: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+}
+
+fn stats_json(test: &str, expr: &str) -> (Output, serde_json::Value) {
+    let dir = input(test).with_extension("");
+    std::fs::create_dir_all(&dir).unwrap();
+    let stats = dir.join("stats.json");
+    let bam = dir.join("out.bam");
+    let out = v8bam(
+        test,
+        &[
+            "-e",
+            expr,
+            "-o",
+            bam.to_str().unwrap(),
+            "--stats-json",
+            stats.to_str().unwrap(),
+        ],
+    );
+    let json = serde_json::from_str(&std::fs::read_to_string(&stats).unwrap()).unwrap();
+    (out, json)
+}
+
+#[test]
+fn stats_json_counts_records() {
+    let (out, stats) = stats_json("stats", "aln.mapq >= 30");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stats["records"],
+        serde_json::json!({
+            "read": 4,
+            "sampled_out": 0,
+            "passed": 2,
+            "failed": 2,
+            "written": 2,
+            "removed_by_header": 0,
+        })
+    );
+    assert_eq!(stats["script_errors"], 0);
+    assert!(stats["error"].is_null());
+    assert_eq!(
+        stats["flags"]["paired"],
+        serde_json::json!({"read": 2, "passed": 2})
+    );
+    assert_eq!(
+        stats["flags"]["unmapped"],
+        serde_json::json!({"read": 1, "passed": 0})
+    );
+    assert_eq!(
+        stats["flags"]["reverse"],
+        serde_json::json!({"read": 1, "passed": 1})
+    );
+    for key in ["wall_seconds", "js_seconds", "io_seconds"] {
+        assert!(stats["time"][key].is_f64(), "{key}: {}", stats["time"]);
+    }
+    assert!(stats["records_per_second"].is_number());
+    assert!(stats["peak_v8_heap_bytes"].as_u64().is_some());
+    assert_eq!(stats["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+fn stats_json_is_written_when_the_script_throws() {
+    let (out, stats) = stats_json(
+        "stats-error",
+        "if (aln.qname === 'single') throw new Error('boom'); return true",
+    );
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stats["records"]["read"], 3);
+    assert_eq!(stats["records"]["passed"], 2);
+    assert_eq!(stats["script_errors"], 1);
+    assert!(
+        stats["error"].as_str().is_some_and(|e| e.contains("boom")),
+        "{}",
+        stats["error"]
+    );
+}
+
+#[test]
+fn native_engine_runs_without_v8() {
+    let dir = input("native-heap").with_extension("");
+    std::fs::create_dir_all(&dir).unwrap();
+    let stats = dir.join("stats.json");
+    let out = v8bam(
+        "native-heap",
+        &[
+            "-e",
+            "aln.mapq >= 30",
+            "--count",
+            "--engine",
+            "native",
+            "--stats-json",
+            stats.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(out.status.code(), Some(0));
+    let stats: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats).unwrap()).unwrap();
+    assert_eq!(stats["records"]["passed"], 2);
+    assert_eq!(stats["peak_v8_heap_bytes"], 0);
+}
+
+/// `--check` for an input that need not exist; only its name is used.
+fn check(input: &str, expr: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_v8bam"))
+        .args([input, "--check", "-e", expr])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn check_runs_synthetic_records_of_the_input_type() {
+    let out = check("reads.fq.gz", "read.seq.length > 0");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stdout(&out),
+        "record 1 (read with a comment): pass\n\
+         record 2 (low-quality read with an N, no comment): pass\n\
+         record 3 (empty read): fail\n\
+         ok\n"
+    );
+
+    let out = check("calls.vcf", "variant.qual === null");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stdout(&out),
+        "record 1 (biallelic SNV with INFO, FILTER and genotypes): fail\n\
+         record 2 (multiallelic indel without QUAL, FILTER or INFO): pass\n\
+         ok\n"
+    );
+
+    let out = check("reads.bam", "aln.mapq >= 30");
+    assert_eq!(out.status.code(), Some(0));
+    assert!(
+        stdout(&out).starts_with("record 1 (mapped forward read, NM=1): pass\n"),
+        "{}",
+        stdout(&out)
+    );
+}
+
+#[test]
+fn check_reports_unknown_properties_of_the_input_object() {
+    let out = check("reads.fastq", "read.mapq > 0");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(
+        stdout(&out).contains("unknown property: read.mapq\n"),
+        "{}",
+        stdout(&out)
+    );
+}
@@ -0,0 +1,136 @@
+//! `console.*`/`print` formatting and the `--log-limit` cap.
+
+use std::sync::{Mutex, Once};
+
+use log::{Level, Log, Metadata, Record};
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::{EngineOptions, JsBamFilterEngine, console};
+
+/// Collects script messages; tests tell theirs apart by a per-test prefix.
+struct Capture(Mutex<Vec<(Level, String)>>);
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == console::LOG_TARGET
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let msg = record.args().to_string();
+            self.0.lock().unwrap().push((record.level(), msg));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+/// Run `expr` over the synthetic records and return the script messages
+/// that contain `marker`, plus any limit warning.
+fn messages(expr: &str, log_limit: Option<u64>, marker: &str) -> Vec<(Level, String)> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    let opts = EngineOptions {
+        log_limit,
+        ..Default::default()
+    };
+    let mut engine = JsBamFilterEngine::with_options(expr, opts).unwrap();
+    let header = synthetic_header();
+    for (_, rec) in synthetic_records() {
+        engine.record_passes(&rec, &header).unwrap();
+    }
+    CAPTURE
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, msg)| msg.contains(marker) || msg.contains("log limit"))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn arguments_are_joined_and_formatted() {
+    let logged = messages(
+        "if (aln.qname === 'synthetic_fwd') \
+             console.log('fmt:', aln.mapq, null, undefined, true, \
+                 {qname: aln.qname, nm: aln.aux('NM')}, [1, 'a'], \
+                 aln.seqBytes.subarray(0, 4), new Float32Array([0.5])); \
+         return true",
+        None,
+        "fmt:",
+    );
+    assert_eq!(
+        logged,
+        [(
+            Level::Info,
+            r#"fmt: 60 null undefined true {"qname":"synthetic_fwd","nm":1} [1,"a"] [65,67,71,84] [0.5]"#
+                .to_string()
+        )]
+    );
+}
+
+#[test]
+fn unserializable_objects_fall_back_to_string() {
+    let logged = messages(
+        "if (aln.qname === 'synthetic_fwd') { \
+             const o = {}; o.self = o; console.log('cycle:', o, () => 1); } \
+         return true",
+        None,
+        "cycle:",
+    );
+    assert_eq!(
+        logged,
+        [(Level::Info, "cycle: [object Object] () => 1".to_string())]
+    );
+}
+
+#[test]
+fn levels_follow_the_method() {
+    let logged = messages(
+        "if (aln.qname === 'synthetic_fwd') { \
+             console.info('lvl: info'); console.warn('lvl: warn'); \
+             console.error('lvl: error'); console.debug('lvl: debug'); print('lvl: print'); } \
+         return true",
+        None,
+        "lvl:",
+    );
+    let levels: Vec<Level> = logged.iter().map(|(level, _)| *level).collect();
+    assert_eq!(
+        levels,
+        [
+            Level::Info,
+            Level::Warn,
+            Level::Error,
+            Level::Debug,
+            Level::Info
+        ]
+    );
+}
+
+#[test]
+fn log_limit_suppresses_further_messages_with_one_warning() {
+    // Two messages per record, three records, limit 3.
+    let logged = messages(
+        "console.log('limited:', aln.qname); console.log('limited:', aln.flag); return true",
+        Some(3),
+        "limited:",
+    );
+    assert_eq!(
+        logged,
+        [
+            (Level::Info, "limited: synthetic_fwd".to_string()),
+            (Level::Info, "limited: 67".to_string()),
+            (Level::Info, "limited: synthetic_rev_clipped".to_string()),
+            (
+                Level::Warn,
+                "script log limit (3) reached; suppressing further messages".to_string()
+            ),
+        ]
+    );
+}
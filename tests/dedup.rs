@@ -0,0 +1,98 @@
+//! Clip-aware positions on `aln` and the `seen()` key set.
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Cigar, CigarString};
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+
+/// A read at chr1:100 with `2H3S10M4S5H`.
+fn clipped_read(reverse: bool) -> bam::Record {
+    let cigar = CigarString(vec![
+        Cigar::HardClip(2),
+        Cigar::SoftClip(3),
+        Cigar::Match(10),
+        Cigar::SoftClip(4),
+        Cigar::HardClip(5),
+    ]);
+    let mut rec = bam::Record::new();
+    rec.set(b"clipped", Some(&cigar), &[b'A'; 17], &[30; 17]);
+    rec.set_tid(0);
+    rec.set_pos(100);
+    rec.set_flags(if reverse { 0x10 } else { 0 });
+    rec
+}
+
+fn positions(rec: &bam::Record) -> String {
+    let mut engine =
+        JsBamFilterEngine::new("[aln.unclippedStart, aln.unclippedEnd, aln.fivePrime]").unwrap();
+    let mut line = String::new();
+    assert!(
+        engine
+            .record_emit(rec, &synthetic_header(), &mut line)
+            .unwrap()
+    );
+    line
+}
+
+#[test]
+fn unclipped_positions_include_soft_and_hard_clips() {
+    // 100 - 3S - 2H = 95; end 110 + 4S + 5H = 119 (exclusive).
+    assert_eq!(positions(&clipped_read(false)), "95\t119\t95");
+    assert_eq!(positions(&clipped_read(true)), "95\t119\t118");
+}
+
+#[test]
+fn five_prime_of_synthetic_records() {
+    let header = synthetic_header();
+    let mut engine = JsBamFilterEngine::new("aln.unmapped ? null : aln.fivePrime").unwrap();
+    let lines: Vec<String> = synthetic_records()
+        .iter()
+        .filter_map(|(_, rec)| {
+            let mut line = String::new();
+            engine
+                .record_emit(rec, &header, &mut line)
+                .unwrap()
+                .then_some(line)
+        })
+        .collect();
+    // Forward 20M at 1000; reverse 5S12M3H at 1200 ends at 1212 + 3H.
+    assert_eq!(lines, ["1000", "1214"]);
+}
+
+/// `expr` over the synthetic records in the order given by `indices`.
+fn passes(expr: &str, indices: &[usize]) -> Vec<bool> {
+    let mut engine = JsBamFilterEngine::new(expr).unwrap();
+    let header = synthetic_header();
+    let records = synthetic_records();
+    indices
+        .iter()
+        .map(|&i| engine.record_passes(&records[i].1, &header).unwrap())
+        .collect()
+}
+
+#[test]
+fn seen_is_false_only_the_first_time() {
+    assert_eq!(
+        passes("!seen(aln.qname)", &[0, 0, 1, 0, 1, 2]),
+        [true, false, true, false, false, true]
+    );
+    // Keys are compared as strings.
+    assert_eq!(passes("seen(1); return seen('1')", &[0]), [true]);
+}
+
+#[test]
+fn seen_clear_forgets_keys() {
+    assert_eq!(
+        passes(
+            "if (aln.unmapped) seen.clear(); return !seen(aln.qname)",
+            &[0, 1, 0, 2, 0, 1]
+        ),
+        [true, true, false, true, true, true]
+    );
+}
+
+#[test]
+fn engines_have_separate_key_sets() {
+    assert_eq!(passes("!seen('k')", &[0, 0]), [true, false]);
+    assert_eq!(passes("!seen('k')", &[0, 0]), [true, false]);
+}
@@ -0,0 +1,113 @@
+//! Running coverage (`depth()`, `engine.depth_at`) and `--max-depth` over
+//! the synthetic records (chr1:1000 20M read1, chr1:1200 12M read2 of the
+//! same pair, and an unmapped read).
+
+use rust_htslib::bam;
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::depth::{DepthCap, script_uses_depth};
+
+fn records() -> Vec<bam::Record> {
+    synthetic_records()
+        .into_iter()
+        .map(|(_, rec)| rec)
+        .collect()
+}
+
+#[test]
+fn depth_helper_reports_running_coverage() {
+    let mut engine = JsBamFilterEngine::new(
+        "[depth('chr1', 1000), depth('chr1', 1010), depth('chr1', 1200), depth('chr2', 5)]",
+    )
+    .unwrap();
+    let header = synthetic_header();
+    let mut lines = Vec::new();
+    for rec in records() {
+        engine.add_to_depth(&rec, &header).unwrap();
+        let mut line = String::new();
+        assert!(engine.record_emit(&rec, &header, &mut line).unwrap());
+        lines.push(line);
+    }
+    // The second read starts after the first ends, so positions before it
+    // are no longer tracked; the unmapped read isn't counted.
+    assert_eq!(lines, ["1\t1\t0\t.", ".\t.\t1\t.", ".\t.\t1\t."]);
+}
+
+#[test]
+fn depth_is_null_without_tracking() {
+    let mut engine = JsBamFilterEngine::new("depth('chr1', 1000) === null").unwrap();
+    let header = synthetic_header();
+    for rec in records() {
+        assert!(engine.record_passes(&rec, &header).unwrap());
+    }
+}
+
+#[test]
+fn depth_at_counts_the_current_read() {
+    let mut engine = JsBamFilterEngine::new("true").unwrap();
+    let header = synthetic_header();
+    let depths: Vec<usize> = records()
+        .iter()
+        .map(|rec| {
+            engine.add_to_depth(rec, &header).unwrap();
+            engine.depth_at(rec)
+        })
+        .collect();
+    assert_eq!(depths, [1, 1, 0]);
+}
+
+#[test]
+fn unsorted_input_is_an_error() {
+    let mut engine = JsBamFilterEngine::new("true").unwrap();
+    let header = synthetic_header();
+    let recs = records();
+    engine.add_to_depth(&recs[1], &header).unwrap();
+    let err = engine.add_to_depth(&recs[0], &header).unwrap_err();
+    assert!(format!("{err:#}").contains("coordinate-sorted"), "{err:#}");
+}
+
+#[test]
+fn cap_keeps_reads_under_the_limit() {
+    let mut cap = DepthCap::new(10, 0);
+    for rec in records() {
+        assert!(cap.keep(&rec, 10));
+    }
+}
+
+#[test]
+fn cap_keeps_or_drops_mates_together() {
+    let recs = records();
+    let mut dropped = 0;
+    for seed in 0..20 {
+        let mut cap = DepthCap::new(1, seed);
+        // The first mate is in a deep region, the second is not.
+        let first = cap.keep(&recs[0], 1000);
+        assert_eq!(cap.keep(&recs[1], 1), first, "seed {seed}");
+        dropped += !first as usize;
+    }
+    assert!(dropped > 0);
+}
+
+#[test]
+fn cap_forgets_mates_that_never_arrive() {
+    let recs = records();
+    let seed = (0..100)
+        .find(|&seed| !DepthCap::new(1, seed).keep(&recs[0], 1000))
+        .unwrap();
+    let mut cap = DepthCap::new(1, seed);
+    assert!(!cap.keep(&recs[0], 1000));
+    // A read past the mate position (chr1:1200) ends the wait for it, so
+    // the mate is decided on its own depth.
+    let mut later = recs[1].clone();
+    later.set_qname(b"other");
+    later.set_pos(1300);
+    assert!(cap.keep(&later, 1));
+    assert!(cap.keep(&recs[1], 1));
+}
+
+#[test]
+fn scripts_using_depth_are_detected() {
+    assert!(script_uses_depth("depth(aln.chrom, aln.pos) < 100"));
+    assert!(!script_uses_depth("aln.mapq > 20"));
+    assert!(!script_uses_depth("aln.aux('XD') === maxdepth"));
+}
@@ -0,0 +1,171 @@
+//! Scripts that keep a reference to `aln` past the `filter()` call it was
+//! passed to must get a JS error instead of reading freed memory.
+
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+
+/// Run `expr` over the synthetic records, then evaluate `later` as a
+/// `header(hdr)` hook, i.e. outside any `filter()` call.
+fn run_then_hook(expr: &str, later: &str) -> anyhow::Result<serde_json::Value> {
+    let mut engine = JsBamFilterEngine::new(expr)?;
+    let header = synthetic_header();
+    for (_, rec) in synthetic_records() {
+        let _ = engine.record_passes(&rec, &header);
+    }
+    engine
+        .header_edits(later, &header)
+        .map(|_| serde_json::Value::Null)
+}
+
+fn assert_detached(result: anyhow::Result<serde_json::Value>) {
+    let err = result.expect_err("reading a kept aln should throw");
+    let msg = format!("{err:#}");
+    assert!(msg.contains("aln is detached"), "unexpected error: {msg}");
+}
+
+#[test]
+fn kept_aln_getter_throws_after_call() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.qname",
+    ));
+}
+
+#[test]
+fn kept_aln_method_throws_after_call() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.aux('NM')",
+    ));
+}
+
+#[test]
+fn kept_aln_htslib_accessor_throws_after_call() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.readGroup",
+    ));
+}
+
+#[test]
+fn kept_aln_source_file_throws_after_call() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.sourceFile",
+    ));
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.sourceIndex",
+    ));
+}
+
+#[test]
+fn kept_aln_in_region_test_throws_after_call() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; return true",
+        "kept.overlaps('chr1', 0, 2000)",
+    ));
+}
+
+#[test]
+fn fields_are_cleared_when_filter_throws() {
+    assert_detached(run_then_hook(
+        "globalThis.kept = aln; throw new Error('boom')",
+        "kept.mapq",
+    ));
+}
+
+#[test]
+fn caught_detached_access_does_not_fail_the_hook() {
+    let result = run_then_hook(
+        "globalThis.kept = aln; return true",
+        "try { kept.pos } catch (e) { if (!(e instanceof TypeError)) throw e } return {}",
+    );
+    assert!(result.is_ok(), "{result:?}");
+}
+
+#[test]
+fn kept_aln_throws_during_next_call() {
+    let mut engine = JsBamFilterEngine::new(
+        "const prev = globalThis.kept; globalThis.kept = aln; \
+         if (prev === undefined) return true; \
+         try { prev.qname; return false } catch (e) { \
+             return e instanceof TypeError && e.message.startsWith('aln is detached') && prev !== aln }",
+    )
+    .unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+}
+
+#[test]
+fn uncaught_access_to_kept_aln_fails_next_call() {
+    let mut engine = JsBamFilterEngine::new(
+        "const prev = globalThis.kept; globalThis.kept = aln; return prev ? prev.mapq > 0 : true",
+    )
+    .unwrap();
+    let header = synthetic_header();
+    let records = synthetic_records();
+    assert!(engine.record_passes(&records[0].1, &header).unwrap());
+    let err = engine
+        .record_passes(&records[1].1, &header)
+        .expect_err("the kept aln belongs to the previous call");
+    assert!(format!("{err:#}").contains("aln is detached"), "{err:#}");
+}
+
+#[test]
+fn record_access_during_call_is_unaffected() {
+    let mut engine = JsBamFilterEngine::new("aln.qname.startsWith('synthetic')").unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+}
+
+#[test]
+fn cloned_aln_stays_valid_after_call() {
+    let result = run_then_hook(
+        "globalThis.kept = aln.clone(); return true",
+        "if (kept.qname !== 'synthetic_unmapped' || kept.chrom !== '*' || kept.aux('NM') !== null) \
+         throw new Error(JSON.stringify(kept)); return {}",
+    );
+    assert!(result.is_ok(), "{result:?}");
+}
+
+#[test]
+fn cloned_aln_keeps_aux_tags() {
+    let result = run_then_hook(
+        "if (aln.qname === 'synthetic_fwd') globalThis.kept = aln.clone(); return true",
+        "if (kept.aux('NM') !== 1 || kept.tags.RG !== 'rg1' || kept.end !== 1020) \
+         throw new Error(JSON.stringify(kept)); return {}",
+    );
+    assert!(result.is_ok(), "{result:?}");
+}
+
+#[test]
+fn cloned_fields_match_aln_getters() {
+    let mut engine = JsBamFilterEngine::new(
+        "const c = aln.clone(); \
+         return ['qname', 'flag', 'mapq', 'chrom', 'pos', 'start', 'end'] \
+             .every((k) => c[k] === aln[k])",
+    )
+    .unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+}
+
+#[test]
+fn transferred_byte_buffer_is_replaced() {
+    let mut engine = JsBamFilterEngine::new(
+        "const bytes = aln.seqBytes; bytes.buffer.transfer(); \
+         return bytes.length === 0 && aln.seqBytes.length > 0 && aln.seqBytes[0] === 65",
+    )
+    .unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+}
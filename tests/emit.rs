@@ -0,0 +1,107 @@
+//! `--emit`: serialization of the script's return value.
+
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+
+/// Lines emitted for the synthetic records; `None` where the record was
+/// dropped.
+fn emit(expr: &str) -> Vec<Option<String>> {
+    let mut engine = JsBamFilterEngine::new(expr).unwrap();
+    let header = synthetic_header();
+    synthetic_records()
+        .iter()
+        .map(|(_, rec)| {
+            let mut line = String::new();
+            engine
+                .record_emit(rec, &header, &mut line)
+                .unwrap()
+                .then_some(line)
+        })
+        .collect()
+}
+
+fn emit_first(expr: &str) -> Option<String> {
+    emit(expr).remove(0)
+}
+
+#[test]
+fn strings_are_written_as_is() {
+    assert_eq!(
+        emit_first("aln.qname + '\\tx'").as_deref(),
+        Some("synthetic_fwd\tx")
+    );
+}
+
+#[test]
+fn primitives_go_through_string() {
+    assert_eq!(emit_first("aln.mapq").as_deref(), Some("60"));
+    assert_eq!(emit_first("0").as_deref(), Some("0"));
+    assert_eq!(emit_first("0.5").as_deref(), Some("0.5"));
+    assert_eq!(emit_first("true").as_deref(), Some("true"));
+    assert_eq!(emit_first("''").as_deref(), Some(""));
+}
+
+#[test]
+fn arrays_are_tab_joined_with_dots_for_missing() {
+    assert_eq!(
+        emit("[aln.qname, aln.chrom, aln.pos, aln.aux('NM'), undefined, {a: [1]}]"),
+        [
+            Some("synthetic_fwd\tchr1\t1000\t1\t.\t{\"a\":[1]}".to_string()),
+            Some("synthetic_rev_clipped\tchr1\t1200\t0\t.\t{\"a\":[1]}".to_string()),
+            Some("synthetic_unmapped\t*\t-1\t.\t.\t{\"a\":[1]}".to_string()),
+        ]
+    );
+    assert_eq!(emit_first("[]").as_deref(), Some(""));
+}
+
+#[test]
+fn objects_are_json_lines() {
+    assert_eq!(
+        emit_first("({qname: aln.qname, flags: [aln.paired, aln.reverse], rg: aln.aux('RG')})")
+            .as_deref(),
+        Some(r#"{"qname":"synthetic_fwd","flags":[true,false],"rg":"rg1"}"#)
+    );
+}
+
+#[test]
+fn null_undefined_and_false_drop_the_record() {
+    assert_eq!(
+        emit("aln.mapq >= 20 && aln.qname"),
+        [Some("synthetic_fwd".to_string()), None, None]
+    );
+    assert_eq!(
+        emit("if (aln.reverse) return null; if (!aln.unmapped) return undefined; return 'u'"),
+        [None, None, Some("u".to_string())]
+    );
+}
+
+#[test]
+fn unserializable_values_fail_the_call() {
+    let mut engine = JsBamFilterEngine::new("const o = {}; o.self = o; return o").unwrap();
+    let header = synthetic_header();
+    let (_, rec) = &synthetic_records()[0];
+    let mut line = String::new();
+    let err = engine
+        .record_emit(rec, &header, &mut line)
+        .expect_err("a cyclic object can't be serialized");
+    assert!(
+        format!("{err:#}").contains("failed to serialize"),
+        "{err:#}"
+    );
+}
+
+#[test]
+fn clones_kept_from_earlier_calls_serialize() {
+    let lines = emit(
+        "const prev = globalThis.prev; globalThis.prev = aln.clone(); \
+         return prev ? [prev.qname, prev.end, aln.qname] : null",
+    );
+    assert_eq!(
+        lines,
+        [
+            None,
+            Some("synthetic_fwd\t1020\tsynthetic_rev_clipped".to_string()),
+            Some("synthetic_rev_clipped\t1212\tsynthetic_unmapped".to_string()),
+        ]
+    );
+}
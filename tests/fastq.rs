@@ -0,0 +1,287 @@
+//! FASTQ output of alignment records, and FASTQ input filtered by
+//! `JsFastqFilterEngine`.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Cigar, CigarString};
+use v8bam::fastq::{
+    FastqOptions, FastqReader, FastqRecord, FastqWriter, JsFastqFilterEngine, format_fastq,
+};
+
+/// An in-memory output whose contents stay readable after the writer that
+/// owns it is done.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Read names in the output, in order.
+    fn names(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone())
+            .unwrap()
+            .lines()
+            .step_by(4)
+            .map(|line| line.trim_start_matches('@').to_string())
+            .collect()
+    }
+}
+
+/// A primary mate of pair `name`; first mates are forward, second mates
+/// reverse.
+fn mate(name: &str, first: bool) -> bam::Record {
+    let mut rec = bam::Record::new();
+    let cigar = CigarString(vec![Cigar::Match(4)]);
+    rec.set(name.as_bytes(), Some(&cigar), b"ACGG", &[30, 31, 32, 33]);
+    rec.set_flags(if first { 0x1 | 0x40 } else { 0x1 | 0x10 | 0x80 });
+    rec
+}
+
+fn unpaired(name: &str) -> bam::Record {
+    let mut rec = mate(name, true);
+    rec.set_flags(0);
+    rec
+}
+
+struct Paired {
+    writer: FastqWriter,
+    r1: Shared,
+    r2: Shared,
+    singletons: Shared,
+}
+
+fn paired() -> Paired {
+    let (r1, r2, singletons) = (Shared::default(), Shared::default(), Shared::default());
+    let writer = FastqWriter::paired(
+        Box::new(r1.clone()),
+        Box::new(r2.clone()),
+        Some(Box::new(singletons.clone())),
+        FastqOptions::default(),
+    );
+    Paired {
+        writer,
+        r1,
+        r2,
+        singletons,
+    }
+}
+
+#[test]
+fn coordinate_sorted_pairs_stay_in_sync() {
+    let mut out = paired();
+    let written: Vec<usize> = [
+        mate("a", true),
+        mate("b", true),
+        mate("b", false),
+        mate("a", false),
+    ]
+    .iter()
+    .map(|rec| out.writer.write(rec).unwrap())
+    .collect();
+    assert_eq!(written, [0, 0, 2, 2]);
+    assert_eq!(out.writer.finish().unwrap(), 0);
+
+    assert_eq!(out.r1.names(), ["b", "a"]);
+    assert_eq!(out.r2.names(), ["b", "a"]);
+    assert!(out.singletons.names().is_empty());
+}
+
+#[test]
+fn mate_of_a_filtered_read_goes_to_singletons() {
+    let mut out = paired();
+    // The filter dropped the second mate of "a" and the first of "c".
+    for rec in [
+        mate("a", true),
+        mate("b", false),
+        mate("c", false),
+        unpaired("u"),
+        mate("b", true),
+    ] {
+        out.writer.write(&rec).unwrap();
+    }
+    assert_eq!(out.singletons.names(), ["u"]);
+    assert_eq!(out.writer.finish().unwrap(), 2);
+
+    assert_eq!(out.r1.names(), ["b"]);
+    assert_eq!(out.r2.names(), ["b"]);
+    assert_eq!(out.singletons.names(), ["u", "a", "c"]);
+}
+
+#[test]
+fn interleaved_output_writes_complete_pairs_only() {
+    let out = Shared::default();
+    let mut writer = FastqWriter::interleaved(Box::new(out.clone()), FastqOptions::default());
+    for rec in [
+        mate("a", true),
+        mate("b", false),
+        unpaired("u"),
+        mate("b", true),
+        mate("c", true),
+        mate("c", false),
+    ] {
+        writer.write(&rec).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), 0);
+    assert_eq!(out.names(), ["u", "b", "b", "c", "c"]);
+
+    // Read 1 comes first, whichever mate was read first.
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    let seqs: Vec<&str> = text.lines().skip(1).step_by(4).collect();
+    assert_eq!(seqs, ["ACGG", "ACGG", "CCGT", "ACGG", "CCGT"]);
+}
+
+#[test]
+fn secondary_and_supplementary_records_are_skipped() {
+    let mut out = paired();
+    for flag in [0x100, 0x800] {
+        let mut rec = mate("a", true);
+        rec.set_flags(rec.flags() | flag);
+        assert_eq!(out.writer.write(&rec).unwrap(), 0);
+    }
+    assert_eq!(out.writer.finish().unwrap(), 0);
+    assert!(out.singletons.names().is_empty());
+}
+
+#[test]
+fn qualities_are_capped_at_93() {
+    let mut rec = unpaired("q");
+    let cigar = CigarString(vec![Cigar::Match(3)]);
+    rec.set(b"q", Some(&cigar), b"ACG", &[0, 93, 250]);
+    let mut out = Vec::new();
+    format_fastq(&rec, &FastqOptions::default(), &mut out);
+    assert_eq!(out, b"@q\nACG\n+\n!~~\n");
+
+    let mut rec = unpaired("q");
+    rec.set(b"q", Some(&cigar), b"ACG", &[0xff; 3]);
+    let opts = FastqOptions {
+        default_qual: 200,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    format_fastq(&rec, &opts, &mut out);
+    assert_eq!(out, b"@q\nACG\n+\n~~~\n");
+}
+
+fn read_all(data: Vec<u8>) -> anyhow::Result<Vec<FastqRecord>> {
+    let mut reader = FastqReader::new(io::Cursor::new(data))?;
+    let mut records = Vec::new();
+    let mut rec = FastqRecord::default();
+    while let Some(result) = reader.read(&mut rec) {
+        result?;
+        records.push(rec.clone());
+    }
+    Ok(records)
+}
+
+fn read_error(text: &str) -> String {
+    let err = read_all(text.as_bytes().to_vec()).expect_err("input should be rejected");
+    format!("{err:#}")
+}
+
+const FASTQ: &str = "@r1 1:N:0:ACGT sample=a\nACGTN\n+\nIIII#\n\
+                     @r2\tBC:Z:TTGA\nGGC\n+r2\n!!I\n\
+                     @r3\nAAAAAAAA\n+\nIIIIIIII\n";
+
+#[test]
+fn reads_names_comments_and_lines() {
+    let records = read_all(FASTQ.as_bytes().to_vec()).unwrap();
+    let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).unwrap();
+    let fields: Vec<[String; 4]> = records
+        .iter()
+        .map(|r| [text(&r.name), text(&r.comment), text(&r.seq), text(&r.qual)])
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ["r1", "1:N:0:ACGT sample=a", "ACGTN", "IIII#"],
+            ["r2", "BC:Z:TTGA", "GGC", "!!I"],
+            ["r3", "", "AAAAAAAA", "IIIIIIII"],
+        ]
+    );
+
+    let mut out = Vec::new();
+    records[0].write_to(&mut out);
+    assert_eq!(out, b"@r1 1:N:0:ACGT sample=a\nACGTN\n+\nIIII#\n");
+}
+
+#[test]
+fn tolerates_crlf_and_blank_lines() {
+    let records = read_all(b"@r1\r\nACG\r\n+\r\nIII\r\n\n\n@r2\nT\n+\nI\n\n".to_vec()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        (&records[0].seq[..], &records[0].qual[..]),
+        (&b"ACG"[..], &b"III"[..])
+    );
+}
+
+#[test]
+fn reads_gzip_input() {
+    // Two gzip members, as written by concatenating .gz files.
+    let mut data = Vec::new();
+    for chunk in [&FASTQ[..FASTQ.find("@r3").unwrap()], "@r3\nA\n+\nI\n"] {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(chunk.as_bytes()).unwrap();
+        data.extend(gz.finish().unwrap());
+    }
+    let names: Vec<Vec<u8>> = read_all(data)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.name)
+        .collect();
+    assert_eq!(names, [b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()]);
+}
+
+#[test]
+fn rejects_malformed_records() {
+    assert!(read_error("@r1\nACGT\n+\n").contains("record 1 is truncated"));
+    assert!(read_error("@r1\nACGT\n").contains("record 1 has no '+' separator line"));
+    assert!(
+        read_error("@r1\nACGT\n+\nIIII\n@r2\nACGT\nIIII\n@r3\n")
+            .contains("record 2 has no '+' separator line")
+    );
+    assert!(
+        read_error("@r1\nACGT\n+\nIII\n")
+            .contains("record 1 has sequence and quality of different lengths")
+    );
+    assert!(read_error("r1\nACGT\n+\nIIII\n").contains("record 1 does not start with '@'"));
+}
+
+#[test]
+fn filters_on_read_fields() {
+    let records = read_all(FASTQ.as_bytes().to_vec()).unwrap();
+    let passes = |expr: &str| -> Vec<bool> {
+        let mut engine = JsFastqFilterEngine::new(expr).unwrap();
+        records
+            .iter()
+            .map(|r| engine.record_passes(r).unwrap())
+            .collect()
+    };
+    assert_eq!(passes("read.name === 'r2'"), [false, true, false]);
+    assert_eq!(
+        passes("read.comment.startsWith('1:N')"),
+        [true, false, false]
+    );
+    assert_eq!(passes("!read.seq.includes('N')"), [false, true, true]);
+    assert_eq!(passes("read.length >= 5"), [true, false, true]);
+    assert_eq!(passes("!read.qual.includes('!')"), [true, false, true]);
+
+    let mut engine =
+        JsFastqFilterEngine::new("[read.name, read.length, read.qual.charCodeAt(0) - 33]").unwrap();
+    let mut line = String::new();
+    assert!(engine.record_emit(&records[1], &mut line).unwrap());
+    assert_eq!(line, "r2\t3\t0");
+}
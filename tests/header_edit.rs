@@ -0,0 +1,42 @@
+//! Record remapping after `header(hdr)` edits.
+
+use rust_htslib::bam;
+use rust_htslib::bam::header::HeaderRecord;
+use v8bam::JsBamFilterEngine;
+use v8bam::check::synthetic_records;
+
+fn two_reference_header() -> bam::HeaderView {
+    let mut header = bam::Header::new();
+    for name in ["chr1", "chr2"] {
+        let mut sq = HeaderRecord::new(b"SQ");
+        sq.push_tag(b"SN", name);
+        sq.push_tag(b"LN", 1_000_000);
+        header.push_record(&sq);
+    }
+    bam::HeaderView::from_header(&header)
+}
+
+#[test]
+fn mate_on_removed_reference_becomes_unmapped() {
+    let header = two_reference_header();
+    let mut engine = JsBamFilterEngine::new("true").unwrap();
+    let (_, remap) = engine
+        .header_edits("({ removeReferences: ['chr1'] })", &header)
+        .unwrap()
+        .apply(&header)
+        .unwrap();
+
+    // Read on chr2 whose mate is on chr1.
+    let (_, mut rec) = synthetic_records().swap_remove(0);
+    rec.set_tid(1);
+    rec.set_mtid(0);
+    rec.push_aux(b"MC", bam::record::Aux::String("20M"))
+        .unwrap();
+    assert!(remap.apply(&mut rec));
+
+    assert_eq!((rec.tid(), rec.mtid(), rec.mpos()), (0, -1, -1));
+    assert_eq!(rec.insert_size(), 0);
+    assert!(rec.is_mate_unmapped());
+    assert!(!rec.is_proper_pair());
+    assert!(rec.aux(b"MC").is_err());
+}
@@ -0,0 +1,122 @@
+//! Header reconciliation for several SAM inputs.
+
+use std::path::PathBuf;
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::Aux;
+use v8bam::inputs::{BamInputs, Combine};
+
+fn write_sam(name: &str, text: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("v8bam-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+/// Header of `test`'s two inputs opened together.
+fn merged_header(test: &str, first: &str, second: &str) -> String {
+    let paths = [
+        write_sam(&format!("{test}-a.sam"), first),
+        write_sam(&format!("{test}-b.sam"), second),
+    ];
+    let inputs = BamInputs::open(&paths, Combine::Concat, None).unwrap();
+    String::from_utf8_lossy(inputs.header().as_bytes()).into_owned()
+}
+
+#[test]
+fn sq_lines_keep_their_tags() {
+    let header = merged_header(
+        "sq",
+        "@SQ\tSN:chr1\tLN:1000\tM5:0123456789abcdef0123456789abcdef\tUR:ref.fa\n",
+        "@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\tAS:test\n",
+    );
+    assert!(
+        header.contains("@SQ\tSN:chr1\tLN:1000\tM5:0123456789abcdef0123456789abcdef\tUR:ref.fa\n"),
+        "{header}"
+    );
+    assert!(
+        header.contains("@SQ\tSN:chr2\tLN:500\tAS:test\n"),
+        "{header}"
+    );
+}
+
+#[test]
+fn clashing_program_ids_are_renamed() {
+    let header = merged_header(
+        "pg",
+        "@SQ\tSN:chr1\tLN:1000\n@PG\tID:bwa\tPN:bwa\tVN:0.7.17\n",
+        "@SQ\tSN:chr1\tLN:1000\n@PG\tID:bwa\tPN:bwa\tVN:0.7.18\n\
+         @PG\tID:samtools\tPN:samtools\tPP:bwa\n",
+    );
+    assert!(
+        header.contains("@PG\tID:bwa\tPN:bwa\tVN:0.7.17\n"),
+        "{header}"
+    );
+    assert!(
+        header.contains("@PG\tID:bwa.1\tPN:bwa\tVN:0.7.18\n"),
+        "{header}"
+    );
+    assert!(
+        header.contains("@PG\tID:samtools\tPN:samtools\tPP:bwa.1\n"),
+        "{header}"
+    );
+}
+
+#[test]
+fn clashing_read_group_ids_are_renamed() {
+    let header = merged_header(
+        "rg",
+        "@SQ\tSN:chr1\tLN:1000\n@RG\tID:lane1\tSM:a\n",
+        "@SQ\tSN:chr1\tLN:1000\n@RG\tID:lane1\tSM:b\n@RG\tID:lane2\tSM:b\n",
+    );
+    assert!(header.contains("@RG\tID:lane1\tSM:a\n"), "{header}");
+    assert!(header.contains("@RG\tID:lane1.1\tSM:b\n"), "{header}");
+    assert!(header.contains("@RG\tID:lane2\tSM:b\n"), "{header}");
+}
+
+#[test]
+fn record_tags_follow_renamed_ids() {
+    let paths = [
+        write_sam(
+            "tags-a.sam",
+            "@SQ\tSN:chr1\tLN:1000\n@RG\tID:lane1\tSM:a\n@PG\tID:bwa\tPN:bwa\tVN:1\n\
+             a1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\tIIII\tRG:Z:lane1\tPG:Z:bwa\n",
+        ),
+        write_sam(
+            "tags-b.sam",
+            "@SQ\tSN:chr1\tLN:1000\n@RG\tID:lane1\tSM:b\n@RG\tID:lane2\tSM:b\n\
+             @PG\tID:bwa\tPN:bwa\tVN:2\n\
+             b1\t0\tchr1\t5\t60\t4M\t*\t0\t0\tACGT\tIIII\tRG:Z:lane1\tPG:Z:bwa\tNM:i:0\n\
+             b2\t0\tchr1\t9\t60\t4M\t*\t0\t0\tACGT\tIIII\tRG:Z:lane2\tPG:Z:bwa\n",
+        ),
+    ];
+    let mut inputs = BamInputs::open(&paths, Combine::Concat, None).unwrap();
+    let mut rec = bam::Record::new();
+    let mut tags = Vec::new();
+    while let Some(result) = inputs.read(&mut rec) {
+        result.unwrap();
+        let tag = |name: &[u8]| match rec.aux(name) {
+            Ok(Aux::String(s)) => s.to_string(),
+            other => panic!("{other:?}"),
+        };
+        tags.push((
+            String::from_utf8_lossy(rec.qname()).into_owned(),
+            tag(b"RG"),
+            tag(b"PG"),
+        ));
+        if rec.qname() == b"b1" {
+            assert!(matches!(rec.aux(b"NM"), Ok(Aux::U8(0) | Aux::I32(0))));
+        }
+    }
+    let expected = [
+        ("a1", "lane1", "bwa"),
+        ("b1", "lane1.1", "bwa.1"),
+        ("b2", "lane2", "bwa.1"),
+    ];
+    let expected: Vec<(String, String, String)> = expected
+        .iter()
+        .map(|&(q, rg, pg)| (q.to_string(), rg.to_string(), pg.to_string()))
+        .collect();
+    assert_eq!(tags, expected);
+}
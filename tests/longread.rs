@@ -0,0 +1,137 @@
+//! `aln.supplementaryAlignments()` (SA) and `aln.modifications()` (MM/ML).
+
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, AuxArray, Cigar, CigarString};
+use v8bam::JsBamFilterEngine;
+use v8bam::check::synthetic_header;
+
+/// A forward or reverse read matching chr1 from 100.
+fn read(seq: &[u8], flags: u16) -> bam::Record {
+    let mut rec = bam::Record::new();
+    let cigar = CigarString(vec![Cigar::Match(seq.len() as u32)]);
+    rec.set(b"read", Some(&cigar), seq, &vec![30; seq.len()]);
+    rec.set_tid(0);
+    rec.set_pos(100);
+    rec.set_flags(flags);
+    rec
+}
+
+fn with_sa(sa: &str) -> bam::Record {
+    let mut rec = read(b"ACGTACGT", 0);
+    rec.push_aux(b"SA", Aux::String(sa)).unwrap();
+    rec
+}
+
+fn with_mods(seq: &[u8], flags: u16, mm: &str, ml: Option<&[u8]>) -> bam::Record {
+    let mut rec = read(seq, flags);
+    rec.push_aux(b"MM", Aux::String(mm)).unwrap();
+    if let Some(ml) = ml {
+        rec.push_aux(b"ML", Aux::ArrayU8(AuxArray::from(ml)))
+            .unwrap();
+    }
+    rec
+}
+
+fn emit(expr: &str, rec: &bam::Record) -> anyhow::Result<String> {
+    let mut engine = JsBamFilterEngine::new(&format!("JSON.stringify({expr})")).unwrap();
+    let mut line = String::new();
+    engine.record_emit(rec, &synthetic_header(), &mut line)?;
+    Ok(line)
+}
+
+fn error(expr: &str, rec: &bam::Record) -> String {
+    let err = emit(expr, rec).expect_err("the call should throw");
+    format!("{err:#}")
+}
+
+#[test]
+fn sa_entries_are_parsed() {
+    let rec = with_sa("chr2,5001,-,30S70M,60,3;chr1,100,+,50M50H,20,0;");
+    assert_eq!(
+        emit("aln.supplementaryAlignments()", &rec).unwrap(),
+        r#"[{"chrom":"chr2","pos":5000,"strand":"-","cigar":"30S70M","mapq":60,"nm":3},{"chrom":"chr1","pos":99,"strand":"+","cigar":"50M50H","mapq":20,"nm":0}]"#
+    );
+    // Without the trailing ';'.
+    let rec = with_sa("chr2,5001,-,30S70M,60,3");
+    assert_eq!(
+        emit("aln.supplementaryAlignments().map((e) => e.pos)", &rec).unwrap(),
+        "[5000]"
+    );
+    assert_eq!(
+        emit("aln.supplementaryAlignments()", &read(b"ACGT", 0)).unwrap(),
+        "[]"
+    );
+}
+
+#[test]
+fn malformed_sa_entries_throw() {
+    for (sa, entry) in [
+        ("chr2,5001,*,30S70M,60,3;", "chr2,5001,*,30S70M,60,3"),
+        (
+            "chr1,100,+,50M,20,0;chr2,5001,-,30S70M,60;",
+            "chr2,5001,-,30S70M,60",
+        ),
+        ("chr2,0,-,30S70M,60,3;", "chr2,0,-,30S70M,60,3"),
+        ("chr2,x,-,30S70M,60,3;", "chr2,x,-,30S70M,60,3"),
+        (
+            "chr2,5001,-,30S70M,60,3,extra;",
+            "chr2,5001,-,30S70M,60,3,extra",
+        ),
+    ] {
+        let err = error("aln.supplementaryAlignments()", &with_sa(sa));
+        assert!(
+            err.contains(&format!("read: malformed SA entry '{entry}'")),
+            "{sa}: {err}"
+        );
+    }
+}
+
+const MODS: &str =
+    "aln.modifications().map((m) => [m.queryPos, m.refPos, m.base, m.code, m.strand, m.prob])";
+
+#[test]
+fn modifications_of_several_types() {
+    // C positions 1, 2, 5 and 7; m on the 1st and 3rd C, h on the 2nd.
+    let rec = with_mods(b"ACCGTCAC", 0, "C+m,0,1;C+h,1;", Some(&[200, 50, 10]));
+    assert_eq!(
+        emit(MODS, &rec).unwrap(),
+        r#"[[1,101,"C","m","+",0.783203125],[2,102,"C","h","+",0.041015625],[5,105,"C","m","+",0.197265625]]"#
+    );
+}
+
+#[test]
+fn modifications_on_reverse_reads_index_the_stored_seq() {
+    // The reverse complement of the read above: MM counts along the
+    // original read, so its 1st and 3rd C are stored at 6 and 2.
+    let rec = with_mods(b"GTGACGGT", 0x10, "C+m,0,1;", Some(&[200, 50]));
+    assert_eq!(
+        emit(MODS, &rec).unwrap(),
+        r#"[[2,102,"C","m","+",0.197265625],[6,106,"C","m","+",0.783203125]]"#
+    );
+}
+
+#[test]
+fn modifications_without_ml_have_null_prob() {
+    let rec = with_mods(b"ACCGTCAC", 0, "C+m,0,1;", None);
+    assert_eq!(
+        emit("aln.modifications().map((m) => [m.queryPos, m.prob])", &rec).unwrap(),
+        "[[1,null],[5,null]]"
+    );
+    assert_eq!(
+        emit("aln.modifications()", &read(b"ACGT", 0)).unwrap(),
+        "[]"
+    );
+}
+
+#[test]
+fn undecodable_mm_ml_throws() {
+    // Fewer ML values than MM calls.
+    let rec = with_mods(b"ACCGTCAC", 0, "C+m,0,1;", Some(&[200]));
+    let err = error("aln.modifications()", &rec);
+    assert!(err.contains("read: invalid MM/ML tags"), "{err}");
+
+    // More Cs skipped than the read has.
+    let rec = with_mods(b"ACCGTCAC", 0, "C+m,5;", None);
+    let err = error("aln.modifications()", &rec);
+    assert!(err.contains("read: invalid MM/ML tags"), "{err}");
+}
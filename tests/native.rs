@@ -0,0 +1,56 @@
+//! The native evaluator accepts only expressions it can evaluate exactly as
+//! V8 would, and agrees with the JS engine on those.
+
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::native::NativeFilter;
+
+#[test]
+fn native_matches_js() {
+    let exprs = [
+        "aln.mapq >= 20 && !aln.duplicate",
+        "aln.mapq > 10 and aln.chrom == 'chr1'",
+        "return aln.reverse || aln.unmapped;",
+        "hasFlag(aln.flag, 0x4 | 0x100) || aln.pos === -1",
+        "(aln.flag & 0x40) !== 0 && aln.end <= 1020",
+        "aln.qname !== \"synthetic_fwd\" && aln.properPair == true",
+        "!aln.chrom",
+        "aln.mapq",
+        "aln.read2 || aln.end > 4000000000",
+    ];
+    let header = synthetic_header();
+    for expr in exprs {
+        let native = NativeFilter::parse(expr).unwrap_or_else(|| panic!("{expr} not parsed"));
+        let mut engine = JsBamFilterEngine::new(expr).unwrap();
+        for (desc, rec) in synthetic_records() {
+            assert_eq!(
+                native.record_passes(&rec, &header),
+                engine.record_passes(&rec, &header).unwrap(),
+                "{expr} on {desc}"
+            );
+        }
+    }
+}
+
+#[test]
+fn other_expressions_are_left_to_js() {
+    for expr in [
+        "aln.qname.startsWith('synthetic')",
+        "aln.aux('NM') > 0",
+        "aln.isDiscordant()",
+        "aln.readGroup === 'rg1'",
+        // JS would convert between types.
+        "aln.mapq == '60'",
+        "aln.chrom < 'chr2'",
+        "(aln.mapq && aln.pos) > 5",
+        "aln.flag & 0x4 == 0",
+        "const q = aln.mapq; return q > 5",
+        "return\naln.mapq > 5",
+        "aln.qname == 'return'",
+        "aln.mapq = 5",
+        "--aln.mapq",
+        "010 < aln.mapq",
+    ] {
+        assert!(NativeFilter::parse(expr).is_none(), "{expr}");
+    }
+}
@@ -0,0 +1,32 @@
+//! `aln.sample` follows the header given to `set_header`.
+
+use rust_htslib::bam;
+use rust_htslib::bam::header::HeaderRecord;
+use v8bam::JsBamFilterEngine;
+use v8bam::check::synthetic_records;
+
+fn header_with_sample(sample: &str) -> bam::HeaderView {
+    let mut header = bam::Header::new();
+    let mut sq = HeaderRecord::new(b"SQ");
+    sq.push_tag(b"SN", "chr1");
+    sq.push_tag(b"LN", 248_956_422);
+    header.push_record(&sq);
+    let mut rg = HeaderRecord::new(b"RG");
+    rg.push_tag(b"ID", "rg1");
+    rg.push_tag(b"SM", sample);
+    header.push_record(&rg);
+    bam::HeaderView::from_header(&header)
+}
+
+#[test]
+fn sample_follows_new_header() {
+    let mut engine = JsBamFilterEngine::new("aln.sample").unwrap();
+    let (_, rec) = synthetic_records().swap_remove(0);
+    for sample in ["first", "second"] {
+        let header = header_with_sample(sample);
+        engine.set_header(&header);
+        let mut line = String::new();
+        assert!(engine.record_emit(&rec, &header, &mut line).unwrap());
+        assert_eq!(line, sample);
+    }
+}
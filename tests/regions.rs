@@ -0,0 +1,51 @@
+//! Loading BED files for `--bed`.
+
+use std::path::PathBuf;
+
+use v8bam::regions::RegionSet;
+
+fn write_bed(name: &str, text: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("v8bam-regions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn tab_and_space_separated_lines_load() {
+    let path = write_bed(
+        "mixed.bed",
+        "track name=test\nchr1\t100\t200\tpeak1\nchr2 300 400\n",
+    );
+    let set = RegionSet::from_bed(&path).unwrap();
+    assert!(set.contains("chr1", 150));
+    assert!(set.overlaps("chr2", 350, 360));
+    assert!(!set.contains("chr2", 400));
+}
+
+#[test]
+fn bad_lines_report_file_and_line() {
+    for (name, text, expected) in [
+        (
+            "reversed.bed",
+            "chr1\t1\t2\nchr1\t200\t100\n",
+            ":2: end 100 is before start 200",
+        ),
+        (
+            "text.bed",
+            "chr1\tstart\tend\n",
+            ":1: invalid coordinate \"start\"",
+        ),
+        (
+            "short.bed",
+            "chr1\t100\n",
+            ":1: expected at least 3 columns",
+        ),
+    ] {
+        let path = write_bed(name, text);
+        let err = RegionSet::from_bed(&path).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains(name) && msg.contains(expected), "{msg}");
+    }
+}
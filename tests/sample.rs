@@ -0,0 +1,79 @@
+//! `--sample`: deterministic subsampling by qname.
+
+use rust_htslib::bam;
+use v8bam::sample::QnameSampler;
+
+fn names(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|i| format!("run1:lane2:{i}:{}", i * 7919))
+        .collect()
+}
+
+fn kept(sampler: &QnameSampler, names: &[String]) -> Vec<bool> {
+    names.iter().map(|n| sampler.keep(n.as_bytes())).collect()
+}
+
+#[test]
+fn same_seed_selects_same_reads() {
+    let names = names(1000);
+    let a = kept(&QnameSampler::new(0.3, 42), &names);
+    let b = kept(&QnameSampler::new(0.3, 42), &names);
+    assert_eq!(a, b);
+
+    let other_seed = kept(&QnameSampler::new(0.3, 43), &names);
+    assert_ne!(a, other_seed);
+}
+
+#[test]
+fn mates_share_the_decision() {
+    let sampler = QnameSampler::new(0.5, 7);
+    for name in names(1000) {
+        let mut first = bam::Record::new();
+        first.set(name.as_bytes(), None, b"ACGT", &[30; 4]);
+        first.set_flags(0x1 | 0x40);
+        let mut second = bam::Record::new();
+        second.set(name.as_bytes(), None, b"TTGCA", &[20; 5]);
+        second.set_flags(0x1 | 0x10 | 0x80);
+        assert_eq!(
+            sampler.keep(first.qname()),
+            sampler.keep(second.qname()),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn kept_fraction_is_close_to_requested() {
+    let names = names(100_000);
+    for fraction in [0.01, 0.1, 0.5, 0.9] {
+        let n = kept(&QnameSampler::new(fraction, 1), &names)
+            .into_iter()
+            .filter(|&k| k)
+            .count();
+        let observed = n as f64 / names.len() as f64;
+        assert!(
+            (observed - fraction).abs() < 0.01,
+            "fraction {fraction}: kept {observed}"
+        );
+    }
+}
+
+#[test]
+fn fractions_outside_unit_interval_are_clamped() {
+    let names = names(100);
+    assert!(
+        kept(&QnameSampler::new(1.5, 0), &names)
+            .into_iter()
+            .all(|k| k)
+    );
+    assert!(
+        kept(&QnameSampler::new(0.0, 0), &names)
+            .into_iter()
+            .all(|k| !k)
+    );
+    assert!(
+        kept(&QnameSampler::new(-1.0, 0), &names)
+            .into_iter()
+            .all(|k| !k)
+    );
+}
@@ -0,0 +1,101 @@
+//! Engines built from a startup snapshot or a code cache must behave like
+//! engines built from scratch.
+
+use std::path::PathBuf;
+
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::snapshot::{create_startup_snapshot, load_or_create_snapshot, snapshot_path};
+use v8bam::{CodeCache, EngineOptions, JsBamFilterEngine};
+
+const EXPR: &str = "aln.mapq >= 0 && aln.cigar.length > 0 && hasFlag(aln.flag, 0x40) && \
+                    aln.aux('NM') === 1 && aln.clone().end === aln.end";
+
+fn cache_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("v8bam-snapshot-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn results(opts: EngineOptions) -> Vec<bool> {
+    let mut engine = JsBamFilterEngine::with_options(EXPR, opts).unwrap();
+    let header = synthetic_header();
+    synthetic_records()
+        .iter()
+        .map(|(_, rec)| engine.record_passes(rec, &header).unwrap())
+        .collect()
+}
+
+fn code_files(dir: &PathBuf) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "code"))
+        .map(|path| {
+            let data = std::fs::read(&path).unwrap();
+            (path, data)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn snapshot_engine_matches_fresh_engine() {
+    let expected = results(EngineOptions::default());
+    assert_eq!(expected, [true, false, false]);
+
+    let opts = EngineOptions {
+        snapshot: Some(create_startup_snapshot().unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(results(opts), expected);
+}
+
+#[test]
+fn snapshot_is_written_once_and_reused() {
+    let dir = cache_dir("reuse");
+    let path = snapshot_path(&dir);
+    let created = load_or_create_snapshot(&path).unwrap();
+    assert!(path.exists());
+    assert_eq!(load_or_create_snapshot(&path).unwrap(), created);
+
+    let opts = EngineOptions {
+        snapshot: Some(created),
+        ..Default::default()
+    };
+    assert_eq!(results(opts), results(EngineOptions::default()));
+}
+
+#[test]
+fn code_cache_is_reused() {
+    let dir = cache_dir("code");
+    let expected = results(EngineOptions::default());
+    let opts = || EngineOptions {
+        code_cache: Some(CodeCache::new(&dir)),
+        ..Default::default()
+    };
+
+    assert_eq!(results(opts()), expected);
+    let stored = code_files(&dir);
+    assert_eq!(stored.len(), 1);
+
+    // A hit consumes the entry without rewriting it.
+    assert_eq!(results(opts()), expected);
+    assert_eq!(code_files(&dir), stored);
+}
+
+#[test]
+fn rejected_code_cache_is_regenerated() {
+    let dir = cache_dir("rejected");
+    let opts = || EngineOptions {
+        code_cache: Some(CodeCache::new(&dir)),
+        ..Default::default()
+    };
+    let expected = results(opts());
+    let (path, _) = code_files(&dir).remove(0);
+    std::fs::write(&path, b"not a code cache").unwrap();
+
+    assert_eq!(results(opts()), expected);
+    let (_, data) = code_files(&dir).remove(0);
+    assert_ne!(data, b"not a code cache");
+}
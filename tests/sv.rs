@@ -0,0 +1,57 @@
+//! `aln.isDiscordant()` and `--preset discordant` on pairs built from the
+//! synthetic records.
+
+use rust_htslib::bam;
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::sv::DISCORDANT_PRESET;
+
+/// A proper pair's read with the given flags, at chr1:1000 with its mate at
+/// `mpos`.
+fn pair_read(flags: u16, mpos: i64) -> bam::Record {
+    let (_, mut rec) = synthetic_records().swap_remove(0);
+    rec.set_flags(0x1 | 0x2 | flags);
+    rec.set_pos(1000);
+    rec.set_mpos(mpos);
+    rec
+}
+
+fn passes(expr: &str, rec: &bam::Record) -> bool {
+    let mut engine = JsBamFilterEngine::new(expr).unwrap();
+    engine.record_passes(rec, &synthetic_header()).unwrap()
+}
+
+#[test]
+fn fr_pairs_are_concordant() {
+    // Forward read1 left of a reverse mate.
+    assert!(!passes("aln.isDiscordant()", &pair_read(0x40 | 0x20, 1200)));
+    // Reverse read1 right of a forward mate.
+    assert!(!passes("aln.isDiscordant()", &pair_read(0x40 | 0x10, 800)));
+}
+
+#[test]
+fn same_start_pairs_on_opposite_strands_are_concordant() {
+    for flags in [0x40 | 0x10, 0x40 | 0x20, 0x80 | 0x10, 0x80 | 0x20] {
+        assert!(
+            !passes("aln.isDiscordant()", &pair_read(flags, 1000)),
+            "flags {flags:#x}"
+        );
+    }
+}
+
+#[test]
+fn rf_and_same_strand_pairs_are_discordant() {
+    // Reverse read left of a forward mate.
+    assert!(passes("aln.isDiscordant()", &pair_read(0x40 | 0x10, 1200)));
+    // Both forward.
+    assert!(passes("aln.isDiscordant()", &pair_read(0x40, 1200)));
+}
+
+#[test]
+fn preset_skips_supplementary_records() {
+    let discordant = pair_read(0x40, 1200);
+    assert!(passes(DISCORDANT_PRESET, &discordant));
+    let mut supplementary = discordant.clone();
+    supplementary.set_flags(supplementary.flags() | 0x800);
+    assert!(!passes(DISCORDANT_PRESET, &supplementary));
+}
@@ -0,0 +1,57 @@
+//! `v8bam.d.ts` against the real `aln` object, and property probes by
+//! built-ins under `--strict`.
+
+use std::collections::BTreeSet;
+
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::{EngineOptions, JsBamFilterEngine, TYPE_DECLARATIONS, UnknownPropertyMode};
+
+/// Property names of `interface Alignment` in the declarations.
+fn declared_alignment_members() -> BTreeSet<String> {
+    let body = TYPE_DECLARATIONS
+        .split_once("interface Alignment {")
+        .and_then(|(_, rest)| rest.split_once("\n}"))
+        .expect("interface Alignment in v8bam.d.ts")
+        .0;
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('/') && !line.starts_with('*'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("readonly ").unwrap_or(line);
+            let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+            matches!(line[end..].chars().next(), Some(':' | '(' | '?'))
+                .then(|| line[..end].to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn declarations_match_aln_template() {
+    let mut engine =
+        JsBamFilterEngine::new("Object.getOwnPropertyNames(aln).sort().join(',')").unwrap();
+    let header = synthetic_header();
+    let (_, rec) = synthetic_records().swap_remove(0);
+    let mut line = String::new();
+    assert!(engine.record_emit(&rec, &header, &mut line).unwrap());
+    let actual: BTreeSet<String> = line.split(',').map(String::from).collect();
+    assert_eq!(declared_alignment_members(), actual);
+}
+
+#[test]
+fn builtin_probes_are_not_unknown_properties() {
+    let opts = EngineOptions {
+        unknown_properties: UnknownPropertyMode::Error,
+        ..EngineOptions::default()
+    };
+    let mut engine = JsBamFilterEngine::with_options(
+        "typeof JSON.stringify(aln) === 'string' && aln.then === undefined \
+         && Promise.resolve(aln) instanceof Promise",
+        opts,
+    )
+    .unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+    assert!(engine.unknown_properties().is_empty());
+}
@@ -0,0 +1,132 @@
+//! `variant` accessors, `info()`/`format()` decoding and GT strings.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use rust_htslib::bcf::{self, Read};
+use v8bam::vcf::JsVcfFilterEngine;
+
+const VCF: &str = "##fileformat=VCFv4.2
+##contig=<ID=chr1,length=100000>
+##FILTER=<ID=PASS,Description=\"All filters passed\">
+##FILTER=<ID=lowq,Description=\"Low quality\">
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Allele count\">
+##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele frequency\">
+##INFO=<ID=MQ,Number=1,Type=Float,Description=\"Mapping quality\">
+##INFO=<ID=DB,Number=0,Type=Flag,Description=\"dbSNP\">
+##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"SV type\">
+##INFO=<ID=ANN,Number=.,Type=String,Description=\"Annotations\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allele depths\">
+##FORMAT=<ID=GQ,Number=1,Type=Float,Description=\"Genotype quality\">
+##FORMAT=<ID=FT,Number=1,Type=String,Description=\"Sample filter\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\ts3
+chr1\t100\trs1\tA\tC,G\t50\tPASS\tDP=10;AC=1,.;AF=0.25,.;MQ=59.5;DB;SVTYPE=SNV;ANN=x,y\tGT:DP:AD:GQ:FT\t0|1:12:5,7,0:30.5:PASS\t1/2:.:3,.:.:.\t1:4:4:.:lowq
+chr1\t200\t.\tTTG\tT\t.\t.\tDP=.;SVTYPE=.\tGT:DP\t./.:.\t.|1:5\t.:.
+";
+
+/// [`VCF`] written once per test run.
+fn vcf_path() -> &'static Path {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+    PATH.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("v8bam-vcf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calls.vcf");
+        std::fs::write(&path, VCF).unwrap();
+        path
+    })
+}
+
+/// `JSON.stringify(expr)` for each record.
+fn json(expr: &str) -> Vec<String> {
+    let mut engine = JsVcfFilterEngine::new(&format!("JSON.stringify({expr})")).unwrap();
+    let mut reader = bcf::Reader::from_path(vcf_path()).unwrap();
+    reader
+        .records()
+        .map(|rec| {
+            let rec = rec.unwrap();
+            let mut line = String::new();
+            assert!(engine.record_emit(&rec, &mut line).unwrap());
+            line
+        })
+        .collect()
+}
+
+#[test]
+fn site_fields() {
+    assert_eq!(
+        json(
+            "[variant.chrom, variant.pos, variant.end, variant.id, variant.ref, \
+              variant.alts, variant.qual, variant.filters]"
+        ),
+        [
+            r#"["chr1",99,100,"rs1","A",["C","G"],50,["PASS"]]"#,
+            r#"["chr1",199,202,".","TTG",["T"],null,[]]"#,
+        ]
+    );
+    assert_eq!(json("variant.samples")[0], r#"["s1","s2","s3"]"#);
+}
+
+#[test]
+fn info_values_by_type() {
+    assert_eq!(
+        json("['DP', 'AC', 'AF', 'MQ', 'DB', 'SVTYPE', 'ANN'].map((k) => variant.info(k))"),
+        [
+            r#"[10,[1,null],[0.25,null],59.5,true,"SNV",["x","y"]]"#,
+            r#"[null,null,null,null,false,null,null]"#,
+        ]
+    );
+}
+
+#[test]
+fn undeclared_keys_are_null() {
+    assert_eq!(
+        json("[variant.info('XX'), variant.format('XX', 0)]"),
+        ["[null,null]", "[null,null]"]
+    );
+}
+
+#[test]
+fn format_values_by_type() {
+    assert_eq!(
+        json("[0, 1, 2].map((s) => ['DP', 'AD', 'GQ', 'FT'].map((k) => variant.format(k, s)))")[0],
+        r#"[[12,[5,7,0],30.5,"PASS"],[null,[3,null],null,null],[4,[4],null,"lowq"]]"#
+    );
+    // Fields missing from the record's FORMAT column are null.
+    assert_eq!(
+        json("[variant.format('DP', 1), variant.format('AD', 1), variant.format('FT', 1)]")[1],
+        "[5,null,null]"
+    );
+}
+
+#[test]
+fn format_sample_by_name_or_index() {
+    assert_eq!(
+        json("['s3', 2, 'nobody', 3, -1].map((s) => variant.format('DP', s))")[0],
+        "[4,4,null,null,null]"
+    );
+}
+
+#[test]
+fn genotypes() {
+    assert_eq!(
+        json("variant.samples.map((s) => variant.format('GT', s))"),
+        [r#"["0|1","1/2","1"]"#, r#"["./.",".|1","."]"#]
+    );
+}
+
+#[test]
+fn filters_on_variant_fields() {
+    let mut engine = JsVcfFilterEngine::new(
+        "variant.qual >= 30 && variant.filters.includes('PASS') && variant.info('DP') > 5",
+    )
+    .unwrap();
+    let mut reader = bcf::Reader::from_path(vcf_path()).unwrap();
+    let passes: Vec<bool> = reader
+        .records()
+        .map(|rec| engine.record_passes(&rec.unwrap()).unwrap())
+        .collect();
+    assert_eq!(passes, [true, false]);
+}
@@ -0,0 +1,69 @@
+//! `filter(aln, window)` over the synthetic records (chr1:1000, chr1:1200
+//! and an unmapped read).
+
+use v8bam::JsBamFilterEngine;
+use v8bam::check::{synthetic_header, synthetic_records};
+use v8bam::window::RecordWindow;
+
+/// Emit `expr` for each record with a window of `distance` bp.
+fn emit_in_window(expr: &str, distance: u64) -> Vec<String> {
+    let mut engine = JsBamFilterEngine::new(expr).unwrap();
+    let header = synthetic_header();
+    let mut input = synthetic_records().into_iter().map(|(_, rec)| rec);
+    let mut window = RecordWindow::new(distance);
+    let mut record = rust_htslib::bam::Record::new();
+    let mut lines = Vec::new();
+    while let Some(next) = window.advance(&mut record, |rec| {
+        *rec = input.next()?;
+        Some(Ok(0))
+    }) {
+        next.unwrap();
+        let mut line = String::new();
+        assert!(
+            engine
+                .record_emit_in(&record, &header, &window, &mut line)
+                .unwrap()
+        );
+        lines.push(line);
+    }
+    lines
+}
+
+#[test]
+fn window_holds_reads_within_distance() {
+    let expr = "[aln.qname, window.map((r) => r.qname).join(',')]";
+    assert_eq!(
+        emit_in_window(expr, 100),
+        [
+            "synthetic_fwd\tsynthetic_fwd",
+            "synthetic_rev_clipped\tsynthetic_rev_clipped",
+            "synthetic_unmapped\tsynthetic_unmapped",
+        ]
+    );
+    assert_eq!(
+        emit_in_window(expr, 200),
+        [
+            "synthetic_fwd\tsynthetic_fwd,synthetic_rev_clipped",
+            "synthetic_rev_clipped\tsynthetic_fwd,synthetic_rev_clipped",
+            "synthetic_unmapped\tsynthetic_unmapped",
+        ]
+    );
+}
+
+#[test]
+fn window_entries_are_copies() {
+    let lines = emit_in_window(
+        "globalThis.prev ??= window[0]; return String(prev.pos)",
+        200,
+    );
+    assert_eq!(lines, ["1000", "1000", "1000"]);
+}
+
+#[test]
+fn window_is_undefined_without_one() {
+    let mut engine = JsBamFilterEngine::new("window === undefined").unwrap();
+    let header = synthetic_header();
+    for (desc, rec) in synthetic_records() {
+        assert!(engine.record_passes(&rec, &header).unwrap(), "{desc}");
+    }
+}
@@ -0,0 +1,300 @@
+// Type declarations for v8bam filter scripts.
+//
+// Reference this file from your editor (e.g. `/// <reference path="v8bam.d.ts" />`)
+// to get completion and typo checking for the `aln` object and helpers.
+
+type CigarOpName =
+  | "Match"
+  | "Ins"
+  | "Del"
+  | "RefSkip"
+  | "SoftClip"
+  | "HardClip"
+  | "Pad"
+  | "Equal"
+  | "Diff";
+
+interface CigarOp {
+  length: number;
+  op: CigarOpName;
+  consumes_ref: boolean;
+  consumes_query: boolean;
+}
+
+type AuxValue =
+  | number
+  | string
+  | Int8Array
+  | Uint8Array
+  | Int16Array
+  | Uint16Array
+  | Int32Array
+  | Uint32Array
+  | Float32Array;
+
+/**
+ * The alignment record passed to `filter(aln)`. Each call gets a new
+ * object whose accessors throw a `TypeError` once the call returns, also
+ * when read during a later call; keep `aln.clone()` instead.
+ */
+interface Alignment {
+  /** Mapping quality. */
+  readonly mapq: number;
+  /** Read name. */
+  readonly qname: string;
+  /** SAM flag. */
+  readonly flag: number;
+  /** 0-based leftmost position. */
+  readonly pos: number;
+  /** Same as `pos`. */
+  readonly start: number;
+  /** 0-based exclusive end position on the reference. */
+  readonly end: number;
+  /** Reference name, or "*" for unmapped reads. */
+  readonly chrom: string;
+  /** Single bits of `flag`. */
+  readonly paired: boolean;
+  readonly properPair: boolean;
+  readonly unmapped: boolean;
+  readonly mateUnmapped: boolean;
+  readonly reverse: boolean;
+  readonly mateReverse: boolean;
+  readonly read1: boolean;
+  readonly read2: boolean;
+  readonly secondary: boolean;
+  readonly qcFail: boolean;
+  readonly duplicate: boolean;
+  readonly supplementary: boolean;
+  /** CIGAR operations. */
+  readonly cigar: CigarOp[];
+  /** Path of the input file this record came from, as given on the command line. */
+  readonly sourceFile: string | null;
+  /** 0-based index of that input file. */
+  readonly sourceIndex: number;
+  /** Value of the RG tag, or null if missing. */
+  readonly readGroup: string | null;
+  /** SM of the record's @RG header line, or null. */
+  readonly sample: string | null;
+  /** LB of the record's @RG header line, or null. */
+  readonly library: string | null;
+  /** PL of the record's @RG header line, or null. */
+  readonly platform: string | null;
+  /** 0-based start including leading soft and hard clips. */
+  readonly unclippedStart: number;
+  /** 0-based exclusive end including trailing soft and hard clips. */
+  readonly unclippedEnd: number;
+  /** 0-based unclipped position of the 5' end (unclippedEnd - 1 on the reverse strand). */
+  readonly fivePrime: number;
+  /** UMI from the configured source (default: UB, else RX tag), or null. */
+  readonly umi: string | null;
+  /** Cell barcode from the configured source (default: CB, else CR tag), or null. */
+  readonly cellBarcode: string | null;
+  /** Mapped and has an SA tag. */
+  readonly isSplit: boolean;
+  /** Which end of the alignment (in reference orientation) is soft/hard clipped, or null. */
+  readonly clipSide: "left" | "right" | "both" | null;
+  /** 0-based exclusive end of the mate (exact with an MC tag), or null if the mate is unmapped. */
+  readonly mateEndEstimate: number | null;
+  /**
+   * Sequence as ASCII codes. The array is a view over a buffer reused for
+   * every record: copy it (e.g. `.slice()`) to keep it past this call.
+   */
+  readonly seqBytes: Uint8Array;
+  /** Phred qualities (without +33), reused like `seqBytes`. */
+  readonly qualBytes: Uint8Array;
+  /** Value of aux tag `tag` (e.g. "NM"), or null if missing. B arrays are typed arrays. */
+  aux(tag: string): AuxValue | null;
+  /** True if the read's reference span overlaps [start, end) on `chrom`. */
+  overlaps(chrom: string, start: number, end: number): boolean;
+  /**
+   * True for a mapped pair whose mate is on another reference, in the wrong
+   * orientation (not forward/reverse) or more than `maxInsert` bases away;
+   * without `maxInsert`, a pair not flagged as proper.
+   */
+  isDiscordant(maxInsert?: number): boolean;
+  /** Entries of the SA tag; empty without one. Throws on a malformed entry. */
+  supplementaryAlignments(): SupplementaryAlignment[];
+  /** Base modification calls from the MM/ML tags; empty without MM. Throws if MM/ML can't be decoded. */
+  modifications(): Modification[];
+  /** A plain copy of the core fields and aux tags that stays valid after the call. */
+  clone(): ClonedAlignment;
+}
+
+/** Result of `aln.clone()`. */
+interface ClonedAlignment {
+  qname: string;
+  flag: number;
+  mapq: number;
+  chrom: string;
+  pos: number;
+  start: number;
+  end: number;
+  cigar: CigarOp[];
+  /** All aux tags by name. */
+  tags: Record<string, AuxValue>;
+  /** `tags[tag]`, or null if missing. */
+  aux(tag: string): AuxValue | null;
+}
+
+interface SupplementaryAlignment {
+  chrom: string;
+  /** 0-based position. */
+  pos: number;
+  strand: "+" | "-";
+  cigar: string;
+  mapq: number;
+  nm: number;
+}
+
+interface Modification {
+  /** Index into the stored SEQ. */
+  queryPos: number;
+  /** 0-based reference position, or null for inserted/clipped bases. */
+  refPos: number | null;
+  /** Canonical base, e.g. "C". */
+  base: string;
+  /** MM modification code, e.g. "m", "h", or a ChEBI number. */
+  code: string;
+  strand: "+" | "-";
+  /** Probability from ML, or null if ML is absent. */
+  prob: number | null;
+}
+
+/** The FASTQ record passed to `filter(read)` for FASTQ input. */
+interface FastqRead {
+  /** Read name (header up to the first whitespace, without "@"). */
+  readonly name: string;
+  /** Rest of the header line after the name; "" if absent. */
+  readonly comment: string;
+  readonly seq: string;
+  /** Phred+33 quality string. */
+  readonly qual: string;
+  /** Sequence length. */
+  readonly length: number;
+}
+
+type InfoValue = number | string | boolean | (number | string | null)[];
+
+/** The VCF/BCF record passed to `filter(variant)` for VCF input. */
+interface Variant {
+  /** Contig name. */
+  readonly chrom: string;
+  /** 0-based position (VCF POS - 1). */
+  readonly pos: number;
+  /** 0-based exclusive end of the reference allele. */
+  readonly end: number;
+  /** ID column; "." if missing. */
+  readonly id: string;
+  /** Reference allele. */
+  readonly ref: string;
+  /** Alternate alleles. */
+  readonly alts: string[];
+  /** QUAL, or null if missing. */
+  readonly qual: number | null;
+  /** Names of the FILTER entries (e.g. ["PASS"]). */
+  readonly filters: string[];
+  /** Sample names from the header. */
+  readonly samples: string[];
+  /**
+   * Value of INFO field `key`: a scalar for Number=1 fields, an array
+   * otherwise, a boolean for flags, and null if absent or undeclared.
+   */
+  info(key: string): InfoValue | null;
+  /**
+   * Value of FORMAT field `key` for a sample given by index or name; GT is
+   * returned as a string such as "0/1". Null if absent.
+   */
+  format(key: string, sample: number | string): InfoValue | null;
+}
+
+/** A BED file loaded with `--bed NAME=PATH`. Coordinates are 0-based. */
+interface RegionSet {
+  /** True if the alignment's reference span overlaps a region. */
+  overlaps(aln: Alignment): boolean;
+  /** True if [start, end) on `chrom` overlaps a region. */
+  overlaps(chrom: string, start: number, end?: number): boolean;
+  /** True if position `pos` on `chrom` lies in a region. */
+  contains(chrom: string, pos: number): boolean;
+}
+
+/** Region sets by the names given to `--bed`. */
+declare const regions: Record<string, RegionSet>;
+
+/** The input header passed to a `header(hdr)` hook (`--header-expr`). */
+interface HeaderInfo {
+  /** Tags of the @HD line, or null. */
+  hd: Record<string, string> | null;
+  references: { name: string; length: number }[];
+  /** Tags of each @RG line, e.g. { ID: "rg1", SM: "NA12878" }. */
+  readGroups: Record<string, string>[];
+  /** Tags of each @PG line. */
+  programs: Record<string, string>[];
+  /** Text of each @CO line. */
+  comments: string[];
+}
+
+/** Edits returned by a `header(hdr)` hook; every field is optional. */
+interface HeaderEdits {
+  /** Old reference name → new name. */
+  renameReferences?: Record<string, string>;
+  /** References to drop; reads on them are not written. */
+  removeReferences?: string[];
+  /** @RG lines to add; each needs an ID. */
+  addReadGroups?: Record<string, string>[];
+  /** IDs of @RG lines to drop; the RG tag is removed from their reads. */
+  removeReadGroups?: string[];
+  /** @CO lines to add. */
+  addComments?: string[];
+  /** true to drop all @CO lines, or the exact texts to drop. */
+  removeComments?: boolean | string[];
+}
+
+/** True if any bit of `mask` is set in `flag`. */
+declare function hasFlag(flag: number, mask: number): boolean;
+
+/**
+ * False the first time `key` is passed, true afterwards. Backed by a Rust
+ * hash set that lives for the whole run, so memory grows with the number of
+ * distinct keys; call `seen.clear()` once earlier keys can't recur (e.g. when
+ * `aln.chrom` changes in a sorted stream).
+ */
+declare const seen: {
+  (key: string | number): boolean;
+  /** Forget all keys passed so far. */
+  clear(): void;
+};
+
+/**
+ * Running coverage at 0-based `pos` over the reads read so far; null before
+ * the current read's start, on another reference, or for unsorted input.
+ */
+declare function depth(chrom: string, pos: number): number | null;
+
+/** Number of differing characters, or Infinity if the lengths differ. */
+declare function hamming(a: string, b: string): number;
+
+/** The `--barcodes` whitelist. */
+declare const barcodes: {
+  /** True if `bc` is in the whitelist; throws a TypeError without one. */
+  has(bc: string): boolean;
+  /** Number of whitelisted barcodes, or null without a whitelist. */
+  readonly size: number | null;
+};
+
+/** Script logging; written to stderr via v8bam's logger (see `--log-limit`). */
+declare const console: {
+  log(...args: unknown[]): void;
+  info(...args: unknown[]): void;
+  warn(...args: unknown[]): void;
+  error(...args: unknown[]): void;
+  debug(...args: unknown[]): void;
+};
+
+/** Same as `console.log`. */
+declare function print(...args: unknown[]): void;
+
+declare const aln: Alignment;
+/** Nearby reads with `--window BP`, `aln` included; undefined otherwise. */
+declare const window: ClonedAlignment[] | undefined;
+declare const read: FastqRead;
+declare const variant: Variant;